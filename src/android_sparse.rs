@@ -0,0 +1,479 @@
+//! Support for reading Android sparse images, as produced by `img2simg` and the
+//! Android build system.
+//!
+//! A sparse image is a header followed by a series of chunks, each of which
+//! describes a run of blocks in the expanded image: raw data, a repeated 4-byte
+//! fill value, or blocks whose contents don't matter. For more information, see
+//! `libsparse/sparse_format.h` in AOSP.
+
+use std::io::{self, Read};
+
+const SPARSE_HEADER_MAGIC: u32 = 0xed26ff3a;
+const FILE_HEADER_LEN: usize = 28;
+const CHUNK_HEADER_LEN: usize = 12;
+
+const CHUNK_TYPE_RAW: u16 = 0xcac1;
+const CHUNK_TYPE_FILL: u16 = 0xcac2;
+const CHUNK_TYPE_DONT_CARE: u16 = 0xcac3;
+const CHUNK_TYPE_CRC32: u16 = 0xcac4;
+
+/// The file header of a sparse image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SparseHeader {
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub file_header_len: u16,
+    pub chunk_header_len: u16,
+    pub block_size: u32,
+    pub total_blocks: u32,
+    pub total_chunks: u32,
+}
+
+impl SparseHeader {
+    /// Size of the image after expansion.
+    pub fn expanded_size(&self) -> u64 {
+        self.block_size as u64 * self.total_blocks as u64
+    }
+
+    /// Parses the header, returning None if the magic number doesn't match.
+    fn parse(buf: &[u8; FILE_HEADER_LEN]) -> io::Result<Option<Self>> {
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        if u32_at(0) != SPARSE_HEADER_MAGIC {
+            return Ok(None);
+        }
+
+        let header = Self {
+            major_version: u16_at(4),
+            minor_version: u16_at(6),
+            file_header_len: u16_at(8),
+            chunk_header_len: u16_at(10),
+            block_size: u32_at(12),
+            total_blocks: u32_at(16),
+            total_chunks: u32_at(20),
+        };
+
+        if header.major_version != 1 {
+            return Err(invalid_data(format!(
+                "unsupported sparse image version {}.{}",
+                header.major_version, header.minor_version
+            )));
+        }
+        if (header.file_header_len as usize) < FILE_HEADER_LEN
+            || (header.chunk_header_len as usize) < CHUNK_HEADER_LEN
+        {
+            return Err(invalid_data("sparse image headers are too small"));
+        }
+        if header.block_size == 0 || !header.block_size.is_multiple_of(4) {
+            return Err(invalid_data(format!(
+                "invalid sparse image block size {}",
+                header.block_size
+            )));
+        }
+
+        Ok(Some(header))
+    }
+}
+
+/// Reads the header from the start of a stream. Returns None if the stream is not a
+/// sparse image.
+pub fn read_header(mut r: impl Read) -> io::Result<Option<SparseHeader>> {
+    let mut buf = [0u8; FILE_HEADER_LEN];
+    let n = read_up_to(&mut r, &mut buf)?;
+    if n < FILE_HEADER_LEN {
+        return Ok(None);
+    }
+    SparseHeader::parse(&buf)
+}
+
+/// Expands an Android sparse image into the raw image it represents. Blocks that
+/// the image doesn't care about are expanded into zeroes.
+///
+/// If constructed with [SparseImageRead::passthrough], this passes data through
+/// unchanged instead, so that readers can have the same type either way.
+pub struct SparseImageRead<R: Read> {
+    r: R,
+    state: State,
+}
+
+enum State {
+    Passthrough,
+    /// We still need to read the file header
+    Start,
+    Sparse {
+        header: SparseHeader,
+        chunks_left: u32,
+        chunk: Chunk,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Chunk {
+    Raw {
+        left: u64,
+    },
+    Fill {
+        value: [u8; 4],
+        offset: u64,
+        left: u64,
+    },
+    DontCare {
+        left: u64,
+    },
+}
+
+impl Chunk {
+    fn left(&self) -> u64 {
+        match self {
+            Chunk::Raw { left } | Chunk::Fill { left, .. } | Chunk::DontCare { left } => *left,
+        }
+    }
+}
+
+impl<R: Read> SparseImageRead<R> {
+    pub fn new(r: R) -> Self {
+        Self {
+            r,
+            state: State::Start,
+        }
+    }
+
+    pub fn passthrough(r: R) -> Self {
+        Self {
+            r,
+            state: State::Passthrough,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.r
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    fn read_file_header(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; FILE_HEADER_LEN];
+        self.r.read_exact(&mut buf)?;
+        let header = SparseHeader::parse(&buf)?
+            .ok_or_else(|| invalid_data("not an Android sparse image"))?;
+        skip(
+            &mut self.r,
+            header.file_header_len as u64 - FILE_HEADER_LEN as u64,
+        )?;
+
+        self.state = State::Sparse {
+            header,
+            chunks_left: header.total_chunks,
+            chunk: Chunk::DontCare { left: 0 },
+        };
+        Ok(())
+    }
+
+    /// Reads the next chunk header, and any data in it that we need to hold on to.
+    fn read_chunk_header(r: &mut R, header: &SparseHeader) -> io::Result<Chunk> {
+        let mut buf = [0u8; CHUNK_HEADER_LEN];
+        r.read_exact(&mut buf)?;
+        skip(r, header.chunk_header_len as u64 - CHUNK_HEADER_LEN as u64)?;
+
+        let chunk_type = u16::from_le_bytes([buf[0], buf[1]]);
+        let blocks = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as u64;
+        let total_len = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]) as u64;
+        let data_len = total_len
+            .checked_sub(header.chunk_header_len as u64)
+            .ok_or_else(|| invalid_data("sparse image chunk is smaller than its header"))?;
+        let expanded_len = blocks * header.block_size as u64;
+
+        let expect_data_len = |expected: u64| {
+            if data_len == expected {
+                Ok(())
+            } else {
+                Err(invalid_data(format!(
+                    "sparse image chunk of type {chunk_type:#x} has {data_len} bytes of data, expected {expected}"
+                )))
+            }
+        };
+
+        match chunk_type {
+            CHUNK_TYPE_RAW => {
+                expect_data_len(expanded_len)?;
+                Ok(Chunk::Raw { left: expanded_len })
+            }
+            CHUNK_TYPE_FILL => {
+                expect_data_len(4)?;
+                let mut value = [0u8; 4];
+                r.read_exact(&mut value)?;
+                Ok(Chunk::Fill {
+                    value,
+                    offset: 0,
+                    left: expanded_len,
+                })
+            }
+            CHUNK_TYPE_DONT_CARE => {
+                expect_data_len(0)?;
+                Ok(Chunk::DontCare { left: expanded_len })
+            }
+            CHUNK_TYPE_CRC32 => {
+                // We rely on verification instead of checking this.
+                expect_data_len(4)?;
+                skip(r, 4)?;
+                Ok(Chunk::DontCare { left: 0 })
+            }
+            other => Err(invalid_data(format!(
+                "unknown sparse image chunk type {other:#x}"
+            ))),
+        }
+    }
+}
+
+impl<R: Read> Read for SparseImageRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let (header, chunks_left, chunk) = match &mut self.state {
+                State::Passthrough => return self.r.read(buf),
+                State::Start => {
+                    self.read_file_header()?;
+                    continue;
+                }
+                State::Sparse {
+                    header,
+                    chunks_left,
+                    chunk,
+                } => (header, chunks_left, chunk),
+            };
+
+            if chunk.left() == 0 {
+                if *chunks_left == 0 {
+                    return Ok(0);
+                }
+                *chunk = Self::read_chunk_header(&mut self.r, header)?;
+                *chunks_left -= 1;
+                continue;
+            }
+
+            let n = buf.len().min(chunk.left().try_into().unwrap_or(usize::MAX));
+            let buf = &mut buf[..n];
+            let read = match chunk {
+                Chunk::Raw { .. } => {
+                    let read = self.r.read(buf)?;
+                    if read == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "sparse image ended in the middle of a chunk",
+                        ));
+                    }
+                    read
+                }
+                Chunk::Fill { value, offset, .. } => {
+                    for (i, b) in buf.iter_mut().enumerate() {
+                        *b = value[(*offset as usize + i) % 4];
+                    }
+                    *offset += n as u64;
+                    n
+                }
+                Chunk::DontCare { .. } => {
+                    buf.fill(0);
+                    n
+                }
+            };
+
+            match chunk {
+                Chunk::Raw { left } | Chunk::Fill { left, .. } | Chunk::DontCare { left } => {
+                    *left -= read as u64
+                }
+            }
+            return Ok(read);
+        }
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn skip(r: &mut impl Read, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut r.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "sparse image ended unexpectedly",
+        ));
+    }
+    Ok(())
+}
+
+/// Like [Read::read_exact], but returns how much was read instead of failing at EOF.
+fn read_up_to(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{ErrorKind, Read};
+
+    use test_case::test_case;
+
+    use super::{SparseHeader, SparseImageRead, read_header};
+
+    pub(crate) enum TestChunk<'a> {
+        Raw(&'a [u8]),
+        Fill([u8; 4], u32),
+        DontCare(u32),
+        Crc32,
+    }
+
+    const BLOCK_SIZE: u32 = 8;
+
+    /// Builds a sparse image with a block size of 8.
+    pub(crate) fn make_image(chunks: &[TestChunk]) -> Vec<u8> {
+        let blocks = |c: &TestChunk| match c {
+            TestChunk::Raw(d) => d.len() as u32 / BLOCK_SIZE,
+            TestChunk::Fill(_, b) | TestChunk::DontCare(b) => *b,
+            TestChunk::Crc32 => 0,
+        };
+
+        let mut out = vec![];
+        out.extend(0xed26ff3au32.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(28u16.to_le_bytes());
+        out.extend(12u16.to_le_bytes());
+        out.extend(BLOCK_SIZE.to_le_bytes());
+        out.extend(chunks.iter().map(blocks).sum::<u32>().to_le_bytes());
+        out.extend((chunks.len() as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+
+        for c in chunks {
+            let (chunk_type, data): (u16, &[u8]) = match c {
+                TestChunk::Raw(d) => (0xcac1, d),
+                TestChunk::Fill(v, _) => (0xcac2, v),
+                TestChunk::DontCare(_) => (0xcac3, &[]),
+                TestChunk::Crc32 => (0xcac4, &[1, 2, 3, 4]),
+            };
+            out.extend(chunk_type.to_le_bytes());
+            out.extend(0u16.to_le_bytes());
+            out.extend(blocks(c).to_le_bytes());
+            out.extend((12 + data.len() as u32).to_le_bytes());
+            out.extend(data);
+        }
+        out
+    }
+
+    /// Reads everything using the given buffer size, to exercise chunk boundaries.
+    fn expand(image: &[u8], buf_size: usize) -> std::io::Result<Vec<u8>> {
+        let mut r = SparseImageRead::new(image);
+        let mut out = vec![];
+        let mut buf = vec![0; buf_size];
+        loop {
+            match r.read(&mut buf)? {
+                0 => return Ok(out),
+                n => out.extend(&buf[..n]),
+            }
+        }
+    }
+
+    #[test_case(1; "byte at a time")]
+    #[test_case(3; "misaligned buffer")]
+    #[test_case(4096; "big buffer")]
+    fn expands_all_chunk_types(buf_size: usize) {
+        let raw: Vec<u8> = (0..16).collect();
+        let image = make_image(&[
+            TestChunk::Raw(&raw),
+            TestChunk::Fill([1, 2, 3, 4], 2),
+            TestChunk::Crc32,
+            TestChunk::DontCare(1),
+            TestChunk::Raw(&raw[..8]),
+        ]);
+
+        let result = expand(&image, buf_size).unwrap();
+
+        let mut expected = raw.clone();
+        expected.extend([1, 2, 3, 4].repeat(4));
+        expected.extend([0; 8]);
+        expected.extend(&raw[..8]);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn read_header_detects_sparse_images() {
+        let image = make_image(&[TestChunk::DontCare(3)]);
+
+        let header = read_header(&image[..]).unwrap().unwrap();
+
+        assert_eq!(
+            header,
+            SparseHeader {
+                major_version: 1,
+                minor_version: 0,
+                file_header_len: 28,
+                chunk_header_len: 12,
+                block_size: 8,
+                total_blocks: 3,
+                total_chunks: 1,
+            }
+        );
+        assert_eq!(header.expanded_size(), 24);
+    }
+
+    #[test_case(&[]; "empty")]
+    #[test_case(&[0x3a, 0xff, 0x26, 0xed]; "magic only")]
+    #[test_case(&[0; 64]; "zeroes")]
+    fn read_header_ignores_other_data(data: &[u8]) {
+        assert_eq!(read_header(data).unwrap(), None);
+    }
+
+    #[test]
+    fn read_header_rejects_unknown_versions() {
+        let mut image = make_image(&[TestChunk::DontCare(3)]);
+        image[4] = 2;
+
+        let err = read_header(&image[..]).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_raw_chunk_fails() {
+        let raw = [5u8; 16];
+        let image = make_image(&[TestChunk::Raw(&raw)]);
+
+        let err = expand(&image[..image.len() - 3], 4096).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn unknown_chunk_type_fails() {
+        let mut image = make_image(&[TestChunk::DontCare(1)]);
+        image[28] = 0xff;
+
+        let err = expand(&image, 4096).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn passthrough_does_not_expand() {
+        let image = make_image(&[TestChunk::DontCare(1)]);
+
+        let mut out = vec![];
+        SparseImageRead::passthrough(&image[..])
+            .read_to_end(&mut out)
+            .unwrap();
+
+        assert_eq!(out, image);
+    }
+}
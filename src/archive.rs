@@ -0,0 +1,791 @@
+//! Support for burning a single member of a zip or tar archive, so that images
+//! distributed as archives don't need to be unpacked first.
+//!
+//! Tar archives are read after decompression, so `.tar.xz` and friends work too.
+//! Zip archives are located using their central directory, so they must not be
+//! compressed any further.
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Seek, SeekFrom, Take},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+const TAR_BLOCK_LEN: u64 = 512;
+const ZIP_LOCAL_HEADER_SIG: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER_SIG: u32 = 0x02014b50;
+const ZIP_EOCD_SIG: u32 = 0x06054b50;
+const ZIP64_EOCD_LOCATOR_SIG: u32 = 0x07064b50;
+const ZIP64_EOCD_SIG: u32 = 0x06064b50;
+const ZIP_LOCAL_HEADER_LEN: usize = 30;
+const ZIP_CENTRAL_HEADER_LEN: usize = 46;
+const ZIP_EOCD_LEN: usize = 22;
+const ZIP64_EOCD_LOCATOR_LEN: usize = 20;
+const ZIP64_EOCD_LEN: usize = 56;
+
+/// File extensions of archive members that we'll offer to burn.
+const IMAGE_EXTENSIONS: &[&str] = &["img", "iso", "raw", "bin", "simg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveFormat::Zip => write!(f, "zip"),
+            ArchiveFormat::Tar => write!(f, "tar"),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    /// Detect the archive format from the start of a (decompressed) stream. Returns
+    /// None if it doesn't look like an archive.
+    pub fn detect(r: impl Read) -> io::Result<Option<ArchiveFormat>> {
+        let mut header = Vec::with_capacity(TAR_BLOCK_LEN as usize);
+        r.take(TAR_BLOCK_LEN).read_to_end(&mut header)?;
+
+        if header.starts_with(&ZIP_LOCAL_HEADER_SIG.to_le_bytes()) {
+            Ok(Some(ArchiveFormat::Zip))
+        } else if header.len() == TAR_BLOCK_LEN as usize && &header[257..262] == b"ustar" {
+            Ok(Some(ArchiveFormat::Tar))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A regular file inside of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveMember {
+    pub name: String,
+    /// Size of the member once extracted.
+    pub size: u64,
+    pub location: MemberLocation,
+}
+
+/// Where to find a member's data inside of its archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemberLocation {
+    /// The data starts this many bytes into the decompressed tar stream.
+    Tar { offset: u64 },
+    /// The member's local file header starts this many bytes into the zip file.
+    Zip {
+        header_offset: u64,
+        compressed_size: u64,
+        method: ZipMethod,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZipMethod {
+    Stored,
+    Deflated,
+}
+
+impl ArchiveMember {
+    /// If this member looks like a disk image, based on its name.
+    pub fn is_likely_image(&self) -> bool {
+        let name = self.name.to_lowercase();
+        (name.rsplit_once('.')).is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext))
+    }
+}
+
+impl Display for ArchiveMember {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, bytesize::ByteSize::b(self.size))
+    }
+}
+
+/// Lists the regular files in a tar stream, in the order they appear.
+pub fn list_tar(mut r: impl Read) -> io::Result<Vec<ArchiveMember>> {
+    let mut members = vec![];
+    let mut offset = 0u64;
+    // Overrides for the next entry, from GNU long name or pax extended headers
+    let mut next_name: Option<String> = None;
+    let mut next_size: Option<u64> = None;
+
+    loop {
+        let mut header = [0u8; TAR_BLOCK_LEN as usize];
+        if read_up_to(&mut r, &mut header)? < header.len() {
+            return Err(unexpected_eof("tar archive ended without an end marker"));
+        }
+        offset += TAR_BLOCK_LEN;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        verify_tar_checksum(&header)?;
+
+        let size = next_size
+            .take()
+            .map_or_else(|| parse_tar_size(&header), Ok)?;
+        let padded_size = size.next_multiple_of(TAR_BLOCK_LEN);
+        let data_offset = offset;
+        offset += padded_size;
+
+        match header[156] {
+            b'0' | 0 => {
+                let name = next_name.take().unwrap_or_else(|| tar_header_name(&header));
+                members.push(ArchiveMember {
+                    name,
+                    size,
+                    location: MemberLocation::Tar {
+                        offset: data_offset,
+                    },
+                });
+                skip(&mut r, padded_size)?;
+            }
+            b'L' => {
+                next_name = Some(read_tar_string(&mut r, size, padded_size)?);
+            }
+            b'x' => {
+                let records = read_tar_string(&mut r, size, padded_size)?;
+                for (key, value) in parse_pax_records(&records)? {
+                    match key {
+                        "path" => next_name = Some(value.to_owned()),
+                        "size" => {
+                            next_size =
+                                Some(value.parse().map_err(|_| {
+                                    invalid_data(format!("invalid pax size {value:?}"))
+                                })?)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            other => {
+                debug!(typeflag = other, "Skipping non-file tar entry");
+                next_name = None;
+                skip(&mut r, padded_size)?;
+            }
+        }
+    }
+
+    Ok(members)
+}
+
+/// Lists the regular files in a zip file, in the order of its central directory.
+/// Members using compression methods other than store and deflate are left out.
+pub fn list_zip(mut r: impl Read + Seek) -> io::Result<Vec<ArchiveMember>> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+
+    // The end of central directory record is followed by a comment of up to 64KiB,
+    // so search backwards for its signature.
+    let tail_len = file_len.min((ZIP_EOCD_LEN + u16::MAX as usize) as u64);
+    let mut tail = vec![0u8; tail_len as usize];
+    r.seek(SeekFrom::Start(file_len - tail_len))?;
+    r.read_exact(&mut tail)?;
+    let eocd_pos = (0..(tail.len() + 1).saturating_sub(ZIP_EOCD_LEN))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == ZIP_EOCD_SIG)
+        .ok_or_else(|| invalid_data("could not find the end of the zip central directory"))?;
+    let eocd = &tail[eocd_pos..];
+
+    let mut entries = u16_at(eocd, 10) as u64;
+    let mut cd_offset = u32_at(eocd, 16) as u64;
+    if entries == u16::MAX as u64 || cd_offset == u32::MAX as u64 {
+        let locator_pos = eocd_pos
+            .checked_sub(ZIP64_EOCD_LOCATOR_LEN)
+            .filter(|&i| u32_at(&tail, i) == ZIP64_EOCD_LOCATOR_SIG)
+            .ok_or_else(|| invalid_data("could not find the zip64 end of central directory"))?;
+        let mut eocd64 = [0u8; ZIP64_EOCD_LEN];
+        r.seek(SeekFrom::Start(u64_at(&tail, locator_pos + 8)))?;
+        r.read_exact(&mut eocd64)?;
+        if u32_at(&eocd64, 0) != ZIP64_EOCD_SIG {
+            return Err(invalid_data("invalid zip64 end of central directory"));
+        }
+        entries = u64_at(&eocd64, 32);
+        cd_offset = u64_at(&eocd64, 48);
+    }
+
+    let mut members = vec![];
+    let mut r = io::BufReader::new(r);
+    r.seek(SeekFrom::Start(cd_offset))?;
+    for _ in 0..entries {
+        let mut header = [0u8; ZIP_CENTRAL_HEADER_LEN];
+        r.read_exact(&mut header)?;
+        if u32_at(&header, 0) != ZIP_CENTRAL_HEADER_SIG {
+            return Err(invalid_data("invalid zip central directory entry"));
+        }
+        let flags = u16_at(&header, 8);
+        let method = u16_at(&header, 10);
+        let mut compressed_size = u32_at(&header, 20) as u64;
+        let mut size = u32_at(&header, 24) as u64;
+        let mut header_offset = u32_at(&header, 42) as u64;
+
+        let mut name = vec![0u8; u16_at(&header, 28) as usize];
+        let mut extra = vec![0u8; u16_at(&header, 30) as usize];
+        r.read_exact(&mut name)?;
+        r.read_exact(&mut extra)?;
+        skip(&mut r, u16_at(&header, 32) as u64)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+
+        // Sizes and offsets that don't fit are replaced with all ones, and moved into
+        // the zip64 extra field in this order.
+        if let Some(mut zip64) = find_zip_extra_field(&extra, 0x0001) {
+            for field in [&mut size, &mut compressed_size, &mut header_offset] {
+                if *field == u32::MAX as u64 && zip64.len() >= 8 {
+                    *field = u64_at(zip64, 0);
+                    zip64 = &zip64[8..];
+                }
+            }
+        }
+
+        let method = match method {
+            0 => ZipMethod::Stored,
+            8 => ZipMethod::Deflated,
+            other => {
+                debug!(
+                    name,
+                    method = other,
+                    "Skipping zip entry with unsupported method"
+                );
+                continue;
+            }
+        };
+        if flags & 1 != 0 {
+            debug!(name, "Skipping encrypted zip entry");
+            continue;
+        }
+        if name.ends_with('/') {
+            continue;
+        }
+
+        members.push(ArchiveMember {
+            name,
+            size,
+            location: MemberLocation::Zip {
+                header_offset,
+                compressed_size,
+                method,
+            },
+        });
+    }
+
+    Ok(members)
+}
+
+/// Reads the data of a single archive member out of the archive's stream.
+///
+/// If constructed with [ArchiveMemberRead::passthrough], this passes data through
+/// unchanged instead, so that readers can have the same type either way.
+pub struct ArchiveMemberRead<R: Read> {
+    /// Only None while transitioning between states.
+    state: Option<State<R>>,
+}
+
+enum State<R: Read> {
+    Passthrough(R),
+    /// We still need to skip to the start of the member.
+    Start(R, MemberLocation, u64),
+    Stored(Take<R>),
+    Deflated(flate2::read::DeflateDecoder<Take<R>>),
+}
+
+impl<R: Read> ArchiveMemberRead<R> {
+    pub fn new(r: R, member: &ArchiveMember) -> Self {
+        Self {
+            state: Some(State::Start(r, member.location, member.size)),
+        }
+    }
+
+    pub fn passthrough(r: R) -> Self {
+        Self {
+            state: Some(State::Passthrough(r)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        match self.state.as_ref().unwrap() {
+            State::Passthrough(r) | State::Start(r, _, _) => r,
+            State::Stored(r) => r.get_ref(),
+            State::Deflated(r) => r.get_ref().get_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        match self.state.as_mut().unwrap() {
+            State::Passthrough(r) | State::Start(r, _, _) => r,
+            State::Stored(r) => r.get_mut(),
+            State::Deflated(r) => r.get_mut().get_mut(),
+        }
+    }
+
+    /// Skips to the start of the member's data.
+    fn start(&mut self) -> io::Result<()> {
+        let Some(State::Start(r, location, size)) = &mut self.state else {
+            return Ok(());
+        };
+        let size = *size;
+
+        // Do all the fallible work before moving the reader into its next state.
+        let (data_len, method) = match *location {
+            MemberLocation::Tar { offset } => {
+                skip(r, offset)?;
+                (size, ZipMethod::Stored)
+            }
+            MemberLocation::Zip {
+                header_offset,
+                compressed_size,
+                method,
+            } => {
+                skip(r, header_offset)?;
+                let mut header = [0u8; ZIP_LOCAL_HEADER_LEN];
+                r.read_exact(&mut header)?;
+                if u32_at(&header, 0) != ZIP_LOCAL_HEADER_SIG {
+                    return Err(invalid_data("invalid zip local file header"));
+                }
+                skip(r, u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64)?;
+                (compressed_size, method)
+            }
+        };
+
+        let Some(State::Start(r, _, _)) = self.state.take() else {
+            unreachable!()
+        };
+        let data = r.take(data_len);
+        self.state = Some(match method {
+            ZipMethod::Stored => State::Stored(data),
+            ZipMethod::Deflated => State::Deflated(flate2::read::DeflateDecoder::new(data)),
+        });
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ArchiveMemberRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.start()?;
+        match self.state.as_mut().unwrap() {
+            State::Passthrough(r) => r.read(buf),
+            State::Start(..) => unreachable!(),
+            State::Stored(r) => {
+                let read = r.read(buf)?;
+                if read == 0 && !buf.is_empty() && r.limit() > 0 {
+                    return Err(unexpected_eof("archive ended in the middle of a member"));
+                }
+                Ok(read)
+            }
+            State::Deflated(r) => r.read(buf),
+        }
+    }
+}
+
+fn parse_tar_size(header: &[u8]) -> io::Result<u64> {
+    let field = &header[124..136];
+    // GNU tar stores sizes too big for octal as big-endian base-256, flagged by the
+    // high bit of the first byte.
+    if field[0] & 0x80 != 0 {
+        return Ok((field[1..].iter()).fold(0u64, |acc, b| (acc << 8) | *b as u64));
+    }
+    parse_octal(field)
+}
+
+fn parse_octal(field: &[u8]) -> io::Result<u64> {
+    let s = std::str::from_utf8(field)
+        .ok()
+        .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+        .ok_or_else(|| invalid_data("invalid octal number in tar header"))?;
+    if s.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(s, 8).map_err(|_| invalid_data(format!("invalid octal number {s:?}")))
+}
+
+fn verify_tar_checksum(header: &[u8; TAR_BLOCK_LEN as usize]) -> io::Result<()> {
+    let expected = parse_octal(&header[148..156])?;
+    let actual: u64 = (header.iter().enumerate())
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u64)
+        .sum();
+    if expected != actual {
+        return Err(invalid_data("tar header checksum mismatch"));
+    }
+    Ok(())
+}
+
+fn tar_header_name(header: &[u8]) -> String {
+    let field = |range: std::ops::Range<usize>| {
+        let f = &header[range];
+        let end = f.iter().position(|b| *b == 0).unwrap_or(f.len());
+        String::from_utf8_lossy(&f[..end]).into_owned()
+    };
+    let name = field(0..100);
+    let prefix = field(345..500);
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
+
+/// Reads a string stored as an entry's data, such as a GNU long name.
+fn read_tar_string(r: &mut impl Read, size: u64, padded_size: u64) -> io::Result<String> {
+    let mut data = vec![0u8; size as usize];
+    r.read_exact(&mut data)?;
+    skip(r, padded_size - size)?;
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+/// Parses pax extended header records, which look like `<length> <key>=<value>\n`.
+fn parse_pax_records(mut records: &str) -> io::Result<Vec<(&str, &str)>> {
+    let mut out = vec![];
+    while !records.is_empty() {
+        let invalid = || invalid_data("invalid pax extended header");
+        let (len, _) = records.split_once(' ').ok_or_else(invalid)?;
+        let len: usize = len.parse().map_err(|_| invalid())?;
+        let record = records.get(..len).ok_or_else(invalid)?;
+        records = &records[len..];
+
+        let (_, kv) = record.split_once(' ').ok_or_else(invalid)?;
+        let (key, value) = (kv.strip_suffix('\n'))
+            .and_then(|kv| kv.split_once('='))
+            .ok_or_else(invalid)?;
+        out.push((key, value));
+    }
+    Ok(out)
+}
+
+fn find_zip_extra_field(mut extra: &[u8], id: u16) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let len = u16_at(extra, 2) as usize;
+        let data = extra.get(4..4 + len)?;
+        if u16_at(extra, 0) == id {
+            return Some(data);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn u16_at(buf: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes(buf[i..i + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(buf[i..i + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], i: usize) -> u64 {
+    u64::from_le_bytes(buf[i..i + 8].try_into().unwrap())
+}
+
+/// Reads and discards exactly `n` bytes.
+fn skip(r: &mut impl Read, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut r.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(unexpected_eof("archive ended unexpectedly"));
+    }
+    Ok(())
+}
+
+/// Like [Read::read_exact], but returns how many bytes were read if the stream ends
+/// early instead of erroring.
+fn read_up_to(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn unexpected_eof(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+
+    pub(crate) enum TarEntry<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        /// A file whose name is too long for the header, stored in a GNU long name entry
+        LongName(&'a str, &'a [u8]),
+        /// A file whose name is stored in a pax extended header
+        Pax(&'a str, &'a [u8]),
+    }
+
+    fn tar_header(name: &str, size: u64, typeflag: u8) -> [u8; 512] {
+        let mut h = [0u8; 512];
+        h[..name.len().min(100)].copy_from_slice(&name.as_bytes()[..name.len().min(100)]);
+        h[100..107].copy_from_slice(b"0000644");
+        h[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        h[156] = typeflag;
+        h[257..263].copy_from_slice(b"ustar\0");
+        h[263..265].copy_from_slice(b"00");
+        h[148..156].copy_from_slice(b"        ");
+        let sum: u64 = h.iter().map(|b| *b as u64).sum();
+        h[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        h
+    }
+
+    fn tar_data(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(512), 0);
+    }
+
+    pub(crate) fn make_tar(entries: &[TarEntry]) -> Vec<u8> {
+        let mut out = vec![];
+        for e in entries {
+            match e {
+                TarEntry::File(name, data) => {
+                    out.extend_from_slice(&tar_header(name, data.len() as u64, b'0'));
+                    tar_data(&mut out, data);
+                }
+                TarEntry::Dir(name) => out.extend_from_slice(&tar_header(name, 0, b'5')),
+                TarEntry::LongName(name, data) => {
+                    let long = format!("{name}\0");
+                    out.extend_from_slice(&tar_header("././@LongLink", long.len() as u64, b'L'));
+                    tar_data(&mut out, long.as_bytes());
+                    out.extend_from_slice(&tar_header("truncated", data.len() as u64, b'0'));
+                    tar_data(&mut out, data);
+                }
+                TarEntry::Pax(name, data) => {
+                    let record = format!(" path={name}\n");
+                    // The length includes its own digits
+                    let mut len = record.len();
+                    while len != len.to_string().len() + record.len() {
+                        len = len.to_string().len() + record.len();
+                    }
+                    let record = format!("{len}{record}");
+                    out.extend_from_slice(&tar_header("PaxHeader", record.len() as u64, b'x'));
+                    tar_data(&mut out, record.as_bytes());
+                    out.extend_from_slice(&tar_header("truncated", data.len() as u64, b'0'));
+                    tar_data(&mut out, data);
+                }
+            }
+        }
+        out.extend_from_slice(&[0; 1024]);
+        out
+    }
+
+    /// Makes a zip file of `(name, data, deflate)` entries. If `zip64`, all sizes and
+    /// offsets are stored in zip64 fields.
+    pub(crate) fn make_zip(entries: &[(&str, &[u8], bool)], zip64: bool) -> Vec<u8> {
+        let mut out = vec![];
+        let mut central = vec![];
+        for (name, data, deflate) in entries {
+            let body = if *deflate {
+                let mut e =
+                    flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            } else {
+                data.to_vec()
+            };
+            let method: u16 = if *deflate { 8 } else { 0 };
+            let offset = out.len() as u64;
+
+            out.extend_from_slice(&ZIP_LOCAL_HEADER_SIG.to_le_bytes());
+            out.extend_from_slice(&[20, 0, 0, 0]);
+            out.extend_from_slice(&method.to_le_bytes());
+            out.extend_from_slice(&[0; 8]); // time, date, crc
+            out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&3u16.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b"xyz"); // junk extra field
+            out.extend_from_slice(&body);
+
+            let small = |x: u64| if zip64 { u32::MAX } else { x as u32 };
+            let mut extra = vec![];
+            if zip64 {
+                extra.extend_from_slice(&1u16.to_le_bytes());
+                extra.extend_from_slice(&24u16.to_le_bytes());
+                for x in [data.len() as u64, body.len() as u64, offset] {
+                    extra.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER_SIG.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&[0; 8]);
+            central.extend_from_slice(&small(body.len() as u64).to_le_bytes());
+            central.extend_from_slice(&small(data.len() as u64).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 10]); // comment len, disk, attrs
+            central.extend_from_slice(&small(offset).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+            central.extend_from_slice(&extra);
+        }
+
+        let cd_offset = out.len() as u64;
+        out.extend_from_slice(&central);
+        if zip64 {
+            let eocd64_offset = out.len() as u64;
+            out.extend_from_slice(&ZIP64_EOCD_SIG.to_le_bytes());
+            out.extend_from_slice(&44u64.to_le_bytes());
+            out.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(central.len() as u64).to_le_bytes());
+            out.extend_from_slice(&cd_offset.to_le_bytes());
+            out.extend_from_slice(&ZIP64_EOCD_LOCATOR_SIG.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&eocd64_offset.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let (entries16, cd_offset32) = match zip64 {
+            true => (u16::MAX, u32::MAX),
+            false => (entries.len() as u16, cd_offset as u32),
+        };
+        out.extend_from_slice(&ZIP_EOCD_SIG.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&entries16.to_le_bytes());
+        out.extend_from_slice(&entries16.to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&cd_offset32.to_le_bytes());
+        out.extend_from_slice(&5u16.to_le_bytes());
+        out.extend_from_slice(b"hello");
+        out
+    }
+
+    fn read_member(archive: &[u8], member: &ArchiveMember) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        ArchiveMemberRead::new(archive, member).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn names(members: &[ArchiveMember]) -> Vec<&str> {
+        members.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn tar_members_are_listed_and_read() {
+        let big = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+        let tar = make_tar(&[
+            TarEntry::Dir("images/"),
+            TarEntry::File("images/README", b"hello"),
+            TarEntry::File("images/empty.img", b""),
+            TarEntry::File("images/disk.img", &big),
+        ]);
+
+        let members = list_tar(&tar[..]).unwrap();
+
+        assert_eq!(
+            names(&members),
+            ["images/README", "images/empty.img", "images/disk.img"]
+        );
+        assert_eq!(read_member(&tar, &members[0]).unwrap(), b"hello");
+        assert_eq!(read_member(&tar, &members[1]).unwrap(), b"");
+        assert_eq!(read_member(&tar, &members[2]).unwrap(), big);
+        assert_eq!(members[2].size, 5000);
+    }
+
+    #[test]
+    fn tar_long_names_are_used() {
+        let long = format!("{}/disk.img", "a".repeat(150));
+        let tar = make_tar(&[
+            TarEntry::LongName(&long, b"long"),
+            TarEntry::Pax("pax/disk.img", b"pax"),
+            TarEntry::File("short", b"short"),
+        ]);
+
+        let members = list_tar(&tar[..]).unwrap();
+
+        assert_eq!(names(&members), [long.as_str(), "pax/disk.img", "short"]);
+        assert_eq!(read_member(&tar, &members[0]).unwrap(), b"long");
+        assert_eq!(read_member(&tar, &members[1]).unwrap(), b"pax");
+        assert_eq!(read_member(&tar, &members[2]).unwrap(), b"short");
+    }
+
+    #[test]
+    fn tar_with_bad_checksum_is_rejected() {
+        let mut tar = make_tar(&[TarEntry::File("disk.img", b"data")]);
+        tar[0] = b'x';
+
+        assert_matches!(list_tar(&tar[..]), Err(e) if e.kind() == io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_tar_member_errors() {
+        let tar = make_tar(&[TarEntry::File("disk.img", &[1; 2000])]);
+        let members = list_tar(&tar[..]).unwrap();
+
+        assert_matches!(
+            read_member(&tar[..1000], &members[0]),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test_case(false; "normal")]
+    #[test_case(true; "zip64")]
+    fn zip_members_are_listed_and_read(zip64: bool) {
+        let big = (0..100000).map(|i| (i / 100) as u8).collect::<Vec<_>>();
+        let zip = make_zip(
+            &[
+                ("dir/", b"", false),
+                ("stored.img", b"stored data", false),
+                ("deflated.img", &big, true),
+            ],
+            zip64,
+        );
+
+        let members = list_zip(Cursor::new(&zip)).unwrap();
+
+        assert_eq!(names(&members), ["stored.img", "deflated.img"]);
+        assert_eq!(members[1].size, big.len() as u64);
+        assert_eq!(read_member(&zip, &members[0]).unwrap(), b"stored data");
+        assert_eq!(read_member(&zip, &members[1]).unwrap(), big);
+    }
+
+    #[test]
+    fn garbage_is_not_a_zip() {
+        assert_matches!(
+            list_zip(Cursor::new(vec![7u8; 1000])),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        let tar = make_tar(&[TarEntry::File("disk.img", b"data")]);
+        let zip = make_zip(&[("disk.img", b"data", false)], false);
+
+        assert_eq!(
+            ArchiveFormat::detect(&tar[..]).unwrap(),
+            Some(ArchiveFormat::Tar)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(&zip[..]).unwrap(),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::detect(&[0u8; 4096][..]).unwrap(), None);
+        assert_eq!(ArchiveFormat::detect(&b""[..]).unwrap(), None);
+    }
+
+    #[test_case("disk.img" => true)]
+    #[test_case("path/to/DISK.ISO" => true)]
+    #[test_case("system.simg" => true)]
+    #[test_case("README.md" => false)]
+    #[test_case("img" => false)]
+    fn likely_images(name: &str) -> bool {
+        ArchiveMember {
+            name: name.into(),
+            size: 0,
+            location: MemberLocation::Tar { offset: 0 },
+        }
+        .is_likely_image()
+    }
+}
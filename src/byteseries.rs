@@ -30,6 +30,16 @@ impl From<EstimatedTimeInfo> for EstimatedTime {
     }
 }
 
+impl EstimatedTime {
+    /// How many seconds are left, if known.
+    pub fn secs_left(&self) -> Option<f64> {
+        match self {
+            EstimatedTime::Known(x) => Some(x.secs_left),
+            EstimatedTime::Unknown => None,
+        }
+    }
+}
+
 impl Display for EstimatedTime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
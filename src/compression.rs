@@ -1,24 +1,42 @@
+mod decompressed_size;
 mod zstd_streaming_decoder;
 
 use clap::ValueEnum;
 use std::{
     fmt::Display,
-    io::{BufRead, Read},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 use serde::{Deserialize, Serialize};
 
+pub use self::decompressed_size::DecompressedSize;
+
 macro_rules! generate {
     {
         reader_var: $reader_var:ident,
         reader_typename: $reader_typename:ident,
+        writer_var: $writer_var:ident,
+        writer_typename: $writer_typename:ident,
+        level_var: $level_var:ident,
         $($enum_arm:ident {
             extension_pattern: $ext_pat:pat,
+            magic: $magic:expr,
             display: $display:expr,
             from_reader() -> $inner:ty {
                 $from_reader:expr
             }
+            levels: {
+                fast: $level_fast:expr,
+                default: $level_default:expr,
+                best: $level_best:expr,
+            }
+            to_writer() -> $outer:ty {
+                $to_writer:expr
+            }
+            finish($finish_var:ident) {
+                $finish:expr
+            }
         })*
     } => {
         #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
@@ -71,12 +89,38 @@ macro_rules! generate {
                 }
             }
 
+            /// Detect the format from the first few bytes of a file. Returns None
+            /// if they don't look like any format we know of.
+            pub fn detect_from_magic(header: &[u8]) -> Option<Self> {
+                $(
+                    if header.starts_with($magic) {
+                        return Some(Self::$enum_arm);
+                    }
+                )*
+                None
+            }
+
             pub fn is_identity(self) -> bool {
                 match self {
                     Self::Identity => true,
                     _ => false,
                 }
             }
+
+            /// The format-specific numeric level corresponding to the given
+            /// [CompressionLevel]. Formats without levels always return 0.
+            pub fn numeric_level(self, level: CompressionLevel) -> u32 {
+                match self {
+                    Self::Identity => 0,
+                    $(
+                        Self::$enum_arm => match level {
+                            CompressionLevel::Fast => $level_fast,
+                            CompressionLevel::Default => $level_default,
+                            CompressionLevel::Best => $level_best,
+                        },
+                    )*
+                }
+            }
         }
 
         impl Display for CompressionFormat {
@@ -90,6 +134,7 @@ macro_rules! generate {
             }
         }
 
+        #[allow(clippy::large_enum_variant)]
         pub enum DecompressRead<$reader_typename: BufRead> {
             Identity($reader_typename),
             $(
@@ -134,6 +179,82 @@ macro_rules! generate {
             }
         }
 
+        pub enum CompressWrite<$writer_typename: Write> {
+            Identity($writer_typename),
+            $(
+                $enum_arm($outer),
+            )*
+        }
+
+        impl<W> CompressWrite<W>
+        where
+            W: Write,
+        {
+            pub fn get_ref(&self) -> &W {
+                match self {
+                    Self::Identity(w) => w,
+                    $(
+                        Self::$enum_arm(w) => w.get_ref(),
+                    )*
+                }
+            }
+
+            /// Write out any trailing data required by the format, and return the
+            /// underlying writer.
+            pub fn finish(self) -> std::io::Result<W> {
+                match self {
+                    Self::Identity(w) => Ok(w),
+                    $(
+                        Self::$enum_arm($finish_var) => Ok($finish),
+                    )*
+                }
+            }
+        }
+
+        impl<W> Write for CompressWrite<W>
+        where
+            W: Write,
+        {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                match self {
+                    Self::Identity(w) => w.write(buf),
+                    $(
+                        Self::$enum_arm(w) => w.write(buf),
+                    )*
+                }
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                match self {
+                    Self::Identity(w) => w.flush(),
+                    $(
+                        Self::$enum_arm(w) => w.flush(),
+                    )*
+                }
+            }
+        }
+
+        /// Open a compressor for the given writer.
+        pub fn compress<W>(
+            cf: CompressionFormat,
+            level: CompressionLevel,
+            $writer_var: W,
+        ) -> anyhow::Result<CompressWrite<W>>
+        where
+            W : Write
+        {
+            #[allow(unused_variables)]
+            let $level_var = cf.numeric_level(level);
+            match cf {
+                CompressionFormat::Identity => Ok(CompressWrite::Identity($writer_var)),
+                $(
+                    CompressionFormat::$enum_arm => {
+                        Ok(CompressWrite::$enum_arm($to_writer))
+                    }
+                )*
+            }
+        }
+
         /// Open a decompressor for the given reader.
         pub fn decompress<R>(cf: CompressionFormat, $reader_var: R) -> anyhow::Result<DecompressRead<R>>
         where
@@ -154,47 +275,233 @@ macro_rules! generate {
 generate! {
     reader_var: r,
     reader_typename: R,
+    writer_var: w,
+    writer_typename: W,
+    level_var: level,
     Gz {
-        extension_pattern: "gz",
+        extension_pattern: "gz" | "tgz",
+        magic: b"\x1f\x8b",
         display: "gzip",
         from_reader() -> flate2::bufread::GzDecoder<R> {
             flate2::bufread::GzDecoder::new(r)
         }
+        levels: {
+            fast: 1,
+            default: 6,
+            best: 9,
+        }
+        to_writer() -> flate2::write::GzEncoder<W> {
+            flate2::write::GzEncoder::new(w, flate2::Compression::new(level))
+        }
+        finish(e) {
+            e.finish()?
+        }
     }
     Bz2 {
         extension_pattern: "bz2",
+        magic: b"BZh",
         display: "bzip2",
         from_reader() -> bzip2::bufread::BzDecoder<R> {
             bzip2::bufread::BzDecoder::new(r)
         }
+        levels: {
+            fast: 1,
+            default: 6,
+            best: 9,
+        }
+        to_writer() -> bzip2::write::BzEncoder<W> {
+            bzip2::write::BzEncoder::new(w, bzip2::Compression::new(level))
+        }
+        finish(e) {
+            e.finish()?
+        }
     }
     Xz {
-        extension_pattern: "xz",
+        extension_pattern: "xz" | "txz",
+        magic: b"\xfd7zXZ\x00",
         display: "xz/LZMA",
         from_reader() -> xz2::bufread::XzDecoder<R> {
             xz2::bufread::XzDecoder::new(r)
         }
+        levels: {
+            fast: 1,
+            default: 6,
+            best: 9,
+        }
+        to_writer() -> xz2::write::XzEncoder<W> {
+            xz2::write::XzEncoder::new(w, level)
+        }
+        finish(e) {
+            e.finish()?
+        }
     }
     Lz4 {
         extension_pattern: "lz4",
+        magic: b"\x04\x22\x4d\x18",
         display: "lz4",
         from_reader() -> lz4_flex::frame::FrameDecoder<R> {
             lz4_flex::frame::FrameDecoder::new(r)
         }
+        // lz4_flex only implements a single level
+        levels: {
+            fast: 0,
+            default: 0,
+            best: 0,
+        }
+        to_writer() -> lz4_flex::frame::FrameEncoder<W> {
+            lz4_flex::frame::FrameEncoder::new(w)
+        }
+        finish(e) {
+            e.finish()?
+        }
     }
     Zst {
         extension_pattern: "zst",
+        magic: b"\x28\xb5\x2f\xfd",
         display: "zstd/ZStandard",
         from_reader() -> self::zstd_streaming_decoder::StreamingDecoder<R, ruzstd::frame_decoder::FrameDecoder> {
             self::zstd_streaming_decoder::StreamingDecoder::new(r)?
         }
+        // Levels above 19 need a very large window, which makes them expensive to decode
+        levels: {
+            fast: 1,
+            default: 3,
+            best: 19,
+        }
+        to_writer() -> zstd::stream::write::Encoder<'static, W> {
+            zstd::stream::write::Encoder::new(w, level as i32)?
+        }
+        finish(e) {
+            e.finish()?
+        }
     }
 }
 
+/// How much effort a compressor should spend on making its output smaller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+pub enum CompressionLevel {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
 impl CompressionFormat {
     pub fn detect_from_path(path: impl AsRef<Path>) -> Option<CompressionFormat> {
         path.as_ref()
             .extension()
             .map(|ext| CompressionFormat::detect_from_extension(&ext.to_string_lossy()))
     }
+
+    /// Detect the format by looking at the start of the stream. Anything that
+    /// doesn't start with a known magic number is assumed to be uncompressed.
+    pub fn detect_from_contents(r: impl Read) -> std::io::Result<CompressionFormat> {
+        let mut header = Vec::with_capacity(MAX_MAGIC_LEN);
+        r.take(MAX_MAGIC_LEN as u64).read_to_end(&mut header)?;
+        Ok(Self::detect_from_magic(&header).unwrap_or(Self::Identity))
+    }
+
+    /// How big `file` is once decompressed, if this format records it.
+    pub fn decompressed_size(
+        self,
+        mut file: impl Read + Seek,
+    ) -> io::Result<Option<DecompressedSize>> {
+        match self {
+            CompressionFormat::Identity => {
+                Ok(Some(DecompressedSize::Exact(file.seek(SeekFrom::End(0))?)))
+            }
+            CompressionFormat::Gz => decompressed_size::gzip(file),
+            CompressionFormat::Bz2 => Ok(None),
+            CompressionFormat::Xz => decompressed_size::xz(file),
+            CompressionFormat::Lz4 => decompressed_size::lz4(file),
+            CompressionFormat::Zst => decompressed_size::zstd(file),
+        }
+    }
+}
+
+/// Long enough to hold the longest magic number we check for (xz's).
+const MAX_MAGIC_LEN: usize = 6;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+    use rstest::rstest;
+
+    use super::{AVAILABLE_FORMATS, CompressionFormat, CompressionLevel, compress, decompress};
+
+    /// Somewhat compressible data, so that the compressors have something to chew on.
+    fn make_data(n: usize) -> Vec<u8> {
+        let mut rng = SmallRng::seed_from_u64(42);
+        (0..n)
+            .map(|i| {
+                if i % 7 == 0 {
+                    rng.r#gen()
+                } else {
+                    (i / 1024) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn compress_then_decompress_roundtrips(
+        #[values(
+            CompressionLevel::Fast,
+            CompressionLevel::Default,
+            CompressionLevel::Best
+        )]
+        level: CompressionLevel,
+        #[values(0, 1, 1000, 300000)] size: usize,
+    ) {
+        let data = make_data(size);
+
+        for &cf in AVAILABLE_FORMATS {
+            let mut compressor = compress(cf, level, vec![]).unwrap();
+            // Write in odd-sized chunks to exercise the encoders' buffering
+            for chunk in data.chunks(4099) {
+                compressor.write_all(chunk).unwrap();
+            }
+            let compressed = compressor.finish().unwrap();
+
+            let mut decompressed = vec![];
+            decompress(cf, &compressed[..])
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+
+            assert!(
+                decompressed == data,
+                "{cf} at level {level:?} did not roundtrip {size} bytes"
+            );
+        }
+    }
+
+    #[rstest]
+    fn detects_format_of_compressed_output(#[values(0, 1000)] size: usize) {
+        let data = make_data(size);
+
+        for &cf in AVAILABLE_FORMATS {
+            let mut compressor = compress(cf, CompressionLevel::Default, vec![]).unwrap();
+            compressor.write_all(&data).unwrap();
+            let compressed = compressor.finish().unwrap();
+
+            assert_eq!(
+                CompressionFormat::detect_from_contents(&compressed[..]).unwrap(),
+                cf,
+                "misdetected {size} bytes of {cf}"
+            );
+        }
+    }
+
+    #[test]
+    fn detects_unknown_contents_as_uncompressed() {
+        for contents in [&b""[..], b"\x1f", b"BZ", b"hello world", &[0; 4096]] {
+            assert_eq!(
+                CompressionFormat::detect_from_contents(contents).unwrap(),
+                CompressionFormat::Identity
+            );
+        }
+    }
 }
@@ -0,0 +1,372 @@
+//! Finding out how big a compressed file is once decompressed, without decompressing
+//! it, by reading the sizes that some formats record in their headers or trailers.
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use bytesize::ByteSize;
+
+/// How big a compressed file is once decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressedSize {
+    Exact(u64),
+    /// The format doesn't record the whole size (i.e. gzip only records it modulo
+    /// 4 GiB), but it is probably at least this big. This is only a hint, since
+    /// anything after the end of the compressed data throws it off.
+    AtLeast(u64),
+}
+
+impl DecompressedSize {
+    /// The smallest it could be.
+    pub fn min_bytes(self) -> u64 {
+        match self {
+            DecompressedSize::Exact(n) | DecompressedSize::AtLeast(n) => n,
+        }
+    }
+
+    /// Whether this definitely won't fit in `capacity` bytes.
+    pub fn exceeds(self, capacity: u64) -> bool {
+        matches!(self, DecompressedSize::Exact(n) if n > capacity)
+    }
+}
+
+impl Display for DecompressedSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompressedSize::Exact(n) => write!(f, "{}", ByteSize::b(*n)),
+            DecompressedSize::AtLeast(n) => write!(f, "at least {}", ByteSize::b(*n)),
+        }
+    }
+}
+
+/// The last 4 bytes of a gzip member are its size modulo 2^32. If there are several
+/// members, this is only the last one's, but the whole is still at least that big.
+pub fn gzip(mut r: impl Read + Seek) -> io::Result<Option<DecompressedSize>> {
+    if r.seek(SeekFrom::End(0))? < 18 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::End(-4))?;
+    let isize = read_u32_le(&mut r)?;
+    Ok(Some(DecompressedSize::AtLeast(isize as u64)))
+}
+
+/// The index at the end of each xz stream records how big each of its blocks are.
+/// Streams are read from the end, since that's where their indexes are.
+pub fn xz(mut r: impl Read + Seek) -> io::Result<Option<DecompressedSize>> {
+    const FOOTER_MAGIC: &[u8; 2] = b"YZ";
+
+    let mut end = r.seek(SeekFrom::End(0))?;
+    let mut total = 0u64;
+    while end > 0 {
+        // Streams may be followed by padding of null bytes, in multiples of 4
+        r.seek(SeekFrom::Start(end.saturating_sub(4)))?;
+        if end >= 4 && read_u32_le(&mut r)? == 0 {
+            end -= 4;
+            continue;
+        }
+
+        if end < 24 {
+            return Ok(None);
+        }
+        let mut footer = [0; 12];
+        r.seek(SeekFrom::Start(end - 12))?;
+        r.read_exact(&mut footer)?;
+        if &footer[10..] != FOOTER_MAGIC {
+            return Ok(None);
+        }
+        let index_size =
+            (u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as u64 + 1) * 4;
+        let Some(index_start) = (end - 12).checked_sub(index_size) else {
+            return Ok(None);
+        };
+
+        r.seek(SeekFrom::Start(index_start))?;
+        if read_u8(&mut r)? != 0 {
+            return Ok(None);
+        }
+        let mut blocks_size = 0u64;
+        for _ in 0..read_vli(&mut r)? {
+            let unpadded = read_vli(&mut r)?;
+            total += read_vli(&mut r)?;
+            blocks_size += unpadded.next_multiple_of(4);
+        }
+
+        let Some(stream_start) = index_start
+            .checked_sub(blocks_size)
+            .and_then(|s| s.checked_sub(12))
+        else {
+            return Ok(None);
+        };
+        end = stream_start;
+    }
+    Ok(Some(DecompressedSize::Exact(total)))
+}
+
+/// Each zstd frame may say how big its content is in its header. To find the next
+/// frame, we skip over the blocks that make up this one.
+pub fn zstd(mut r: impl Read + Seek) -> io::Result<Option<DecompressedSize>> {
+    const MAGIC: u32 = 0xFD2FB528;
+
+    let len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let mut total = 0u64;
+    while r.stream_position()? < len {
+        match read_u32_le(&mut r)? {
+            MAGIC => {}
+            m if is_skippable_frame(m) => {
+                let size = read_u32_le(&mut r)?;
+                r.seek(SeekFrom::Current(size as i64))?;
+                continue;
+            }
+            _ => return Ok(None),
+        }
+
+        let descriptor = read_u8(&mut r)?;
+        let fcs_flag = descriptor >> 6;
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let dict_id_len = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let fcs_len = match (fcs_flag, single_segment) {
+            (0, false) => return Ok(None),
+            (0, true) => 1,
+            (1, _) => 2,
+            (2, _) => 4,
+            _ => 8,
+        };
+
+        let window_len = if single_segment { 0 } else { 1 };
+        r.seek(SeekFrom::Current(window_len + dict_id_len))?;
+        let mut fcs = [0; 8];
+        r.read_exact(&mut fcs[..fcs_len])?;
+        total += match fcs_len {
+            // The 2-byte size is stored minus 256, since 1 byte can hold anything smaller
+            2 => u64::from_le_bytes(fcs) + 256,
+            _ => u64::from_le_bytes(fcs),
+        };
+
+        loop {
+            let mut header = [0; 4];
+            r.read_exact(&mut header[..3])?;
+            let header = u32::from_le_bytes(header);
+            let last = header & 1 != 0;
+            let size = match (header >> 1) & 0b11 {
+                // Raw or compressed, so the data is this long
+                0 | 2 => header >> 3,
+                // RLE, so the data is a single byte to repeat
+                1 => 1,
+                _ => return Ok(None),
+            };
+            r.seek(SeekFrom::Current(size as i64))?;
+            if last {
+                break;
+            }
+        }
+        if has_checksum {
+            r.seek(SeekFrom::Current(4))?;
+        }
+    }
+    Ok(Some(DecompressedSize::Exact(total)))
+}
+
+/// lz4 frames may say how big their content is in their header, though most tools
+/// leave it out. To find the next frame, we skip over the blocks that make up this one.
+pub fn lz4(mut r: impl Read + Seek) -> io::Result<Option<DecompressedSize>> {
+    const MAGIC: u32 = 0x184D2204;
+
+    let len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let mut total = 0u64;
+    while r.stream_position()? < len {
+        match read_u32_le(&mut r)? {
+            MAGIC => {}
+            m if is_skippable_frame(m) => {
+                let size = read_u32_le(&mut r)?;
+                r.seek(SeekFrom::Current(size as i64))?;
+                continue;
+            }
+            _ => return Ok(None),
+        }
+
+        let flags = read_u8(&mut r)?;
+        let has_block_checksums = flags & 0x10 != 0;
+        let has_content_size = flags & 0x08 != 0;
+        let has_content_checksum = flags & 0x04 != 0;
+        let has_dict_id = flags & 0x01 != 0;
+        if !has_content_size {
+            return Ok(None);
+        }
+
+        // Skip the block descriptor
+        r.seek(SeekFrom::Current(1))?;
+        let mut content_size = [0; 8];
+        r.read_exact(&mut content_size)?;
+        total += u64::from_le_bytes(content_size);
+        // Skip the dictionary ID and header checksum
+        r.seek(SeekFrom::Current(if has_dict_id { 5 } else { 1 }))?;
+
+        loop {
+            let size = read_u32_le(&mut r)? & 0x7FFFFFFF;
+            if size == 0 {
+                break;
+            }
+            let checksum_len = if has_block_checksums { 4 } else { 0 };
+            r.seek(SeekFrom::Current(size as i64 + checksum_len))?;
+        }
+        if has_content_checksum {
+            r.seek(SeekFrom::Current(4))?;
+        }
+    }
+    Ok(Some(DecompressedSize::Exact(total)))
+}
+
+/// Both zstd and lz4 allow frames of arbitrary data that decompressors skip over.
+fn is_skippable_frame(magic: u32) -> bool {
+    magic & 0xFFFFFFF0 == 0x184D2A50
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32_le(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// xz's variable-length integers, which hold 7 bits per byte, least significant first.
+fn read_vli(r: &mut impl Read) -> io::Result<u64> {
+    let mut n = 0u64;
+    for i in 0..9 {
+        let b = read_u8(r)?;
+        n |= ((b & 0x7F) as u64) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "xz integer is too long",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use rstest::rstest;
+
+    use super::{DecompressedSize, gzip, lz4, xz, zstd};
+
+    fn data(n: usize) -> Vec<u8> {
+        (0..n).map(|i| (i / 1000) as u8).collect()
+    }
+
+    #[rstest]
+    fn reads_gzip_size(#[values(0, 1, 300000)] size: usize) {
+        let mut e = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        e.write_all(&data(size)).unwrap();
+        let compressed = e.finish().unwrap();
+
+        assert_eq!(
+            gzip(Cursor::new(compressed)).unwrap(),
+            Some(DecompressedSize::AtLeast(size as u64))
+        );
+    }
+
+    #[test]
+    fn gzip_size_with_trailing_bytes_is_only_a_hint() {
+        let mut e = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        e.write_all(&data(1000)).unwrap();
+        let mut compressed = e.finish().unwrap();
+        compressed.extend([0xff; 8]);
+
+        let size = gzip(Cursor::new(compressed)).unwrap().unwrap();
+        assert_eq!(size, DecompressedSize::AtLeast(0xffffffff));
+        assert!(!size.exceeds(1000));
+    }
+
+    #[rstest]
+    fn reads_xz_size(#[values(0, 1, 300000)] size: usize) {
+        let mut e = xz2::write::XzEncoder::new(vec![], 6);
+        e.write_all(&data(size)).unwrap();
+        let compressed = e.finish().unwrap();
+
+        assert_eq!(
+            xz(Cursor::new(compressed)).unwrap(),
+            Some(DecompressedSize::Exact(size as u64))
+        );
+    }
+
+    #[test]
+    fn reads_size_of_concatenated_padded_xz_streams() {
+        let mut compressed = vec![];
+        for size in [1000, 20000] {
+            let mut e = xz2::write::XzEncoder::new(vec![], 6);
+            e.write_all(&data(size)).unwrap();
+            compressed.extend(e.finish().unwrap());
+            compressed.extend([0; 8]);
+        }
+
+        assert_eq!(
+            xz(Cursor::new(compressed)).unwrap(),
+            Some(DecompressedSize::Exact(21000))
+        );
+    }
+
+    #[rstest]
+    fn reads_zstd_size(#[values(0, 1, 300, 70000, 300000)] size: usize) {
+        let mut compressed = vec![];
+        // Two frames, like multithreaded compressors write
+        for _ in 0..2 {
+            let mut e = ::zstd::stream::write::Encoder::new(vec![], 3).unwrap();
+            e.include_checksum(true).unwrap();
+            e.set_pledged_src_size(Some(size as u64)).unwrap();
+            e.write_all(&data(size)).unwrap();
+            compressed.extend(e.finish().unwrap());
+        }
+
+        assert_eq!(
+            zstd(Cursor::new(compressed)).unwrap(),
+            Some(DecompressedSize::Exact(2 * size as u64))
+        );
+    }
+
+    #[test]
+    fn zstd_without_content_size_is_unknown() {
+        let mut e = ::zstd::stream::write::Encoder::new(vec![], 3).unwrap();
+        e.write_all(&data(1000)).unwrap();
+        let compressed = e.finish().unwrap();
+
+        assert_eq!(zstd(Cursor::new(compressed)).unwrap(), None);
+    }
+
+    #[rstest]
+    fn reads_lz4_size(#[values(0, 1, 300000)] size: usize, #[values(false, true)] checksums: bool) {
+        let info = lz4_flex::frame::FrameInfo::new()
+            .content_size(Some(size as u64))
+            .block_checksums(checksums)
+            .content_checksum(checksums);
+        let mut e = lz4_flex::frame::FrameEncoder::with_frame_info(info, vec![]);
+        e.write_all(&data(size)).unwrap();
+        let compressed = e.finish().unwrap();
+
+        assert_eq!(
+            lz4(Cursor::new(compressed)).unwrap(),
+            Some(DecompressedSize::Exact(size as u64))
+        );
+    }
+
+    #[test]
+    fn lz4_without_content_size_is_unknown() {
+        let mut e = lz4_flex::frame::FrameEncoder::new(vec![]);
+        e.write_all(&data(1000)).unwrap();
+        let compressed = e.finish().unwrap();
+
+        assert_eq!(lz4(Cursor::new(compressed)).unwrap(), None);
+    }
+}
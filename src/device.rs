@@ -8,15 +8,25 @@ use std::{
 use bytesize::ByteSize;
 use serde::{Deserialize, Serialize};
 
+#[cfg(target_os = "linux")]
+pub mod mounts;
+pub mod selector;
+#[cfg(target_os = "linux")]
+pub mod system;
+pub mod watch;
+
 #[cfg(target_os = "linux")]
 pub fn enumerate_devices() -> impl Iterator<Item = WriteTarget> {
     use std::fs::read_dir;
 
     let paths = read_dir("/sys/class/block").unwrap();
+    // These are the same for every device, so they only need to be read once
+    let mounts = mounts::read_mounts();
+    let swaps = system::read_swaps();
 
     paths
         .filter_map(|r| r.ok())
-        .filter_map(|d| WriteTarget::try_from(d.path().as_ref()).ok())
+        .filter_map(move |d| WriteTarget::from_dev_name(&d.file_name(), &mounts, &swaps).ok())
 }
 
 #[cfg(target_os = "macos")]
@@ -81,9 +91,18 @@ pub fn enumerate_devices() -> impl Iterator<Item = WriteTarget> {
                 devnode,
                 size,
                 model,
+                vendor: None,
+                serial: None,
+                transport: None,
                 removable,
+                rotational: None,
+                read_only: None,
                 target_type,
                 block_size,
+                logical_block_size: BlockSize(None),
+                partitions: vec![],
+                mountpoints: vec![],
+                system_uses: vec![],
             })
         }
 
@@ -95,6 +114,20 @@ pub fn enumerate_devices() -> impl Iterator<Item = WriteTarget> {
     out.into_iter()
 }
 
+/// The devices that can be picked as targets, sorted by their device nodes. Unless
+/// `show_all_disks` is set, only removable ones are included.
+///
+/// Disks that the running system depends on are left out unless `include_system` is
+/// set, which should only be done if they won't be written to.
+pub fn enumerate_targets(show_all_disks: bool, include_system: bool) -> Vec<WriteTarget> {
+    let mut targets: Vec<WriteTarget> = enumerate_devices()
+        .filter(|d| show_all_disks || d.removable == Removable::Yes)
+        .filter(|d| include_system || d.system_uses.is_empty())
+        .collect();
+    targets.sort();
+    targets
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct WriteTarget {
     /// A user-friendly name for the disk (i.e. sda, nvme0n1, disk1s4)
@@ -102,9 +135,36 @@ pub struct WriteTarget {
     pub devnode: PathBuf,
     pub size: TargetSize,
     pub model: Model,
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    /// How the disk is attached to the computer.
+    pub transport: Option<Transport>,
     pub removable: Removable,
+    /// If the disk is a spinning hard drive.
+    pub rotational: Option<bool>,
+    pub read_only: Option<bool>,
     pub target_type: Type,
+    /// The physical block size.
     pub block_size: BlockSize,
+    pub logical_block_size: BlockSize,
+    /// If this is a disk, the partitions on it.
+    pub partitions: Vec<Partition>,
+    /// Where this is mounted, not including any of its partitions.
+    pub mountpoints: Vec<PathBuf>,
+    /// What the running system needs this or any of its partitions for. Burning over
+    /// a disk with any of these would break the system.
+    pub system_uses: Vec<SystemUse>,
+}
+
+/// A partition on a [WriteTarget].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Partition {
+    pub name: String,
+    pub devnode: PathBuf,
+    pub size: TargetSize,
+    /// The label of the filesystem on it, if it has one.
+    pub label: Option<String>,
+    pub mountpoints: Vec<PathBuf>,
 }
 
 impl WriteTarget {
@@ -129,25 +189,14 @@ impl WriteTarget {
         }
     }
 
+    /// `mounts` and `swaps` are what [mounts::read_mounts] and [system::read_swaps] give.
     #[cfg(target_os = "linux")]
-    fn from_dev_name(name: &OsStr) -> Result<Self, DeviceParseError> {
-        use std::fs::read_to_string;
-
-        fn read_sys_file(p: impl AsRef<Path>) -> Result<Option<String>, std::io::Error> {
-            into_none_if_not_exists(read_to_string(p).map(|s| s.trim().to_owned()))
-        }
-
-        fn into_none_if_not_exists<T>(
-            r: Result<T, std::io::Error>,
-        ) -> Result<Option<T>, std::io::Error> {
-            match r {
-                Ok(x) => Ok(Some(x)),
-                Err(e) => match e.kind() {
-                    std::io::ErrorKind::NotFound => Ok(None),
-                    _ => Err(e),
-                },
-            }
-        }
+    fn from_dev_name(
+        name: &OsStr,
+        mounts: &[mounts::Mount],
+        swaps: &[PathBuf],
+    ) -> Result<Self, DeviceParseError> {
+        use self::linux::{read_partitions, read_sys_bool, read_sys_file};
 
         let devnode = Path::new("/dev").join(name);
         if !devnode.exists() {
@@ -155,6 +204,9 @@ impl WriteTarget {
         }
 
         let sysnode = Path::new("/sys/class/block").join(name);
+        // The real location in sysfs says what bus the disk is on. Not every device
+        // node has one, and then we just don't know.
+        let syspath = sysnode.canonicalize().ok();
 
         let removable = match read_sys_file(sysnode.join("removable"))?
             .as_ref()
@@ -170,28 +222,75 @@ impl WriteTarget {
                 .and_then(|s| s.parse::<u64>().ok().map(|n| ByteSize::b(n * 512))),
         );
 
-        let model =
-            Model(read_sys_file(sysnode.join("device/model"))?.map(|m| m.trim().to_owned()));
+        let model = Model(read_sys_file(sysnode.join("device/model"))?);
+
+        // USB sticks usually say who made them in the USB device, rather than the
+        // disk inside of it.
+        let usb_device =
+            (syspath.as_deref()).and_then(|s| s.ancestors().find(|p| p.join("idVendor").exists()));
+
+        // SATA disks are all made by "ATA", according to the SCSI layer, and virtio
+        // disks only have a PCI vendor ID
+        let vendor = match read_sys_file(sysnode.join("device/vendor"))?
+            .filter(|v| v != "ATA" && !v.starts_with("0x"))
+        {
+            Some(v) => Some(v),
+            None => usb_device
+                .map(|p| read_sys_file(p.join("manufacturer")))
+                .transpose()?
+                .flatten(),
+        };
+
+        let serial = match (read_sys_file(sysnode.join("serial"))?)
+            .or(read_sys_file(sysnode.join("device/serial"))?)
+        {
+            Some(s) => Some(s),
+            None => (usb_device.map(|p| read_sys_file(p.join("serial"))))
+                .transpose()?
+                .flatten(),
+        };
 
         let target_type = match sysnode.join("partition").exists() {
             true => Type::Partition,
             false => Type::Disk,
         };
 
-        let block_size = BlockSize(
-            read_sys_file(sysnode.join("queue/physical_block_size"))?
-                .and_then(|s| s.parse::<u64>().ok())
-                .map(ByteSize::b),
+        let read_block_size = |file: &str| -> Result<BlockSize, io::Error> {
+            Ok(BlockSize(
+                read_sys_file(sysnode.join("queue").join(file))?
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .map(ByteSize::b),
+            ))
+        };
+
+        let dev = read_sys_file(sysnode.join("dev"))?;
+        let partitions = match target_type {
+            Type::Disk => read_partitions(&sysnode, mounts)?,
+            _ => vec![],
+        };
+        let system_uses = system::system_uses(
+            std::iter::once(name).chain(partitions.iter().map(|p| OsStr::new(&p.name))),
+            mounts,
+            swaps,
         );
 
         Ok(Self {
             name: name.to_string_lossy().into(),
-            devnode,
             size,
             removable,
             model,
+            vendor,
+            serial,
+            transport: syspath.as_deref().and_then(Transport::from_sys_path),
+            rotational: read_sys_bool(sysnode.join("queue/rotational"))?,
+            read_only: read_sys_bool(sysnode.join("ro"))?,
             target_type,
-            block_size,
+            block_size: read_block_size("physical_block_size")?,
+            logical_block_size: read_block_size("logical_block_size")?,
+            partitions,
+            mountpoints: mounts::mountpoints_of(mounts, dev.as_deref(), &devnode),
+            system_uses,
+            devnode,
         })
     }
 
@@ -201,13 +300,118 @@ impl WriteTarget {
             devnode: path.into(),
             size: TargetSize(None),
             model: Model(None),
+            vendor: None,
+            serial: None,
+            transport: None,
             removable: Removable::Unknown,
+            rotational: None,
+            read_only: None,
             target_type: Type::File,
             block_size: BlockSize(None),
+            logical_block_size: BlockSize(None),
+            partitions: vec![],
+            mountpoints: vec![],
+            system_uses: vec![],
         })
     }
 }
 
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{fs::read_to_string, io, path::Path};
+
+    use super::{Partition, TargetSize, mounts};
+    use bytesize::ByteSize;
+
+    /// Read a sysfs attribute, or None if it doesn't exist or is blank.
+    pub fn read_sys_file(p: impl AsRef<Path>) -> Result<Option<String>, io::Error> {
+        match read_to_string(p) {
+            Ok(s) => Ok(Some(s.trim().to_owned()).filter(|s| !s.is_empty())),
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    pub fn read_sys_bool(p: impl AsRef<Path>) -> Result<Option<bool>, io::Error> {
+        Ok(match read_sys_file(p)?.as_deref() {
+            Some("0") => Some(false),
+            Some("1") => Some(true),
+            _ => None,
+        })
+    }
+
+    /// Look up something that udev found out about a device, i.e. `ID_FS_LABEL`, given
+    /// its `major:minor` device number.
+    pub fn read_udev_property(dev: &str, key: &str) -> Option<String> {
+        let data = read_to_string(Path::new("/run/udev/data").join(format!("b{dev}"))).ok()?;
+        data.lines()
+            .filter_map(|l| l.strip_prefix("E:")?.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.to_owned())
+    }
+
+    /// Read the partitions of the disk at `sysnode`, in order.
+    pub fn read_partitions(
+        sysnode: &Path,
+        mounts: &[mounts::Mount],
+    ) -> Result<Vec<Partition>, io::Error> {
+        let entries = match sysnode.read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut partitions = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let Some(number) = read_sys_file(path.join("partition"))? else {
+                continue;
+            };
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+
+            let devnode = Path::new("/dev").join(name);
+            let dev = read_sys_file(path.join("dev"))?;
+            let partition = Partition {
+                name: name.to_string_lossy().into(),
+                size: TargetSize(
+                    read_sys_file(path.join("size"))?
+                        .and_then(|s| s.parse::<u64>().ok().map(|n| ByteSize::b(n * 512))),
+                ),
+                label: (dev.as_deref()).and_then(|d| read_udev_property(d, "ID_FS_LABEL")),
+                mountpoints: mounts::mountpoints_of(mounts, dev.as_deref(), &devnode),
+                devnode,
+            };
+            partitions.push((number.parse::<u64>().unwrap_or(u64::MAX), partition));
+        }
+        partitions.sort_by_key(|(n, _)| *n);
+        Ok(partitions.into_iter().map(|(_, p)| p).collect())
+    }
+}
+
+impl WriteTarget {
+    /// Who made it and what it is, as well as we know.
+    pub fn description(&self) -> String {
+        match (&self.vendor, &self.model.0) {
+            (Some(v), Some(m)) => format!("{v} {m}"),
+            (Some(v), None) => v.clone(),
+            (None, _) => self.model.to_string(),
+        }
+    }
+
+    /// Everywhere this or any of its partitions are mounted.
+    pub fn all_mountpoints(&self) -> impl Iterator<Item = &Path> {
+        (self.mountpoints.iter())
+            .chain(self.partitions.iter().flat_map(|p| &p.mountpoints))
+            .map(PathBuf::as_path)
+    }
+}
+
 impl PartialOrd for WriteTarget {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -225,10 +429,10 @@ impl TryFrom<&Path> for WriteTarget {
 
     fn try_from(value: &Path) -> Result<Self, Self::Error> {
         #[cfg(target_os = "linux")]
-        if value.starts_with("/sys/class/block") || value.starts_with("/dev") {
-            if let Some(n) = value.file_name() {
-                return Self::from_dev_name(n);
-            }
+        if (value.starts_with("/sys/class/block") || value.starts_with("/dev"))
+            && let Some(n) = value.file_name()
+        {
+            return Self::from_dev_name(n, &mounts::read_mounts(), &system::read_swaps());
         }
 
         #[cfg(target_os = "macos")]
@@ -252,7 +456,7 @@ pub enum DeviceParseError {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
-pub struct Model(Option<String>);
+pub struct Model(pub Option<String>);
 
 impl Display for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -264,7 +468,7 @@ impl Display for Model {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
-pub struct TargetSize(Option<ByteSize>);
+pub struct TargetSize(pub Option<ByteSize>);
 
 impl Display for TargetSize {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -327,6 +531,85 @@ impl Display for Type {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transport {
+    Usb,
+    Sata,
+    Nvme,
+    Mmc,
+    Scsi,
+    Virtio,
+}
+
+impl Transport {
+    /// Work out how a disk is attached, from where it is in `/sys/devices`.
+    pub fn from_sys_path(path: &Path) -> Option<Self> {
+        let components: Vec<_> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        let any = |prefix: &str| components.iter().any(|c| c.starts_with(prefix));
+
+        // USB and SATA disks are both SCSI disks too, so check for those first
+        if any("usb") {
+            Some(Transport::Usb)
+        } else if any("nvme") {
+            Some(Transport::Nvme)
+        } else if any("mmc") {
+            Some(Transport::Mmc)
+        } else if any("ata") {
+            Some(Transport::Sata)
+        } else if any("virtio") {
+            Some(Transport::Virtio)
+        } else if any("target") {
+            Some(Transport::Scsi)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Transport::Usb => "usb",
+                Transport::Sata => "sata",
+                Transport::Nvme => "nvme",
+                Transport::Mmc => "mmc",
+                Transport::Scsi => "scsi",
+                Transport::Virtio => "virtio",
+            }
+        )
+    }
+}
+
+/// Something the running system needs a disk for.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SystemUse {
+    /// It has the root filesystem on it.
+    Root,
+    /// It has `/boot` or the EFI system partition on it.
+    Boot,
+    Swap,
+    /// It is part of the named device, i.e. an LVM volume, a RAID array, or an
+    /// encrypted volume.
+    Holder(String),
+}
+
+impl Display for SystemUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SystemUse::Root => write!(f, "root filesystem"),
+            SystemUse::Boot => write!(f, "boot filesystem"),
+            SystemUse::Swap => write!(f, "swap"),
+            SystemUse::Holder(name) => write!(f, "part of {name}"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
 pub struct BlockSize(pub Option<ByteSize>);
 
@@ -338,3 +621,32 @@ impl Display for BlockSize {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use test_case::test_case;
+
+    use super::Transport;
+
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb"
+        => Some(Transport::Usb); "usb stick")]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:17.0/ata3/host2/target2:0:0/2:0:0:0/block/sda"
+        => Some(Transport::Sata); "sata disk")]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:1d.0/0000:03:00.0/nvme/nvme0/nvme0n1"
+        => Some(Transport::Nvme); "nvme disk")]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:14.5/mmc_host/mmc0/mmc0:aaaa/block/mmcblk0"
+        => Some(Transport::Mmc); "sd card")]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:02.0/virtio1/block/vda"
+        => Some(Transport::Virtio); "virtio disk")]
+    #[test_case("/sys/devices/virtual/block/loop0" => None; "loop device")]
+    fn transport_from_sys_path(path: &str) -> Option<Transport> {
+        Transport::from_sys_path(Path::new(path))
+    }
+}
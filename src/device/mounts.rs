@@ -0,0 +1,122 @@
+//! Finding out where block devices are mounted, from `/proc/self/mountinfo`.
+
+use std::{
+    ffi::OsString,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+/// A single mounted filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// The `major:minor` device number of what is mounted.
+    pub dev: String,
+    /// What was mounted, i.e. `/dev/sda1`.
+    pub source: PathBuf,
+    pub mountpoint: PathBuf,
+}
+
+/// Everything that is currently mounted. If this can't be read, nothing is.
+pub fn read_mounts() -> Vec<Mount> {
+    match std::fs::read_to_string("/proc/self/mountinfo") {
+        Ok(s) => parse_mountinfo(&s),
+        Err(error) => {
+            warn!(?error, "Failed to read mountinfo");
+            vec![]
+        }
+    }
+}
+
+/// Where the device with the given `major:minor` number and device node is mounted.
+pub fn mountpoints_of(mounts: &[Mount], dev: Option<&str>, devnode: &Path) -> Vec<PathBuf> {
+    mounts
+        .iter()
+        // Some filesystems, like btrfs, report a device number that isn't the disk's
+        .filter(|m| Some(m.dev.as_str()) == dev || m.source == devnode)
+        .map(|m| m.mountpoint.clone())
+        .collect()
+}
+
+fn parse_mountinfo(s: &str) -> Vec<Mount> {
+    s.lines().filter_map(parse_line).collect()
+}
+
+/// See proc(5) for the format. Briefly:
+///
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_line(line: &str) -> Option<Mount> {
+    let (before, after) = line.split_once(" - ")?;
+    let mut before = before.split(' ');
+    let dev = before.nth(2)?;
+    let mountpoint = before.nth(1)?;
+    let source = after.split(' ').nth(1)?;
+
+    Some(Mount {
+        dev: dev.to_owned(),
+        source: unescape(source),
+        mountpoint: unescape(mountpoint),
+    })
+}
+
+/// Undo the octal escapes used for spaces and such, i.e. `\040`.
+pub(super) fn unescape(s: &str) -> PathBuf {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.as_bytes();
+    while let Some((&b, rest)) = bytes.split_first() {
+        let escaped = (b == b'\\')
+            .then(|| rest.get(..3))
+            .flatten()
+            .and_then(|oct| u8::from_str_radix(std::str::from_utf8(oct).ok()?, 8).ok());
+        match escaped {
+            Some(c) => {
+                out.push(c);
+                bytes = &rest[3..];
+            }
+            None => {
+                out.push(b);
+                bytes = rest;
+            }
+        }
+    }
+    OsString::from_vec(out).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{mountpoints_of, parse_mountinfo};
+
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+23 22 0:21 / /proc rw,nosuid shared:5 - proc proc rw
+90 22 8:1 / /run/media/user/MY\\040STICK rw,nosuid shared:50 - vfat /dev/sda1 rw,fmask=0022
+91 22 0:45 /@home /home rw,relatime shared:30 - btrfs /dev/sdb2 rw,subvol=/@home
+";
+
+    #[test]
+    fn finds_mountpoints_by_device_number() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+
+        assert_eq!(
+            mountpoints_of(&mounts, Some("8:1"), Path::new("/dev/sda1")),
+            vec![PathBuf::from("/run/media/user/MY STICK")]
+        );
+        assert_eq!(
+            mountpoints_of(&mounts, Some("8:2"), Path::new("/dev/sda2")),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn finds_mountpoints_by_source() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+
+        assert_eq!(
+            mountpoints_of(&mounts, Some("8:18"), Path::new("/dev/sdb2")),
+            vec![PathBuf::from("/home")]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Picking a disk by something that stays the same between boots and replugs, unlike
+//! `/dev/sdX` names.
+
+use std::{
+    fmt::Display,
+    fs::read_dir,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use regex_lite::Regex;
+
+use super::{DeviceParseError, Type, WriteTarget, enumerate_devices, watch::TargetFilter};
+
+/// Which disk to use, as given on the command line.
+#[derive(Debug, Clone)]
+pub enum TargetSelector {
+    /// A device node or a normal file, i.e. `/dev/sda` or `disk.img`.
+    Path(PathBuf),
+    /// `serial:4C530001`
+    Serial(String),
+    /// `model:Ultra Fit`, which has to be the whole model.
+    Model(String),
+    /// `model:~^SanDisk`, which is matched against the vendor and model.
+    ModelRegex(Regex),
+    /// `by-id:usb-SanDisk_Ultra_Fit_4C530001-0:0`, a name in `/dev/disk/by-id`.
+    ById(String),
+    /// `by-path:pci-0000:00:14.0-usb-0:1:1.0-scsi-0:0:0:0`, a name in
+    /// `/dev/disk/by-path`.
+    ByPath(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SelectorError {
+    #[error("No disk matches {0}")]
+    NoMatch(String),
+    #[error("More than one disk matches {selector}: {}", matches.join(", "))]
+    MultipleMatches {
+        selector: String,
+        matches: Vec<String>,
+    },
+    #[error(transparent)]
+    Device(#[from] DeviceParseError),
+}
+
+impl TargetSelector {
+    /// Find the one disk this selects.
+    pub fn resolve(&self) -> Result<WriteTarget, SelectorError> {
+        self.resolve_in(Path::new("/dev/disk"))
+    }
+
+    /// Like [Self::resolve], but with the `by-*` links in `links_dir` rather than
+    /// `/dev/disk`.
+    fn resolve_in(&self, links_dir: &Path) -> Result<WriteTarget, SelectorError> {
+        let disks = || enumerate_devices().filter(|t| t.target_type == Type::Disk);
+        let matches: Vec<WriteTarget> = match self {
+            TargetSelector::Path(p) => return Ok(WriteTarget::try_from(p.as_path())?),
+            TargetSelector::Serial(s) => disks().filter(|t| t.serial.as_ref() == Some(s)).collect(),
+            TargetSelector::Model(m) => disks().filter(|t| t.model.0.as_ref() == Some(m)).collect(),
+            TargetSelector::ModelRegex(r) => {
+                let filter = TargetFilter {
+                    model: Some(r.clone()),
+                    ..Default::default()
+                };
+                disks().filter(|t| filter.matches(t)).collect()
+            }
+            TargetSelector::ById(name) => linked_targets(&links_dir.join("by-id"), name)?,
+            TargetSelector::ByPath(name) => linked_targets(&links_dir.join("by-path"), name)?,
+        };
+
+        match <[_; 1]>::try_from(matches) {
+            Ok([target]) => Ok(target),
+            Err(matches) if matches.is_empty() => Err(SelectorError::NoMatch(self.to_string())),
+            Err(matches) => Err(SelectorError::MultipleMatches {
+                selector: self.to_string(),
+                matches: (matches.iter())
+                    .map(|t| t.devnode.to_string_lossy().into_owned())
+                    .collect(),
+            }),
+        }
+    }
+}
+
+/// The disks that the links in `dir` named `name` point to. If there is no link with
+/// exactly that name, links starting with it are used instead, not counting ones to
+/// partitions.
+fn linked_targets(dir: &Path, name: &str) -> Result<Vec<WriteTarget>, DeviceParseError> {
+    let exact = dir.join(name);
+    let links = if exact.exists() {
+        vec![exact]
+    } else {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            // Not Linux, or no disks of that kind at all
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        (entries.filter_map(Result::ok))
+            .filter(|e| {
+                let link = e.file_name();
+                let link = link.to_string_lossy();
+                link.starts_with(name) && !is_partition_link(&link)
+            })
+            .map(|e| e.path())
+            .collect()
+    };
+
+    // Several links usually point at the same disk
+    let mut devnodes = (links.iter())
+        .map(|l| l.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    devnodes.sort();
+    devnodes.dedup();
+    devnodes
+        .iter()
+        .map(|d| WriteTarget::try_from(d.as_path()))
+        .collect()
+}
+
+/// If a link in `/dev/disk/by-*` is to a partition, i.e. `usb-SanDisk_...-0:0-part1`.
+fn is_partition_link(name: &str) -> bool {
+    name.rsplit_once("-part")
+        .is_some_and(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+impl FromStr for TargetSelector {
+    type Err = regex_lite::Error;
+
+    /// Anything without one of the known prefixes is a path. To use a file that is
+    /// named like a selector, prefix it with `./`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, value)) = s.split_once(':') else {
+            return Ok(TargetSelector::Path(s.into()));
+        };
+        Ok(match kind {
+            "serial" => TargetSelector::Serial(value.into()),
+            "model" => match value.strip_prefix('~') {
+                Some(regex) => TargetSelector::ModelRegex(Regex::new(regex)?),
+                None => TargetSelector::Model(value.into()),
+            },
+            "by-id" => TargetSelector::ById(value.into()),
+            "by-path" => TargetSelector::ByPath(value.into()),
+            _ => TargetSelector::Path(s.into()),
+        })
+    }
+}
+
+impl Display for TargetSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetSelector::Path(p) => write!(f, "{}", p.display()),
+            TargetSelector::Serial(s) => write!(f, "serial:{s}"),
+            TargetSelector::Model(m) => write!(f, "model:{m}"),
+            TargetSelector::ModelRegex(r) => write!(f, "model:~{}", r.as_str()),
+            TargetSelector::ById(n) => write!(f, "by-id:{n}"),
+            TargetSelector::ByPath(n) => write!(f, "by-path:{n}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+    };
+
+    use test_case::test_case;
+
+    use super::{SelectorError, TargetSelector, is_partition_link};
+
+    /// Make a directory like `/dev/disk`, with `by-id` links to two fake disks and a
+    /// partition on the first one. `by-path` is left out.
+    fn fake_links_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "caligula-selector-test-{}-{}",
+            std::process::id(),
+            name.replace([':', '/'], "_")
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let by_id = dir.join("by-id");
+        fs::create_dir_all(&by_id).unwrap();
+        for (link, disk) in [
+            ("usb-SanDisk_Ultra_Fit_4C530001-0:0", "sda"),
+            ("usb-SanDisk_Ultra_Fit_4C530001-0:0-part1", "sda1"),
+            ("wwn-0x5002538e40a1b2c3", "sda"),
+            ("usb-Kingston_DataTraveler_3.0-0:0", "sdb"),
+        ] {
+            fs::write(dir.join(disk), b"").unwrap();
+            symlink(dir.join(disk), by_id.join(link)).unwrap();
+        }
+        dir
+    }
+
+    fn resolve(dir: &Path, selector: &str) -> Result<PathBuf, SelectorError> {
+        let target = selector
+            .parse::<TargetSelector>()
+            .unwrap()
+            .resolve_in(dir)?;
+        Ok(target.devnode)
+    }
+
+    #[test_case("by-id:usb-SanDisk_Ultra_Fit_4C530001-0:0" => "sda"; "exact")]
+    #[test_case("by-id:usb-SanDisk" => "sda"; "prefix, skipping partitions")]
+    #[test_case("by-id:usb-SanDisk_Ultra_Fit_4C530001-0:0-part1" => "sda1"; "exact partition")]
+    #[test_case("by-id:wwn-" => "sda"; "other link to the same disk")]
+    fn resolves_links(selector: &str) -> String {
+        let dir = fake_links_dir(selector);
+        let devnode = resolve(&dir, selector).unwrap();
+        let name = devnode
+            .strip_prefix(&dir)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        fs::remove_dir_all(dir).unwrap();
+        name
+    }
+
+    #[test_case("by-id:nvme-"; "no link")]
+    #[test_case("by-path:pci-"; "no by-path directory")]
+    fn fails_if_no_links_match(selector: &str) {
+        let dir = fake_links_dir(selector);
+        let result = resolve(&dir, selector);
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(result, Err(SelectorError::NoMatch(s)) if s == selector));
+    }
+
+    #[test]
+    fn fails_if_several_disks_match() {
+        let dir = fake_links_dir("several");
+        let result = resolve(&dir, "by-id:usb-");
+        let expected = [dir.join("sda"), dir.join("sdb")].map(|p| p.to_string_lossy().into_owned());
+        fs::remove_dir_all(dir).unwrap();
+        match result {
+            Err(SelectorError::MultipleMatches { selector, matches }) => {
+                assert_eq!(selector, "by-id:usb-");
+                assert_eq!(matches, expected);
+            }
+            other => panic!("expected several matches, got {other:?}"),
+        }
+    }
+
+    #[test_case("/dev/sda"; "device node")]
+    #[test_case("disk.img"; "file")]
+    #[test_case("./serial:4C530001"; "file named like a selector")]
+    #[test_case("C:stuff"; "unknown prefix")]
+    #[test_case("serial:4C530001")]
+    #[test_case("model:Ultra Fit")]
+    #[test_case("model:~^SanDisk (Ultra|Extreme)")]
+    #[test_case("by-id:usb-SanDisk_Ultra_Fit_4C530001-0:0")]
+    #[test_case("by-path:pci-0000:00:14.0-usb-0:1:1.0-scsi-0:0:0:0")]
+    fn selectors_round_trip(s: &str) {
+        assert_eq!(s.parse::<TargetSelector>().unwrap().to_string(), s);
+    }
+
+    #[test_case("/dev/sda" => "Path"; "device node")]
+    #[test_case("./serial:4C530001" => "Path"; "file named like a selector")]
+    #[test_case("serial:4C530001" => "Serial")]
+    #[test_case("model:Ultra Fit" => "Model")]
+    #[test_case("model:~Ultra" => "ModelRegex")]
+    #[test_case("by-id:usb-SanDisk" => "ById")]
+    #[test_case("by-path:pci-0000:00:14.0" => "ByPath")]
+    fn parses_selector_kinds(s: &str) -> &'static str {
+        match s.parse::<TargetSelector>().unwrap() {
+            TargetSelector::Path(_) => "Path",
+            TargetSelector::Serial(_) => "Serial",
+            TargetSelector::Model(_) => "Model",
+            TargetSelector::ModelRegex(_) => "ModelRegex",
+            TargetSelector::ById(_) => "ById",
+            TargetSelector::ByPath(_) => "ByPath",
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_model_regexes() {
+        assert!("model:~(".parse::<TargetSelector>().is_err());
+    }
+
+    #[test_case("usb-SanDisk_Ultra_Fit_4C530001-0:0-part1" => true)]
+    #[test_case("nvme-eui.0025388b91b1a2b3-part12" => true)]
+    #[test_case("usb-SanDisk_Ultra_Fit_4C530001-0:0" => false)]
+    #[test_case("wwn-0x5002538e40a1b2c3" => false)]
+    #[test_case("usb-Some_party_Disk-part" => false)]
+    fn partition_links(name: &str) -> bool {
+        is_partition_link(name)
+    }
+}
@@ -0,0 +1,135 @@
+//! Finding out which disks the running system depends on, so that they aren't burned
+//! over by accident.
+
+use std::{
+    ffi::OsStr,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use super::{
+    SystemUse,
+    linux::read_sys_file,
+    mounts::{self, Mount},
+};
+
+/// Everything that is being used as swap. If this can't be read, nothing is.
+pub fn read_swaps() -> Vec<PathBuf> {
+    match std::fs::read_to_string("/proc/swaps") {
+        Ok(s) => parse_swaps(&s),
+        Err(error) => {
+            warn!(?error, "Failed to read swaps");
+            vec![]
+        }
+    }
+}
+
+/// How the system uses the block devices named `names` (i.e. `sda` and its partitions),
+/// including through anything built on top of them, like LVM volumes, RAID arrays or
+/// encrypted volumes.
+pub fn system_uses<'a>(
+    names: impl IntoIterator<Item = &'a OsStr>,
+    mounts: &[Mount],
+    swaps: &[PathBuf],
+) -> Vec<SystemUse> {
+    let mut uses = vec![];
+    for name in names {
+        add_system_uses(name, mounts, swaps, &mut uses);
+    }
+    uses
+}
+
+fn add_system_uses(name: &OsStr, mounts: &[Mount], swaps: &[PathBuf], uses: &mut Vec<SystemUse>) {
+    let sysnode = Path::new("/sys/class/block").join(name);
+    let devnode = Path::new("/dev").join(name);
+    let dev = read_sys_file(sysnode.join("dev")).ok().flatten();
+    for mountpoint in mounts::mountpoints_of(mounts, dev.as_deref(), &devnode) {
+        if let Some(u) = mountpoint_use(&mountpoint) {
+            add(uses, u);
+        }
+    }
+    if swaps.contains(&devnode) {
+        add(uses, SystemUse::Swap);
+    }
+
+    // Whatever is built on top of this device, i.e. dm-0 for an LVM volume
+    let Ok(holders) = read_dir(sysnode.join("holders")) else {
+        return;
+    };
+    for holder in holders.filter_map(Result::ok) {
+        let holder = holder.file_name();
+        add(uses, SystemUse::Holder(holder.to_string_lossy().into()));
+        add_system_uses(&holder, mounts, swaps, uses);
+    }
+}
+
+fn add(uses: &mut Vec<SystemUse>, u: SystemUse) {
+    if !uses.contains(&u) {
+        uses.push(u);
+    }
+}
+
+/// What the system uses the filesystem mounted at `mountpoint` for, if it can't do
+/// without it.
+fn mountpoint_use(mountpoint: &Path) -> Option<SystemUse> {
+    if mountpoint == Path::new("/") {
+        Some(SystemUse::Root)
+    } else if mountpoint.starts_with("/boot") || mountpoint.starts_with("/efi") {
+        Some(SystemUse::Boot)
+    } else {
+        None
+    }
+}
+
+/// See proc(5) for the format. Briefly:
+///
+/// ```text
+/// Filename      Type       Size     Used  Priority
+/// /dev/dm-1     partition  8388604  0     -2
+/// ```
+fn parse_swaps(s: &str) -> Vec<PathBuf> {
+    (s.lines().skip(1))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(mounts::unescape)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use test_case::test_case;
+
+    use crate::device::SystemUse;
+
+    use super::{mountpoint_use, parse_swaps};
+
+    #[test_case("/" => Some(SystemUse::Root))]
+    #[test_case("/boot" => Some(SystemUse::Boot))]
+    #[test_case("/boot/efi" => Some(SystemUse::Boot))]
+    #[test_case("/efi" => Some(SystemUse::Boot))]
+    #[test_case("/bootstrap" => None)]
+    #[test_case("/run/media/user/STICK" => None)]
+    fn mountpoint_uses(mountpoint: &str) -> Option<SystemUse> {
+        mountpoint_use(Path::new(mountpoint))
+    }
+
+    #[test]
+    fn parses_swaps() {
+        let swaps = "\
+Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/dm-1                               partition\t8388604\t\t0\t\t-2
+/var/lib/swap\\040file                   file\t\t1048572\t\t0\t\t-3
+";
+
+        assert_eq!(
+            parse_swaps(swaps),
+            vec![
+                PathBuf::from("/dev/dm-1"),
+                PathBuf::from("/var/lib/swap file")
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Noticing disks being plugged in and removed, by looking at what's there every so
+//! often.
+
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use bytesize::ByteSize;
+use regex_lite::Regex;
+
+use super::WriteTarget;
+
+/// How often to look for disks that have been plugged in or removed.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keeps track of which disks are present, to find out which ones are new.
+///
+/// A disk only counts as present if there is media in it, so that putting an SD card
+/// into a reader that was already plugged in counts as a new disk.
+#[derive(Debug, Default)]
+pub struct NewTargets {
+    present: HashSet<PathBuf>,
+}
+
+impl NewTargets {
+    /// Start watching, treating `targets` as already present.
+    pub fn new(targets: &[WriteTarget]) -> Self {
+        let mut new_targets = Self::default();
+        new_targets.update(targets.to_vec());
+        new_targets
+    }
+
+    /// Of `targets`, which are what is present now, the ones that weren't the last time
+    /// this was called. Disks that were removed are forgotten, so plugging them back in
+    /// counts as new.
+    pub fn update(&mut self, targets: Vec<WriteTarget>) -> Vec<WriteTarget> {
+        let targets = (targets.into_iter())
+            .filter(|t| t.size.0.is_some_and(|s| s.as_u64() > 0))
+            .collect::<Vec<_>>();
+        let previous = std::mem::replace(
+            &mut self.present,
+            targets.iter().map(|t| t.devnode.clone()).collect(),
+        );
+        (targets.into_iter())
+            .filter(|t| !previous.contains(&t.devnode))
+            .collect()
+    }
+}
+
+/// What a disk has to be like for us to pick it without asking.
+#[derive(Debug, Clone, Default)]
+pub struct TargetFilter {
+    pub min_size: Option<ByteSize>,
+    pub max_size: Option<ByteSize>,
+    /// Matched against the vendor and model, separated by a space.
+    pub model: Option<Regex>,
+}
+
+impl TargetFilter {
+    pub fn matches(&self, target: &WriteTarget) -> bool {
+        // A disk of unknown size is never known to be in range
+        let size = target.size.0;
+        let big_enough = (self.min_size).is_none_or(|min| size.is_some_and(|s| s >= min));
+        let small_enough = (self.max_size).is_none_or(|max| size.is_some_and(|s| s <= max));
+        let model_matches = self.model.as_ref().is_none_or(|model| {
+            let name = (target.vendor.iter())
+                .chain(target.model.0.iter())
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+            model.is_match(&name)
+        });
+        big_enough && small_enough && model_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytesize::ByteSize;
+    use regex_lite::Regex;
+    use test_case::test_case;
+
+    use crate::device::{BlockSize, Model, Removable, TargetSize, Type, WriteTarget};
+
+    use super::{NewTargets, TargetFilter};
+
+    fn target(name: &str, size: u64, model: Option<&str>) -> WriteTarget {
+        WriteTarget {
+            name: name.into(),
+            devnode: format!("/dev/{name}").into(),
+            size: TargetSize(Some(ByteSize::b(size))),
+            model: Model(model.map(Into::into)),
+            vendor: Some("SanDisk".into()),
+            serial: None,
+            transport: None,
+            removable: Removable::Yes,
+            rotational: None,
+            read_only: None,
+            target_type: Type::Disk,
+            block_size: BlockSize(None),
+            logical_block_size: BlockSize(None),
+            partitions: vec![],
+            mountpoints: vec![],
+            system_uses: vec![],
+        }
+    }
+
+    fn names(targets: Vec<WriteTarget>) -> Vec<String> {
+        targets.into_iter().map(|t| t.name).collect()
+    }
+
+    #[test]
+    fn finds_disks_plugged_in_after_starting() {
+        let mut new_targets = NewTargets::new(&[target("sda", 1000, None)]);
+
+        assert_eq!(
+            names(new_targets.update(vec![target("sda", 1000, None)])),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            names(new_targets.update(vec![target("sda", 1000, None), target("sdb", 1000, None)])),
+            vec!["sdb"]
+        );
+        assert_eq!(
+            names(new_targets.update(vec![target("sda", 1000, None), target("sdb", 1000, None)])),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn replugged_disks_are_new() {
+        let mut new_targets = NewTargets::new(&[target("sda", 1000, None)]);
+
+        new_targets.update(vec![]);
+
+        assert_eq!(
+            names(new_targets.update(vec![target("sda", 1000, None)])),
+            vec!["sda"]
+        );
+    }
+
+    #[test]
+    fn inserted_media_is_new() {
+        let mut new_targets = NewTargets::new(&[target("sda", 0, None)]);
+
+        assert_eq!(
+            names(new_targets.update(vec![target("sda", 1000, None)])),
+            vec!["sda"]
+        );
+    }
+
+    #[test_case(TargetFilter::default() => true; "no filter")]
+    #[test_case(TargetFilter { min_size: Some(ByteSize::gb(8)), ..Default::default() } => true; "big enough")]
+    #[test_case(TargetFilter { min_size: Some(ByteSize::gb(32)), ..Default::default() } => false; "too small")]
+    #[test_case(TargetFilter { max_size: Some(ByteSize::gb(32)), ..Default::default() } => true; "small enough")]
+    #[test_case(TargetFilter { max_size: Some(ByteSize::gb(8)), ..Default::default() } => false; "too big")]
+    #[test_case(
+        TargetFilter { model: Some(Regex::new("(?i)ultra").unwrap()), ..Default::default() } => true;
+        "model matches")]
+    #[test_case(
+        TargetFilter { model: Some(Regex::new("^SanDisk Ultra").unwrap()), ..Default::default() } => true;
+        "vendor and model match")]
+    #[test_case(
+        TargetFilter { model: Some(Regex::new("Kingston").unwrap()), ..Default::default() } => false;
+        "model doesn't match")]
+    fn filter_matches(filter: TargetFilter) -> bool {
+        filter.matches(&target("sda", 16_000_000_000, Some("Ultra Fit")))
+    }
+}
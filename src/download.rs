@@ -0,0 +1,135 @@
+//! Fetching images and hash files over HTTP(S).
+
+use std::{io::Read, time::Duration};
+
+use anyhow::Context;
+use tracing::debug;
+
+/// How long to wait for the server to send anything before giving up. Applies to
+/// every read of the body, not just the start.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A download that has been started, but not read yet.
+pub struct Download {
+    /// How big the body is, if the server told us.
+    pub size: Option<u64>,
+    pub body: Box<dyn Read + Send + Sync>,
+}
+
+/// If `s` looks like something we can download.
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Start downloading `url`. Fails if the server doesn't respond with a success.
+#[tracing::instrument]
+pub fn get(url: &str) -> anyhow::Result<Download> {
+    let response = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(30))
+        .timeout_read(READ_TIMEOUT)
+        .build()
+        .get(url)
+        .call()
+        .with_context(|| format!("Failed to download {url}"))?;
+    let size = response
+        .header("Content-Length")
+        .and_then(|l| l.parse().ok());
+    debug!(?size, "Started download");
+
+    Ok(Download {
+        size,
+        body: response.into_reader(),
+    })
+}
+
+/// Split `url` into the URL of the directory it's in, and the name of the file it points
+/// to, ignoring any query or fragment.
+pub fn split_file_name(url: &str) -> Option<(&str, &str)> {
+    let url = url.split(['?', '#']).next()?;
+    let (_, path) = url.split_once("://")?;
+    if !path.contains('/') {
+        return None;
+    }
+    let (dir, name) = url.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((dir, name))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use test_case::test_case;
+
+    use super::{get, split_file_name};
+
+    /// Serve `files` over HTTP on localhost in the background, and return the base URL.
+    /// Paths that aren't in `files` get a 404.
+    pub(crate) fn serve(files: HashMap<&'static str, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Skip the rest of the headers
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = match files.get(path) {
+                    Some(body) => ("200 OK", &body[..]),
+                    None => ("404 Not Found", &b"not found"[..]),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(body);
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn downloads_file_with_size() {
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let base = serve(HashMap::from([("/images/disk.img", data.clone())]));
+
+        let mut download = get(&format!("{base}/images/disk.img")).unwrap();
+        let mut body = vec![];
+        download.body.read_to_end(&mut body).unwrap();
+
+        assert_eq!(download.size, Some(data.len() as u64));
+        assert_eq!(body, data);
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let base = serve(HashMap::new());
+
+        assert!(get(&format!("{base}/nothing.img")).is_err());
+    }
+
+    #[test_case("https://example.com/a/b.img", Some(("https://example.com/a", "b.img")))]
+    #[test_case("https://example.com/b.img?x=/y#z", Some(("https://example.com", "b.img")); "query")]
+    #[test_case("https://example.com/a/", None; "directory")]
+    #[test_case("https://example.com", None; "no path")]
+    fn splits_file_name(url: &str, expected: Option<(&str, &str)>) {
+        assert_eq!(split_file_name(url), expected);
+    }
+}
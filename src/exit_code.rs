@@ -0,0 +1,207 @@
+//! The exit codes caligula finishes with, so that scripts can tell what went wrong.
+//!
+//! These are documented in the README, and scripts depend on them, so existing codes
+//! must never be renumbered.
+
+use inquire::InquireError;
+
+use crate::{
+    herder_daemon::ipc::{WriteVerifyError, WriteVerifyEvent},
+    herder_facade::{DaemonError, StartWriterError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Something went wrong that doesn't fit into any other category.
+    Other = 1,
+    /// The command line arguments were invalid, or we needed to ask something but
+    /// weren't running interactively. This matches what clap exits with.
+    Usage = 2,
+    /// The user said no, or cancelled.
+    Aborted = 3,
+    PermissionDenied = 4,
+    /// The image did not match its hash.
+    InputHashMismatch = 5,
+    /// The disk did not match the image after writing it.
+    VerificationFailed = 6,
+    /// The image did not fit on the disk.
+    TargetTooSmall = 7,
+    /// Reading the image or writing the disk failed.
+    WriteFailed = 8,
+    /// We couldn't start or talk to the process doing the writing.
+    HerderFailed = 9,
+    FailedToUnmount = 10,
+    /// The burn being resumed can't be, because the disk or image changed.
+    CheckpointMismatch = 11,
+}
+
+/// An error that the user has already been told about, so all that's left to do is
+/// exit with the right code.
+#[derive(Debug, thiserror::Error)]
+#[error("Exiting with {0:?}")]
+pub struct AlreadyReported(pub ExitCode);
+
+/// The command line arguments don't make sense together.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct UsageError(pub String);
+
+impl ExitCode {
+    pub fn of_write_error(error: &WriteVerifyError) -> Self {
+        match error {
+            WriteVerifyError::EndOfOutput => ExitCode::TargetTooSmall,
+            WriteVerifyError::PermissionDenied => ExitCode::PermissionDenied,
+            WriteVerifyError::VerificationFailed(_)
+            | WriteVerifyError::RepairFailed { .. }
+            | WriteVerifyError::HashMismatch { .. } => ExitCode::VerificationFailed,
+            WriteVerifyError::InputHashMismatch { .. } => ExitCode::InputHashMismatch,
+            WriteVerifyError::UnexpectedTermination
+            | WriteVerifyError::UnknownChildProcError(_)
+            | WriteVerifyError::SourceFailed(_) => ExitCode::WriteFailed,
+            WriteVerifyError::FailedToUnmount { .. } | WriteVerifyError::UnmountFailed { .. } => {
+                ExitCode::FailedToUnmount
+            }
+            WriteVerifyError::CheckpointMismatch => ExitCode::CheckpointMismatch,
+        }
+    }
+
+    /// Work out what to exit with because of `error`.
+    pub fn of_error(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(AlreadyReported(code)) = cause.downcast_ref() {
+                return *code;
+            }
+            if cause.is::<UsageError>() {
+                return ExitCode::Usage;
+            }
+            if let Some(e) = cause.downcast_ref::<InquireError>() {
+                return match e {
+                    InquireError::OperationCanceled | InquireError::OperationInterrupted => {
+                        ExitCode::Aborted
+                    }
+                    InquireError::NotTTY => ExitCode::Usage,
+                    _ => ExitCode::Other,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<StartWriterError<WriteVerifyEvent>>() {
+                return match e {
+                    StartWriterError::Failed(e) => Self::of_write_error(e),
+                    _ => ExitCode::HerderFailed,
+                };
+            }
+            if cause.is::<DaemonError>() {
+                return ExitCode::HerderFailed;
+            }
+        }
+        ExitCode::Other
+    }
+
+    /// If this is likely to be a bug in caligula, which we should ask for a report of.
+    pub fn is_likely_bug(self) -> bool {
+        self == ExitCode::HerderFailed
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(value: ExitCode) -> Self {
+        std::process::ExitCode::from(value as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use inquire::InquireError;
+    use test_case::test_case;
+
+    use crate::{
+        herder_daemon::ipc::{Mismatches, WriteVerifyError, WriteVerifyEvent},
+        herder_facade::{DaemonError, StartWriterError},
+    };
+
+    use super::{AlreadyReported, ExitCode, UsageError};
+
+    fn mismatches() -> Mismatches {
+        Mismatches {
+            first_offset: 10,
+            first_block_differing_bytes: 1,
+            block_size: 512,
+            bad_ranges: vec![],
+            bad_range_count: 0,
+            bad_blocks: 0,
+            bad_bytes: 0,
+            scanned_all: false,
+        }
+    }
+
+    #[test_case(WriteVerifyError::EndOfOutput => ExitCode::TargetTooSmall)]
+    #[test_case(WriteVerifyError::VerificationFailed(mismatches()) => ExitCode::VerificationFailed)]
+    #[test_case(
+        WriteVerifyError::RepairFailed { attempts: 3, unfixed: mismatches() }
+        => ExitCode::VerificationFailed
+    )]
+    #[test_case(
+        WriteVerifyError::HashMismatch { expected: vec![1], actual: vec![2] }
+        => ExitCode::VerificationFailed
+    )]
+    #[test_case(
+        WriteVerifyError::InputHashMismatch { expected: vec![1], actual: vec![2] }
+        => ExitCode::InputHashMismatch
+    )]
+    #[test_case(
+        WriteVerifyError::UnmountFailed { mountpoint: "/mnt".into(), error: "busy".into() }
+        => ExitCode::FailedToUnmount
+    )]
+    #[test_case(WriteVerifyError::CheckpointMismatch => ExitCode::CheckpointMismatch)]
+    #[test_case(WriteVerifyError::SourceFailed("timed out".into()) => ExitCode::WriteFailed)]
+    fn write_errors_exit_with_their_own_codes(error: WriteVerifyError) -> ExitCode {
+        ExitCode::of_write_error(&error)
+    }
+
+    #[test]
+    fn start_failures_exit_with_the_failure_code() {
+        let error: StartWriterError<WriteVerifyEvent> =
+            StartWriterError::Failed(WriteVerifyError::PermissionDenied);
+        let error = anyhow::Error::from(error).context("Failed to start burning to /dev/sda");
+
+        assert_eq!(ExitCode::of_error(&error), ExitCode::PermissionDenied);
+    }
+
+    #[test]
+    fn daemon_errors_exit_with_herder_failed() {
+        let error: StartWriterError<WriteVerifyEvent> = DaemonError::UnexpectedDisconnect.into();
+
+        assert_eq!(ExitCode::of_error(&error.into()), ExitCode::HerderFailed);
+    }
+
+    #[test]
+    fn cancelling_a_prompt_exits_with_aborted() {
+        let error: anyhow::Result<()> =
+            Err(InquireError::OperationCanceled).context("Failed to ask for the disk");
+
+        assert_eq!(ExitCode::of_error(&error.unwrap_err()), ExitCode::Aborted);
+    }
+
+    #[test]
+    fn reported_errors_exit_with_their_code() {
+        let error = AlreadyReported(ExitCode::InputHashMismatch).into();
+
+        assert_eq!(ExitCode::of_error(&error), ExitCode::InputHashMismatch);
+    }
+
+    #[test]
+    fn usage_errors_exit_with_usage() {
+        let error = anyhow::anyhow!(UsageError("--foo can't be used with --bar".into()));
+
+        assert_eq!(ExitCode::of_error(&error), ExitCode::Usage);
+    }
+
+    #[test]
+    fn other_errors_exit_with_other() {
+        assert_eq!(
+            ExitCode::of_error(&anyhow::anyhow!("Something broke")),
+            ExitCode::Other
+        );
+    }
+}
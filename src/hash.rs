@@ -41,6 +41,20 @@ macro_rules! generate {
             )*)*
         }
 
+        /// An incremental hasher that is fed bytes directly, rather than pulling
+        /// them from a reader.
+        #[derive(Clone)]
+        pub struct Hasher {
+            inner: HasherInner,
+        }
+
+        #[derive(Clone)]
+        enum HasherInner {
+            $($(
+                $enum_arm($hash_inner),
+            )*)*
+        }
+
         impl HashAlg {
             /// Parses from SRI algorithm prefix. See https://www.w3.org/TR/SRI/ for more information.
             /// Note that although SRI only supports sha256, sha384, and sha512, we parse out
@@ -125,6 +139,35 @@ macro_rules! generate {
             }
         }
 
+        impl Hasher {
+            pub fn new(alg: HashAlg) -> Self {
+                let inner = match alg {
+                    $($(
+                        HashAlg::$enum_arm => HasherInner::$enum_arm($makehash_expr),
+                    )*)*
+                };
+
+                Self { inner }
+            }
+
+            #[inline]
+            pub fn update(&mut self, data: &[u8]) {
+                match &mut self.inner {
+                    $($(
+                        HasherInner::$enum_arm(h) => h.update(data),
+                    )*)*
+                }
+            }
+
+            pub fn finalize(self) -> Vec<u8> {
+                match self.inner {
+                    $($(
+                        HasherInner::$enum_arm(h) => h.finalize()[..].into(),
+                    )*)*
+                }
+            }
+        }
+
         impl<R> Iterator for Hashing<R>
         where
             R: Read,
@@ -219,6 +262,13 @@ pub struct FileHashInfo {
     pub file_hash: Vec<u8>,
 }
 
+/// A hash that some data is expected to have.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedHash {
+    pub alg: HashAlg,
+    pub hash: Vec<u8>,
+}
+
 impl<H, R> GenericHashing<H, R>
 where
     H: Digest,
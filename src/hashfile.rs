@@ -1,4 +1,4 @@
-use crate::hash::HashAlg;
+use crate::{download, hash::HashAlg};
 use anyhow::anyhow;
 use std::{
     fs::File,
@@ -34,6 +34,13 @@ const HASH_FILES: &[(HashAlg, &str)] = &[
     (HashAlg::Sha512, "SHA512SUMS"),
 ];
 
+/// Hash files to look for next to an image that is being downloaded. This is shorter
+/// than [HASH_FILES], since each one we try is a round trip to the server.
+const URL_HASH_FILES: &[(HashAlg, &str)] = &[
+    (HashAlg::Sha256, "SHA256SUMS"),
+    (HashAlg::Sha512, "SHA512SUMS"),
+];
+
 /// Common hash file extensions.
 const HASH_EXTENSIONS: &[(HashAlg, &str)] = &[
     (HashAlg::Md5, "md5"),
@@ -77,11 +84,32 @@ pub fn find_hash_in_standard_files(input: &Path) -> Option<(Vec<HashAlg>, String
     None
 }
 
+/// Like [find_hash_in_standard_files], but for an image at `url`, looking for hash files
+/// on the same server.
+pub fn find_hash_at_url(url: &str) -> Option<(Vec<HashAlg>, String, Vec<u8>)> {
+    let (dir, basename) = download::split_file_name(url)?;
+    for (alg, hash_file) in URL_HASH_FILES {
+        let hash_url = format!("{dir}/{hash_file}");
+        match download::get(&hash_url) {
+            Ok(d) => match parse_hashfile(BufReader::new(d.body), basename) {
+                Ok(Some(expected_hash)) => {
+                    return Some((vec![*alg], hash_url, expected_hash));
+                }
+                Ok(None) => tracing::warn!("Hash not found in {hash_url}"),
+                Err(e) => tracing::warn!("{e}"),
+            },
+            Err(e) => tracing::debug!("{e:#}"),
+        }
+    }
+
+    None
+}
+
 pub fn find_hash_in_user_file<'a>(
     input: &Path,
     hash_filepath: &'a Path,
 ) -> Option<(Vec<HashAlg>, &'a str, Vec<u8>)> {
-    match File::open(&hash_filepath) {
+    match File::open(hash_filepath) {
         Ok(file) => match parse_hashfile(BufReader::new(file), input.file_name()?.to_str()?) {
             Ok(Some(expected_hash)) => {
                 return Some((
@@ -121,8 +149,9 @@ fn parse_hashfile(hash_file: impl BufRead, input_file: &str) -> anyhow::Result<O
 
 #[cfg(test)]
 mod tests {
-    use super::parse_hashfile;
-    use std::io::Cursor;
+    use super::{find_hash_at_url, parse_hashfile};
+    use crate::{download::tests::serve, hash::HashAlg};
+    use std::{collections::HashMap, io::Cursor};
 
     #[test]
     fn parse_simple_hashfile() {
@@ -172,4 +201,24 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn finds_hash_next_to_url() {
+        let hash = "bceb3dded8935c1d3521c475a69ae557e082839b46d921c8b400524470b5c965";
+        let base = serve(HashMap::from([(
+            "/iso/SHA256SUMS",
+            format!("{hash}  other.iso\n{hash}  disk.iso\n").into_bytes(),
+        )]));
+
+        assert_eq!(
+            find_hash_at_url(&format!("{base}/iso/disk.iso")),
+            Some((
+                vec![HashAlg::Sha256],
+                format!("{base}/iso/SHA256SUMS"),
+                base16::decode(hash).unwrap()
+            ))
+        );
+        assert_eq!(find_hash_at_url(&format!("{base}/iso/missing.iso")), None);
+        assert_eq!(find_hash_at_url(&format!("{base}/elsewhere/disk.iso")), None);
+    }
 }
@@ -2,7 +2,10 @@ use std::fmt::{Debug, Display};
 
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-pub use super::writer_process::ipc::{WriteVerifyAction, WriteVerifyError, WriteVerifyEvent};
+pub use super::writer_process::ipc::{
+    ImageHash, Mismatches, ReadDiskAction, SparseMode, VerifyAction, VerifyAgainst, WipeAction,
+    WipeMode, WriteCheckpoint, WriteVerifyAction, WriteVerifyError, WriteVerifyEvent,
+};
 
 /// Tell the herder to start a herd for performing an arbitrary action.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -16,7 +19,7 @@ pub struct StartHerd<A> {
 
 /// Arbitrary herd initialization action. This can be anything, from writing to verifying to voiding.
 pub trait HerdAction:
-    Serialize + DeserializeOwned + Debug + Clone + PartialEq + Send + 'static
+    Serialize + DeserializeOwned + Debug + Clone + PartialEq + Into<TopLevelHerdAction> + Send + 'static
 {
     /// The events emitted by the herd afterwards.
     type Event: HerdEvent;
@@ -48,6 +51,16 @@ pub trait HerdEvent:
     fn downcast_as_failure(self) -> Result<Self::Failure, Self>;
 }
 
+/// An enum containing all implemented and valid types of herd action.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, derive_more::From)]
+#[non_exhaustive]
+pub enum TopLevelHerdAction {
+    Write(WriteVerifyAction),
+    ReadDisk(ReadDiskAction),
+    Wipe(WipeAction),
+    Verify(VerifyAction),
+}
+
 /// An enum containing all implemented and valid types of herder event.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, derive_more::From)]
 #[non_exhaustive]
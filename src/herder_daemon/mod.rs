@@ -8,7 +8,7 @@ use tracing::info;
 use tracing_unwrap::ResultExt;
 
 use crate::{
-    herder_daemon::ipc::{TopLevelHerdEvent, WriteVerifyAction},
+    herder_daemon::ipc::{TopLevelHerdAction, TopLevelHerdEvent},
     ipc_common::{read_msg_async, write_msg},
 };
 
@@ -18,7 +18,7 @@ mod writer_process;
 pub async fn main() {
     loop {
         let msg =
-            match read_msg_async::<ipc::StartHerd<WriteVerifyAction>>(tokio::io::stdin()).await {
+            match read_msg_async::<ipc::StartHerd<TopLevelHerdAction>>(tokio::io::stdin()).await {
                 Ok(d) => d,
                 Err(e) => {
                     tracing::info!("Error received on stdin, quitting: {e}");
@@ -27,13 +27,16 @@ pub async fn main() {
             };
         info!(?msg, "Received StartAction request");
 
-        let child = writer_process::spawn_writer(
-            msg.id,
-            move |m| {
-                write_msg(std::io::stdout(), &(msg.id, TopLevelHerdEvent::from(m))).ok_or_log();
-            },
-            msg.action,
-        );
-        info!(?child, "Spawned writer thread");
+        let id = msg.id;
+        let tx = move |m| {
+            write_msg(std::io::stdout(), &(id, TopLevelHerdEvent::from(m))).ok_or_log();
+        };
+        let child = match msg.action {
+            TopLevelHerdAction::Write(action) => writer_process::spawn_writer(id, tx, action),
+            TopLevelHerdAction::ReadDisk(action) => writer_process::spawn_reader(id, tx, action),
+            TopLevelHerdAction::Wipe(action) => writer_process::spawn_wiper(id, tx, action),
+            TopLevelHerdAction::Verify(action) => writer_process::spawn_verifier(id, tx, action),
+        };
+        info!(?child, "Spawned herd thread");
     }
 }
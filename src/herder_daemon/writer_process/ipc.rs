@@ -1,25 +1,199 @@
 use std::{fmt::Display, path::PathBuf};
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-use crate::compression::CompressionFormat;
+use crate::archive::ArchiveMember;
+use crate::compression::{CompressionFormat, CompressionLevel};
 use crate::device::Type;
+use crate::hash::ExpectedHash;
 use crate::herder_daemon::ipc::{self, HerdAction};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WriteVerifyAction {
     pub dest: PathBuf,
+    /// The image file to write. This may also be a pipe, in which case it is only read
+    /// once, and verification is done against a hash of what was written instead.
     pub src: PathBuf,
+    /// If `src` is a pipe, how much is expected to come out of it, if known.
+    pub src_size: Option<u64>,
+    /// If true, compare the disk against the source file after writing
     pub verify: bool,
+    /// If true, keep comparing after the first mismatch while verifying against the
+    /// source file, so that every region that differs gets reported.
+    pub verify_all: bool,
+    /// If nonzero, regions of the disk that don't match the source file are rewritten
+    /// and verified again, up to this many times, before giving up.
+    pub repair_attempts: u32,
+    /// If provided, hash the written region of the disk after writing and compare it
+    /// against this. This does not need the source file.
+    pub verify_hash: Option<ExpectedHash>,
+    /// If provided, hash the source file while writing it, and fail before verifying if
+    /// the hash does not match.
+    pub input_hash: Option<ImageHash>,
+    /// If provided, don't write blocks that are entirely zeroes
+    pub sparse: Option<SparseMode>,
     pub compression: CompressionFormat,
+    /// If true, send [WriteVerifyEvent::Checkpoint]s while writing, so that the write
+    /// can be resumed if it gets interrupted.
+    pub checkpoints: bool,
+    /// If provided, the disk already has everything up to this checkpoint written to
+    /// it. Once that is checked, writing continues from there.
+    pub resume_from: Option<WriteCheckpoint>,
+    /// If provided, the source file (after decompression) is an archive, and only this
+    /// member of it is written
+    pub archive_member: Option<ArchiveMember>,
+    /// If true, the source file (after decompression) is an Android sparse image to
+    /// expand while writing
+    pub android_sparse: bool,
+    /// If true, unmount the disk and all of its partitions before opening it. This is
+    /// only done on Linux, since macOS always unmounts the whole disk.
+    pub unmount: bool,
     pub target_type: Type,
     pub block_size: Option<u64>,
 }
 
+/// How to avoid writing blocks of zeroes to a disk. Files are always made sparse
+/// instead, regardless of the mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum SparseMode {
+    /// Ask the disk to zero the blocks out, which is usually faster than writing zeroes
+    ZeroOut,
+    /// Tell the disk that the blocks are unused. Their contents are unspecified
+    /// afterwards, so they can't be verified.
+    Discard,
+}
+
+/// The expected hash of an image file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageHash {
+    pub expected: ExpectedHash,
+    /// If true, this is the hash of the file as-is. Otherwise, it is the hash of the
+    /// file's decompressed contents.
+    pub of_compressed: bool,
+}
+
 impl HerdAction for WriteVerifyAction {
     type Event = WriteVerifyEvent;
 }
 
+/// Read a disk into an image file. This is the reverse of [WriteVerifyAction].
+///
+/// The [WriteVerifyEvent]s emitted report bytes read from the disk as `src`, and bytes
+/// written to the image file (after compression) as `dest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadDiskAction {
+    /// The disk to read from
+    pub src: PathBuf,
+    /// The image file to write to
+    pub dest: PathBuf,
+    pub verify: bool,
+    /// What format to compress the image file with
+    pub compression: CompressionFormat,
+    pub compression_level: CompressionLevel,
+    pub source_type: Type,
+    pub block_size: Option<u64>,
+    /// Size of the disk, if known. Otherwise, we find it by seeking to the end.
+    pub size: Option<u64>,
+}
+
+impl HerdAction for ReadDiskAction {
+    type Event = WriteVerifyEvent;
+}
+
+/// Compare a disk against an image file or a hash, without writing to it.
+///
+/// The [WriteVerifyEvent]s emitted are the same as for a write that has already
+/// finished: bytes read from the image file are reported as `src`, and bytes read from
+/// the disk as `dest`. When comparing against a hash, both are bytes read from the disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyAction {
+    /// The disk to check
+    pub dest: PathBuf,
+    pub against: VerifyAgainst,
+    pub target_type: Type,
+    pub block_size: Option<u64>,
+}
+
+impl HerdAction for VerifyAction {
+    type Event = WriteVerifyEvent;
+}
+
+/// What a [VerifyAction] compares the disk against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerifyAgainst {
+    /// An image file, read the same way as when it was written
+    Image {
+        src: PathBuf,
+        compression: CompressionFormat,
+        archive_member: Option<ArchiveMember>,
+        android_sparse: bool,
+    },
+    /// A hash of the first `len` bytes of the disk, or of the whole disk if `len` is
+    /// not provided
+    Hash {
+        expected: ExpectedHash,
+        len: Option<u64>,
+    },
+}
+
+/// Wipe a disk, so that what was on it can't be read back.
+///
+/// The [WriteVerifyEvent]s emitted report bytes wiped as both `src` and `dest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WipeAction {
+    pub dest: PathBuf,
+    pub mode: WipeMode,
+    /// If true, read the disk back after wiping it to make sure it was. This is ignored
+    /// for modes that can't be verified.
+    pub verify: bool,
+    /// If true, unmount the disk and all of its partitions before opening it. This is
+    /// only done on Linux, since macOS always unmounts the whole disk.
+    pub unmount: bool,
+    pub target_type: Type,
+    pub block_size: Option<u64>,
+    /// Size of the disk, if known. Otherwise, we find it by seeking to the end.
+    pub size: Option<u64>,
+}
+
+impl HerdAction for WipeAction {
+    type Event = WriteVerifyEvent;
+}
+
+/// How to wipe a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum WipeMode {
+    /// Fill it with zeroes
+    Zero,
+    /// Fill it with random data from a cryptographically secure generator
+    Random,
+    /// Tell the disk that all of it is unused
+    Discard,
+    /// Tell the disk that all of it is unused, and that it must erase it for good
+    SecureDiscard,
+}
+
+impl WipeMode {
+    /// If the disk has known contents after wiping it this way, so it can be verified.
+    pub fn can_verify(self) -> bool {
+        match self {
+            WipeMode::Zero | WipeMode::Random => true,
+            WipeMode::Discard | WipeMode::SecureDiscard => false,
+        }
+    }
+}
+
+impl Display for WipeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WipeMode::Zero => write!(f, "fill with zeroes"),
+            WipeMode::Random => write!(f, "fill with random data"),
+            WipeMode::Discard => write!(f, "discard"),
+            WipeMode::SecureDiscard => write!(f, "secure discard"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WriteVerifyEvent {
     InitSuccess(WriteVerifyStart),
@@ -27,9 +201,23 @@ pub enum WriteVerifyEvent {
         src: u64,
         dest: u64,
     },
+    /// The hash of the source file, computed while writing it. This is sent after
+    /// writing is complete, but before [WriteVerifyEvent::FinishedWriting].
+    InputHashed {
+        hash: Vec<u8>,
+    },
+    /// Everything up to this checkpoint has been written and synced to the disk.
+    Checkpoint(WriteCheckpoint),
     FinishedWriting {
         verifying: bool,
     },
+    /// Verifying found regions of the disk that didn't match, and they are being
+    /// rewritten. This is sent before every attempt.
+    Repairing {
+        attempt: u32,
+        /// How many bytes are being rewritten
+        bad_bytes: u64,
+    },
     BlockSizeChanged(u64),
     BlockSizeSpeedInfo {
         blocks_written: usize,
@@ -61,19 +249,134 @@ impl ipc::HerdEvent for WriteVerifyEvent {
     }
 }
 
+/// How far a write got, so that it can be picked back up after an interruption.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteCheckpoint {
+    /// How much of the source file had been read. This is only for showing progress,
+    /// since decompressors read ahead of what they've output.
+    pub src_offset: u64,
+    /// How much of the image had been read out of the source file.
+    pub decompressed_offset: u64,
+    /// How much of the disk had been written.
+    pub dest_offset: u64,
+    /// The hash of everything written to the disk before `dest_offset`.
+    pub written_hash: ExpectedHash,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WriteVerifyStart {
-    pub input_file_bytes: u64,
+    /// Size of the input file, or None if it is a pipe whose size we can't know ahead
+    /// of time.
+    pub input_file_bytes: Option<u64>,
+}
+
+/// Where a disk didn't match what should have been on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mismatches {
+    /// Offset of the first byte on the disk that differs
+    pub first_offset: u64,
+    /// How many bytes differ in the block containing [Self::first_offset]
+    pub first_block_differing_bytes: u64,
+    pub block_size: u64,
+    /// The regions of the disk that differ, in whole blocks, with adjacent blocks
+    /// merged together. If [Self::scanned_all] is false, these are only the ones in
+    /// the buffer where the first mismatch was found. Only the first
+    /// [Self::MAX_BAD_RANGES] are kept, but all of them are counted below.
+    pub bad_ranges: Vec<ByteRange>,
+    /// How many ranges differ, including ones past the end of [Self::bad_ranges]
+    pub bad_range_count: u64,
+    /// How many blocks differ
+    pub bad_blocks: u64,
+    /// How many bytes are in the blocks that differ
+    pub bad_bytes: u64,
+    /// If true, the whole disk was compared, rather than stopping at the first
+    /// mismatch.
+    pub scanned_all: bool,
+}
+
+/// A region of a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Mismatches {
+    /// How many bad ranges to keep track of. A disk that is bad all over could
+    /// otherwise have millions of them.
+    pub const MAX_BAD_RANGES: usize = 1024;
+
+    /// How many bad ranges to list before we stop printing them.
+    const MAX_DISPLAYED_RANGES: usize = 10;
+
+    /// If there were more bad ranges than are in [Self::bad_ranges].
+    pub fn is_truncated(&self) -> bool {
+        self.bad_range_count > self.bad_ranges.len() as u64
+    }
+}
+
+impl Display for Mismatches {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The first mismatch is at byte {}, where {} of the {} bytes in that block differ.",
+            self.first_offset, self.first_block_differing_bytes, self.block_size
+        )?;
+        if !self.scanned_all {
+            return Ok(());
+        }
+        write!(
+            f,
+            "\n{} blocks ({} bytes) differ in {} ranges:",
+            self.bad_blocks, self.bad_bytes, self.bad_range_count
+        )?;
+        for r in self.bad_ranges.iter().take(Self::MAX_DISPLAYED_RANGES) {
+            write!(f, "\n  {r}")?;
+        }
+        let shown = self.bad_ranges.len().min(Self::MAX_DISPLAYED_RANGES) as u64;
+        if self.bad_range_count > shown {
+            let more = self.bad_range_count - shown;
+            write!(f, "\n  ... and {more} more")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bytes {}..{} ({} bytes)",
+            self.offset,
+            self.offset + self.len,
+            self.len
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WriteVerifyError {
     EndOfOutput,
     PermissionDenied,
-    VerificationFailed,
+    /// The disk was read back, and parts of it didn't match what should be on it.
+    VerificationFailed(Mismatches),
+    /// Parts of the disk didn't match, and still didn't after rewriting them.
+    RepairFailed {
+        attempts: u32,
+        /// What still didn't match after the last attempt
+        unfixed: Mismatches,
+    },
+    HashMismatch { expected: Vec<u8>, actual: Vec<u8> },
+    InputHashMismatch { expected: Vec<u8>, actual: Vec<u8> },
     UnexpectedTermination,
     UnknownChildProcError(String),
     FailedToUnmount { message: String, exit_code: i32 },
+    /// One of the filesystems on the disk could not be unmounted.
+    UnmountFailed { mountpoint: PathBuf, error: String },
+    /// The disk or the image no longer match the checkpoint being resumed from.
+    CheckpointMismatch,
+    /// The image stopped coming in partway through, like a download that stalled.
+    SourceFailed(String),
 }
 
 impl From<std::io::Error> for WriteVerifyError {
@@ -85,6 +388,26 @@ impl From<std::io::Error> for WriteVerifyError {
     }
 }
 
+impl WriteVerifyError {
+    /// A short, stable name for this kind of error, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WriteVerifyError::EndOfOutput => "end_of_output",
+            WriteVerifyError::PermissionDenied => "permission_denied",
+            WriteVerifyError::VerificationFailed(_) => "verification_failed",
+            WriteVerifyError::RepairFailed { .. } => "repair_failed",
+            WriteVerifyError::HashMismatch { .. } => "hash_mismatch",
+            WriteVerifyError::InputHashMismatch { .. } => "input_hash_mismatch",
+            WriteVerifyError::UnexpectedTermination => "unexpected_termination",
+            WriteVerifyError::UnknownChildProcError(_) => "unknown_child_proc_error",
+            WriteVerifyError::FailedToUnmount { .. } => "failed_to_unmount",
+            WriteVerifyError::UnmountFailed { .. } => "unmount_failed",
+            WriteVerifyError::CheckpointMismatch => "checkpoint_mismatch",
+            WriteVerifyError::SourceFailed(_) => "source_failed",
+        }
+    }
+}
+
 impl Display for WriteVerifyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -93,7 +416,23 @@ impl Display for WriteVerifyError {
                 "Unexpected end of output file. Is your output file too small?"
             ),
             WriteVerifyError::PermissionDenied => write!(f, "Permission denied while opening file"),
-            WriteVerifyError::VerificationFailed => write!(f, "Disk verification failed!"),
+            WriteVerifyError::VerificationFailed(m) => write!(f, "Disk verification failed! {m}"),
+            WriteVerifyError::RepairFailed { attempts, unfixed } => write!(
+                f,
+                "Disk verification failed, and parts of the disk still didn't match after rewriting them {attempts} times! {unfixed}"
+            ),
+            WriteVerifyError::HashMismatch { expected, actual } => write!(
+                f,
+                "Disk hash did not match!\n  Expected: {}\n    Actual: {}",
+                base16::encode_lower(expected),
+                base16::encode_lower(actual)
+            ),
+            WriteVerifyError::InputHashMismatch { expected, actual } => write!(
+                f,
+                "Input file hash did not match! Your disk image may be corrupted!\n  Expected: {}\n    Actual: {}",
+                base16::encode_lower(expected),
+                base16::encode_lower(actual)
+            ),
             WriteVerifyError::UnexpectedTermination => {
                 write!(f, "The child process unexpectedly terminated!")
             }
@@ -104,6 +443,18 @@ impl Display for WriteVerifyError {
                 f,
                 "Failed to unmount disk (exit code {exit_code})\n{message}"
             ),
+            WriteVerifyError::UnmountFailed { mountpoint, error } => write!(
+                f,
+                "Failed to unmount {}: {error}. Is something still using it?",
+                mountpoint.to_string_lossy()
+            ),
+            WriteVerifyError::CheckpointMismatch => write!(
+                f,
+                "The disk or the image changed since the interrupted burn, so it can't be resumed. Please burn again without --resume."
+            ),
+            WriteVerifyError::SourceFailed(err) => {
+                write!(f, "Failed to read the image: {err}")
+            }
         }
     }
 }
@@ -4,22 +4,28 @@
 
 use std::fs::OpenOptions;
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::thread::JoinHandle;
 use std::{
     fs::File,
-    io::{self, Read, Seek, Write},
+    io::{self, BufWriter, Read, Seek, Write},
 };
 
 use aligned_vec::avec_rt;
 use tracing::{debug, info, trace};
 use tracing_unwrap::ResultExt;
 
-use crate::compression::CompressionFormat;
+use crate::archive::ArchiveMember;
+use crate::compression::{CompressionFormat, CompressionLevel, compress};
 use crate::device;
+use crate::hash::{ExpectedHash, HashAlg, Hasher};
 
-use self::utils::{CountRead, CountWrite, FileSourceReader, SyncDataFile};
-use self::xplat::open_blockdev;
+use self::utils::{
+    CountRead, CountWrite, FileSourceReader, MismatchFinder, SkipHow, SkipRegion, SyncDataFile,
+    is_zeroes,
+};
+use self::xplat::{open_blockdev, open_blockdev_readonly};
 
 use ipc::*;
 
@@ -27,14 +33,21 @@ pub mod ipc;
 #[cfg(test)]
 mod tests;
 mod utils;
+mod wipe;
 mod xplat;
 
+pub use self::wipe::spawn_wiper;
+
 /// Maximum size we may allocate for each buffer.
 const MAX_BUF_SIZE: usize = 1 << 20; // 1MiB
 
 /// How many bytes should be written before we perform a checkpoint (aka report progress).
 const CHECKPOINT_BYTES: usize = 8 * (1 << 20); // 8MiB
 
+/// Hash algorithm to remember what we wrote with, for verifying piped input and for
+/// checkpoints.
+const WRITTEN_HASH_ALG: HashAlg = HashAlg::Sha256;
+
 pub fn spawn_writer(
     id: u64,
     mut tx: impl FnMut(WriteVerifyEvent) + Send + 'static,
@@ -60,11 +73,211 @@ fn run(
     mut tx: impl FnMut(WriteVerifyEvent),
     args: &WriteVerifyAction,
 ) -> Result<(), WriteVerifyError> {
-    if cfg!(target_os = "macos") && args.target_type == device::Type::Disk {
+    unmount_dest(&args.dest, args.target_type, args.unmount)?;
+
+    // The disk is opened first, so that if we don't have permissions on it, we fail
+    // before touching the source. If the source is a pipe, that leaves it for the
+    // escalated herd to read.
+    info!("Opening {} for writing", args.dest.to_string_lossy());
+
+    let mut disk = SyncDataFile(match args.target_type {
+        device::Type::File => OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(args.resume_from.is_none())
+            .open(&args.dest)?,
+        device::Type::Disk | device::Type::Partition => {
+            open_blockdev(&args.dest, args.compression)?
+        }
+    });
+
+    info!("Opening file {}", args.src.to_string_lossy());
+    let mut file = File::open(&args.src).unwrap_or_log();
+    let is_pipe = !file.metadata()?.is_file();
+    let size = if is_pipe {
+        args.src_size
+    } else {
+        let size = file.seek(io::SeekFrom::End(0))?;
+        file.seek(io::SeekFrom::Start(0))?;
+        Some(size)
+    };
+
+    info!(size, "Got input file size");
+
+    tx(WriteVerifyEvent::InitSuccess(WriteVerifyStart {
+        input_file_bytes: size,
+    }));
+
+    let bs = match args.block_size {
+        Some(bs) => bs,
+        None => {
+            info!("Unknown block size, assuming 512");
+            512
+        }
+    };
+    let buf_size = ((bs * 2048) as usize).min(MAX_BUF_SIZE);
+    let checkpoint_period = CHECKPOINT_BYTES / buf_size;
+
+    let skip_zeroes = args.sparse.map(|mode| match (args.target_type, mode) {
+        (device::Type::File, _) => SkipHow::Seek,
+        (_, SparseMode::ZeroOut) => SkipHow::ZeroOut,
+        (_, SparseMode::Discard) => SkipHow::Discard,
+    });
+    info!(?skip_zeroes, "Determined how to skip zero blocks");
+
+    // Pipes can't be read again to verify against, so we remember what they contained
+    // by hashing everything we write instead.
+    let mut output_hash =
+        (args.checkpoints || (args.verify && is_pipe)).then(|| Hasher::new(WRITTEN_HASH_ALG));
+
+    let mut file_at_checkpoint = false;
+    if let Some(cp) = &args.resume_from {
+        info!(?cp, "Checking what was already written before resuming");
+        output_hash = Some(check_written(
+            &mut disk,
+            cp,
+            buf_size,
+            bs as usize,
+            checkpoint_period,
+        )?);
+        disk.seek(io::SeekFrom::Start(cp.dest_offset))?;
+
+        // Uncompressed images can be jumped right into. Otherwise, the decoder needs to
+        // start from the beginning, so WriteOp reads its way up to the checkpoint.
+        if !is_pipe
+            && args.compression.is_identity()
+            && args.archive_member.is_none()
+            && !args.android_sparse
+            && args.input_hash.is_none()
+        {
+            file.seek(io::SeekFrom::Start(cp.decompressed_offset))?;
+            file_at_checkpoint = true;
+        }
+    }
+
+    let actual_input_bytes = WriteOp {
+        file: &mut file,
+        disk: &mut disk,
+        cf: args.compression,
+        archive_member: args.archive_member.as_ref(),
+        android_sparse: args.android_sparse,
+        buf_size,
+        disk_block_size: bs as usize,
+        checkpoint_period,
+        file_read_buf_size: buf_size,
+        input_hash: args.input_hash.as_ref(),
+        skip_zeroes,
+        output_hash: output_hash.as_mut(),
+        checkpoints: args.checkpoints,
+        resume_from: args.resume_from.as_ref(),
+        file_at_checkpoint,
+    }
+    .execute(&mut tx)?;
+
+    if args.target_type == device::Type::File {
+        info!(
+            ?actual_input_bytes,
+            "Output is a file, setting it to input length in case we wrote too much or skipped the end"
+        );
+        disk.0.set_len(actual_input_bytes)?;
+    };
+
+    let output_hash = output_hash.filter(|_| is_pipe).map(|h| ExpectedHash {
+        alg: WRITTEN_HASH_ALG,
+        hash: h.finalize(),
+    });
+    let verify_hash = args.verify_hash.as_ref().or(output_hash.as_ref());
+
+    let verifying = args.verify || verify_hash.is_some();
+    tx(WriteVerifyEvent::FinishedWriting { verifying });
+
+    if !verifying {
+        info!("Verification skip was requested, stopping");
+        return Ok(());
+    }
+
+    info!("Rewinding target to beginning");
+    disk.seek(io::SeekFrom::Start(0))?;
+
+    if let Some(expected) = verify_hash {
+        info!(alg = ?expected.alg, "Executing hash verification");
+        HashVerifyOp {
+            disk: &mut disk,
+            expected,
+            len: actual_input_bytes,
+            buf_size,
+            disk_block_size: bs as usize,
+            checkpoint_period,
+        }
+        .execute(&mut tx)?;
+        disk.seek(io::SeekFrom::Start(0))?;
+    }
+
+    if !args.verify || is_pipe {
+        return Ok(());
+    }
+
+    info!("Rewinding source to beginning");
+    file.seek(io::SeekFrom::Start(0))?;
+
+    info!("Executing verification");
+    let verified = VerifyOp {
+        file: &mut file,
+        disk: &mut disk,
+        cf: args.compression,
+        archive_member: args.archive_member.as_ref(),
+        android_sparse: args.android_sparse,
+        buf_size,
+        disk_block_size: bs as usize,
+        checkpoint_period,
+        file_read_buf_size: buf_size,
+        ignore_zero_blocks: skip_zeroes == Some(SkipHow::Discard),
+        // Repairing needs to know about every region that doesn't match
+        scan_all: args.verify_all || args.repair_attempts > 0,
+    }
+    .execute(&mut tx);
+
+    let mismatches = match verified {
+        Err(WriteVerifyError::VerificationFailed(m)) if args.repair_attempts > 0 => m,
+        other => return other,
+    };
+
+    info!(?mismatches, "Verification failed, repairing");
+    RepairOp {
+        file: &mut file,
+        disk: &mut disk,
+        cf: args.compression,
+        archive_member: args.archive_member.as_ref(),
+        android_sparse: args.android_sparse,
+        buf_size,
+        disk_block_size: bs as usize,
+        file_read_buf_size: buf_size,
+        ignore_zero_blocks: skip_zeroes == Some(SkipHow::Discard),
+        attempts: args.repair_attempts,
+    }
+    .execute(mismatches, tx)?;
+
+    if args.target_type == device::Type::File {
+        info!("Output is a file, setting it to input length again after repairing");
+        disk.0.set_len(actual_input_bytes)?;
+    }
+
+    Ok(())
+}
+
+/// Unmount `dest` before opening it, so that nothing else writes to it while we do.
+/// On Linux, this is only done if `unmount` is set.
+fn unmount_dest(
+    dest: &Path,
+    target_type: device::Type,
+    unmount: bool,
+) -> Result<(), WriteVerifyError> {
+    if cfg!(target_os = "macos") && target_type == device::Type::Disk {
         let mut command = Command::new("diskutil");
         command
             .arg("unmountdisk")
-            .arg(&args.dest)
+            .arg(dest)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
@@ -90,29 +303,65 @@ fn run(
         }
     }
 
-    info!("Opening file {}", args.src.to_string_lossy());
-    let mut file = File::open(&args.src).unwrap_or_log();
-    let size = file.seek(io::SeekFrom::End(0))?;
-    file.seek(io::SeekFrom::Start(0))?;
+    #[cfg(target_os = "linux")]
+    if unmount {
+        xplat::unmount_target(dest)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = unmount;
 
-    info!(size, "Got input file size");
+    Ok(())
+}
 
-    info!("Opening {} for writing", args.dest.to_string_lossy());
+pub fn spawn_reader(
+    id: u64,
+    mut tx: impl FnMut(WriteVerifyEvent) + Send + 'static,
+    init_config: ReadDiskAction,
+) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .name(format!("reader/{id}"))
+        .spawn(move || {
+            debug!("Spawned child thread {:?}", std::thread::current().id());
+            let final_msg = match run_read(&mut tx, &init_config) {
+                Ok(_) => WriteVerifyEvent::Success,
+                Err(e) => WriteVerifyEvent::Error(e),
+            };
+            info!(?final_msg, "Completed");
+            tx(final_msg);
+        })
+        .unwrap()
+}
 
-    let mut disk = SyncDataFile(match args.target_type {
-        device::Type::File => OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&args.dest)?,
-        device::Type::Disk | device::Type::Partition => {
-            open_blockdev(&args.dest, args.compression)?
-        }
+fn run_read(
+    mut tx: impl FnMut(WriteVerifyEvent),
+    args: &ReadDiskAction,
+) -> Result<(), WriteVerifyError> {
+    info!("Opening {} for reading", args.src.to_string_lossy());
+    let mut disk = SyncDataFile(match args.source_type {
+        device::Type::File => File::open(&args.src)?,
+        device::Type::Disk | device::Type::Partition => open_blockdev_readonly(&args.src)?,
     });
 
+    let size = match args.size {
+        Some(size) => size,
+        None => {
+            let size = disk.seek(io::SeekFrom::End(0))?;
+            disk.seek(io::SeekFrom::Start(0))?;
+            size
+        }
+    };
+    info!(size, "Got input disk size");
+
+    info!("Opening {} for writing", args.dest.to_string_lossy());
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&args.dest)?;
+
     tx(WriteVerifyEvent::InitSuccess(WriteVerifyStart {
-        input_file_bytes: size,
+        input_file_bytes: Some(size),
     }));
 
     let bs = match args.block_size {
@@ -125,14 +374,14 @@ fn run(
     let buf_size = ((bs * 2048) as usize).min(MAX_BUF_SIZE);
     let checkpoint_period = CHECKPOINT_BYTES / buf_size;
 
-    let actual_input_bytes = WriteOp {
-        file: &mut file,
+    ReadOp {
         disk: &mut disk,
+        file: &mut file,
         cf: args.compression,
+        level: args.compression_level,
         buf_size,
         disk_block_size: bs as usize,
         checkpoint_period,
-        file_read_buf_size: buf_size,
     }
     .execute(&mut tx)?;
 
@@ -149,41 +398,142 @@ fn run(
     file.seek(io::SeekFrom::Start(0))?;
     disk.seek(io::SeekFrom::Start(0))?;
 
-    if args.target_type == device::Type::File {
-        info!(
-            ?actual_input_bytes,
-            "Output is a file, truncating to input length in case we wrote too much"
-        );
-        disk.0.set_len(actual_input_bytes)?;
-    };
-
     info!("Executing verification");
     VerifyOp {
         file: &mut file,
         disk: &mut disk,
         cf: args.compression,
+        archive_member: None,
+        android_sparse: false,
         buf_size,
         disk_block_size: bs as usize,
         checkpoint_period,
         file_read_buf_size: buf_size,
+        ignore_zero_blocks: false,
+        scan_all: false,
     }
     .execute(tx)?;
 
     Ok(())
 }
 
+pub fn spawn_verifier(
+    id: u64,
+    mut tx: impl FnMut(WriteVerifyEvent) + Send + 'static,
+    init_config: VerifyAction,
+) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .name(format!("verifier/{id}"))
+        .spawn(move || {
+            debug!("Spawned child thread {:?}", std::thread::current().id());
+            let final_msg = match run_verify(&mut tx, &init_config) {
+                Ok(_) => WriteVerifyEvent::Success,
+                Err(e) => WriteVerifyEvent::Error(e),
+            };
+            info!(?final_msg, "Completed");
+            tx(final_msg);
+        })
+        .unwrap()
+}
+
+fn run_verify(
+    mut tx: impl FnMut(WriteVerifyEvent),
+    args: &VerifyAction,
+) -> Result<(), WriteVerifyError> {
+    info!("Opening {} for reading", args.dest.to_string_lossy());
+    let mut disk = match args.target_type {
+        device::Type::File => File::open(&args.dest)?,
+        device::Type::Disk | device::Type::Partition => open_blockdev_readonly(&args.dest)?,
+    };
+
+    let bs = match args.block_size {
+        Some(bs) => bs,
+        None => {
+            info!("Unknown block size, assuming 512");
+            512
+        }
+    };
+    let buf_size = ((bs * 2048) as usize).min(MAX_BUF_SIZE);
+    let checkpoint_period = CHECKPOINT_BYTES / buf_size;
+
+    match &args.against {
+        VerifyAgainst::Image {
+            src,
+            compression,
+            archive_member,
+            android_sparse,
+        } => {
+            info!("Opening {} for reading", src.to_string_lossy());
+            let mut file = File::open(src)?;
+            tx(WriteVerifyEvent::InitSuccess(WriteVerifyStart {
+                input_file_bytes: Some(file.metadata()?.len()),
+            }));
+            // There's nothing to write, so go straight to verifying
+            tx(WriteVerifyEvent::FinishedWriting { verifying: true });
+
+            info!("Executing verification");
+            VerifyOp {
+                file: &mut file,
+                disk: &mut disk,
+                cf: *compression,
+                archive_member: archive_member.as_ref(),
+                android_sparse: *android_sparse,
+                buf_size,
+                disk_block_size: bs as usize,
+                checkpoint_period,
+                file_read_buf_size: buf_size,
+                ignore_zero_blocks: false,
+                scan_all: true,
+            }
+            .execute(&mut tx)?;
+        }
+        VerifyAgainst::Hash { expected, len } => {
+            let len = match len {
+                Some(len) => *len,
+                None => {
+                    let size = disk.seek(io::SeekFrom::End(0))?;
+                    disk.seek(io::SeekFrom::Start(0))?;
+                    size
+                }
+            };
+            tx(WriteVerifyEvent::InitSuccess(WriteVerifyStart {
+                input_file_bytes: Some(len),
+            }));
+            tx(WriteVerifyEvent::FinishedWriting { verifying: true });
+
+            info!(alg = ?expected.alg, len, "Executing hash verification");
+            HashVerifyOp {
+                disk: &mut disk,
+                expected,
+                len,
+                buf_size,
+                disk_block_size: bs as usize,
+                checkpoint_period,
+            }
+            .execute(&mut tx)?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Wraps a bunch of parameters for a big complicated operation where we:
 ///
 /// - decompress the input file
+/// - optionally hash the input file
 /// - write to a disk
 /// - write stats down a pipe
-struct WriteOp<S: Read, D: Write> {
+struct WriteOp<'a, S: Read, D: Write + SkipRegion> {
     /// File to read from
     file: S,
     /// Disk to write to
     disk: D,
     /// Compression format to use
     cf: CompressionFormat,
+    /// If provided, the file is an archive and this is the member to read from it
+    archive_member: Option<&'a ArchiveMember>,
+    /// If true, the file is an Android sparse image to expand
+    android_sparse: bool,
     /// Buffer size to use when writing
     buf_size: usize,
     /// Block size of the disk
@@ -192,25 +542,65 @@ struct WriteOp<S: Read, D: Write> {
     checkpoint_period: usize,
     /// How big the file reader's buffer should be
     file_read_buf_size: usize,
+    /// If provided, the hash to check the input file against while writing
+    input_hash: Option<&'a ImageHash>,
+    /// If provided, blocks of zeroes are skipped over rather than written
+    skip_zeroes: Option<SkipHow>,
+    /// If provided, everything we write to the disk is fed into this, including
+    /// blocks of zeroes that were skipped
+    output_hash: Option<&'a mut Hasher>,
+    /// If true, send [WriteVerifyEvent::Checkpoint]s as we go. Requires
+    /// [`Self::output_hash`].
+    checkpoints: bool,
+    /// If provided, we are resuming from this checkpoint. The disk must already be
+    /// positioned at its `dest_offset`, and [`Self::output_hash`] must have been fed
+    /// everything before that.
+    resume_from: Option<&'a WriteCheckpoint>,
+    /// If true, the file has already been positioned at the checkpoint. Otherwise,
+    /// the image is read from the start, and the part of it that was already written
+    /// is checked against the checkpoint and skipped.
+    file_at_checkpoint: bool,
 }
 
-impl<S: Read, D: Write> WriteOp<S, D> {
+impl<S: Read, D: Write + SkipRegion> WriteOp<'_, S, D> {
     /// Execute the write operation. Returns total number of bytes written.
     #[inline(always)]
     fn execute(&mut self, mut tx: impl FnMut(WriteVerifyEvent)) -> Result<u64, WriteVerifyError> {
-        let mut file = FileSourceReader::new(self.cf, self.file_read_buf_size, &mut self.file);
+        let mut file = FileSourceReader::new(
+            self.cf,
+            self.archive_member,
+            self.android_sparse,
+            self.file_read_buf_size,
+            &mut self.file,
+            self.input_hash,
+        );
         let mut disk = CountWrite::new(&mut self.disk);
         let mut buf = avec_rt![[self.disk_block_size] | 0u8; self.buf_size];
 
+        // How far into the file and disk we were before we started counting
+        let (mut file_base, mut disk_base) = (0, 0);
+        if let Some(cp) = self.resume_from {
+            disk_base = cp.dest_offset;
+            if self.file_at_checkpoint {
+                file_base = cp.decompressed_offset;
+            } else {
+                skip_written(&mut file, &mut buf, cp)?;
+            }
+        }
+
         macro_rules! checkpoint {
             () => {
                 tx(WriteVerifyEvent::TotalBytes {
-                    src: file.read_file_bytes(),
-                    dest: disk.count(),
+                    src: file_base + file.read_file_bytes(),
+                    dest: disk_base + disk.count(),
                 });
             };
         }
 
+        if self.resume_from.is_some() {
+            checkpoint!();
+        }
+
         loop {
             for _ in 0..self.checkpoint_period {
                 // Try to fill up the block if we can.
@@ -218,7 +608,32 @@ impl<S: Read, D: Write> WriteOp<S, D> {
                 if read_bytes == 0 {
                     disk.flush()?;
                     checkpoint!();
-                    return Ok(file.decompressed_bytes());
+
+                    if let (Some(expected), Some(hash)) = (self.input_hash, file.take_hash()?) {
+                        tx(WriteVerifyEvent::InputHashed { hash: hash.clone() });
+                        if hash != expected.expected.hash {
+                            return Err(WriteVerifyError::InputHashMismatch {
+                                expected: expected.expected.hash.clone(),
+                                actual: hash,
+                            });
+                        }
+                    }
+
+                    return Ok(file_base + file.decompressed_bytes());
+                }
+
+                if let Some(h) = self.output_hash.as_deref_mut() {
+                    h.update(&buf[..read_bytes]);
+                }
+
+                if let Some(how) = self.skip_zeroes
+                    && is_zeroes(&buf[..read_bytes])
+                {
+                    // Only the final read can be short, so we don't need to care about
+                    // where the next one starts.
+                    let len = read_bytes.next_multiple_of(self.disk_block_size);
+                    disk.skip_region(len as u64, how)?;
+                    continue;
                 }
 
                 // Write the entire buffer, because we're doing direct writes.
@@ -231,6 +646,138 @@ impl<S: Read, D: Write> WriteOp<S, D> {
                 }
             }
             checkpoint!();
+
+            if self.checkpoints
+                && let Some(h) = self.output_hash.as_deref()
+            {
+                disk.flush()?;
+                tx(WriteVerifyEvent::Checkpoint(WriteCheckpoint {
+                    src_offset: file_base + file.read_file_bytes(),
+                    decompressed_offset: file_base + file.decompressed_bytes(),
+                    dest_offset: disk_base + disk.count(),
+                    written_hash: ExpectedHash {
+                        alg: WRITTEN_HASH_ALG,
+                        hash: h.clone().finalize(),
+                    },
+                }));
+            }
+        }
+    }
+}
+
+/// Make sure the start of `disk` still has what was written before `cp`. Returns the
+/// hasher that was fed it, to continue hashing what gets written after it.
+fn check_written(
+    disk: impl Read,
+    cp: &WriteCheckpoint,
+    buf_size: usize,
+    disk_block_size: usize,
+    checkpoint_period: usize,
+) -> Result<Hasher, WriteVerifyError> {
+    let written = HashVerifyOp {
+        disk,
+        expected: &cp.written_hash,
+        len: cp.dest_offset,
+        buf_size,
+        disk_block_size,
+        checkpoint_period,
+    }
+    .hash(|_| {})
+    .map_err(|e| match e {
+        WriteVerifyError::EndOfOutput => WriteVerifyError::CheckpointMismatch,
+        e => e,
+    })?;
+    if written.clone().finalize() != cp.written_hash.hash {
+        return Err(WriteVerifyError::CheckpointMismatch);
+    }
+    Ok(written)
+}
+
+/// Read the part of the image that was already written before `cp` out of `file`, and
+/// make sure it is what was written.
+fn skip_written(
+    file: &mut impl Read,
+    buf: &mut [u8],
+    cp: &WriteCheckpoint,
+) -> Result<(), WriteVerifyError> {
+    let mut hasher = Hasher::new(cp.written_hash.alg);
+    let mut remaining = cp.decompressed_offset;
+    while remaining > 0 {
+        let len = (buf.len() as u64).min(remaining) as usize;
+        let read_bytes = try_read_exact(file, &mut buf[..len])?;
+        if read_bytes == 0 {
+            return Err(WriteVerifyError::CheckpointMismatch);
+        }
+        hasher.update(&buf[..read_bytes]);
+        remaining -= read_bytes as u64;
+    }
+    if hasher.finalize() != cp.written_hash.hash {
+        return Err(WriteVerifyError::CheckpointMismatch);
+    }
+    Ok(())
+}
+
+/// Wraps a bunch of parameters for a big complicated operation where we:
+///
+/// - read from a disk
+/// - compress the data
+/// - write it to an image file
+/// - write stats down a pipe
+struct ReadOp<S: Read, D: Write> {
+    /// Disk to read from
+    disk: S,
+    /// File to write to
+    file: D,
+    /// Compression format to use
+    cf: CompressionFormat,
+    /// Compression level to use
+    level: CompressionLevel,
+    /// Buffer size to use when reading
+    buf_size: usize,
+    /// Block size of the disk
+    disk_block_size: usize,
+    /// How many reads of size [`Self::buf_size`] before we report back
+    checkpoint_period: usize,
+}
+
+impl<S: Read, D: Write> ReadOp<S, D> {
+    /// Execute the read operation. Returns total number of bytes read from the disk.
+    #[inline(always)]
+    fn execute(&mut self, mut tx: impl FnMut(WriteVerifyEvent)) -> Result<u64, WriteVerifyError> {
+        let mut disk = CountRead::new(&mut self.disk);
+        let mut file = compress(
+            self.cf,
+            self.level,
+            CountWrite::new(BufWriter::with_capacity(self.buf_size, &mut self.file)),
+        )
+        .map_err(|e| WriteVerifyError::UnknownChildProcError(format!("{e:#}")))?;
+        let mut buf = avec_rt![[self.disk_block_size] | 0u8; self.buf_size];
+
+        macro_rules! checkpoint {
+            () => {
+                tx(WriteVerifyEvent::TotalBytes {
+                    src: disk.count(),
+                    dest: file.get_ref().count(),
+                });
+            };
+        }
+
+        loop {
+            for _ in 0..self.checkpoint_period {
+                let read_bytes = try_read_exact(&mut disk, &mut buf)?;
+                if read_bytes == 0 {
+                    let mut inner = file.finish()?;
+                    inner.flush()?;
+                    tx(WriteVerifyEvent::TotalBytes {
+                        src: disk.count(),
+                        dest: inner.count(),
+                    });
+                    return Ok(disk.count());
+                }
+
+                file.write_all(&buf[..read_bytes])?;
+            }
+            checkpoint!();
         }
     }
 }
@@ -259,13 +806,17 @@ fn try_read_exact(r: &mut impl Read, mut buf: &mut [u8]) -> std::io::Result<usiz
 /// - read from a disk
 /// - verify both sides are correct
 /// - write stats down a pipe
-struct VerifyOp<S: Read, D: Read> {
+struct VerifyOp<'a, S: Read, D: Read> {
     /// File to validate against
     file: S,
     /// Disk to validate
     disk: D,
     /// Compression format to use
     cf: CompressionFormat,
+    /// If provided, the file is an archive and this is the member to read from it
+    archive_member: Option<&'a ArchiveMember>,
+    /// If true, the file is an Android sparse image to expand
+    android_sparse: bool,
     /// Buffer size to use when writing
     buf_size: usize,
     /// Block size of the disk
@@ -274,12 +825,26 @@ struct VerifyOp<S: Read, D: Read> {
     checkpoint_period: usize,
     /// How big the file reader's buffer should be
     file_read_buf_size: usize,
+    /// If true, blocks of zeroes in the file were discarded on the disk rather than
+    /// written, so their contents on the disk are unspecified and not compared.
+    /// [`Self::buf_size`] must be the same as it was when writing.
+    ignore_zero_blocks: bool,
+    /// If true, keep comparing after the first mismatch, so that every region of the
+    /// disk that differs gets reported.
+    scan_all: bool,
 }
 
-impl<S: Read, D: Read> VerifyOp<S, D> {
+impl<S: Read, D: Read> VerifyOp<'_, S, D> {
     #[inline(always)]
     fn execute(&mut self, mut tx: impl FnMut(WriteVerifyEvent)) -> Result<(), WriteVerifyError> {
-        let mut file = FileSourceReader::new(self.cf, self.file_read_buf_size, &mut self.file);
+        let mut file = FileSourceReader::new(
+            self.cf,
+            self.archive_member,
+            self.android_sparse,
+            self.file_read_buf_size,
+            &mut self.file,
+            None,
+        );
         let mut disk = CountRead::new(&mut self.disk);
 
         let mut file_buf = avec_rt![[self.disk_block_size] | 0u8; self.buf_size];
@@ -294,20 +859,288 @@ impl<S: Read, D: Read> VerifyOp<S, D> {
             };
         }
 
+        let mut mismatches = MismatchFinder::new(self.disk_block_size);
+
         loop {
             for _ in 0..self.checkpoint_period {
                 let file_read_bytes = try_read_exact(&mut file, &mut file_buf)?;
                 if file_read_bytes == 0 {
                     checkpoint!();
-                    return Ok(());
+                    return mismatches.finish(true);
                 }
 
-                try_read_exact(&mut disk, &mut disk_buf)?;
+                let offset = disk.count();
+                if try_read_exact(&mut disk, &mut disk_buf)? < file_read_bytes {
+                    checkpoint!();
+                    return Err(WriteVerifyError::EndOfOutput);
+                }
 
-                if file_buf[..file_read_bytes] != disk_buf[..file_read_bytes] {
-                    trace!(file_read_bytes, "verification failed");
-                    return Err(WriteVerifyError::VerificationFailed);
+                if self.ignore_zero_blocks && is_zeroes(&file_buf[..file_read_bytes]) {
+                    continue;
                 }
+
+                let differs = mismatches.compare(
+                    offset,
+                    &file_buf[..file_read_bytes],
+                    &disk_buf[..file_read_bytes],
+                );
+                if differs {
+                    trace!(file_read_bytes, offset, "verification failed");
+                    if !self.scan_all {
+                        return mismatches.finish(false);
+                    }
+                }
+            }
+            checkpoint!();
+        }
+    }
+}
+
+/// Wraps a bunch of parameters for an operation where we:
+///
+/// - decompress the input file again, up to each region of a disk that [VerifyOp]
+///   found didn't match it
+/// - rewrite those regions
+/// - verify them again, and repeat with whatever still doesn't match
+/// - write stats down a pipe
+struct RepairOp<'a, S: Read + Seek, D: Read + Write + Seek> {
+    /// File to rewrite the regions from
+    file: S,
+    /// Disk to repair
+    disk: D,
+    /// Compression format to use
+    cf: CompressionFormat,
+    /// If provided, the file is an archive and this is the member to read from it
+    archive_member: Option<&'a ArchiveMember>,
+    /// If true, the file is an Android sparse image to expand
+    android_sparse: bool,
+    /// Buffer size to use when writing and reading. Must be a multiple of
+    /// [`Self::disk_block_size`].
+    buf_size: usize,
+    /// Block size of the disk
+    disk_block_size: usize,
+    /// How big the file reader's buffer should be
+    file_read_buf_size: usize,
+    /// If true, blocks of zeroes in the file were discarded on the disk rather than
+    /// written, so they aren't compared when looking past the regions we were given.
+    /// [`Self::buf_size`] must be the same as it was when writing.
+    ignore_zero_blocks: bool,
+    /// How many times to rewrite the regions before giving up
+    attempts: u32,
+}
+
+impl<S: Read + Seek, D: Read + Write + Seek> RepairOp<'_, S, D> {
+    fn execute(
+        &mut self,
+        mut mismatches: Mismatches,
+        mut tx: impl FnMut(WriteVerifyEvent),
+    ) -> Result<(), WriteVerifyError> {
+        for attempt in 1..=self.attempts {
+            let bad_bytes = mismatches.bad_ranges.iter().map(|r| r.len).sum();
+            info!(attempt, bad_bytes, "Rewriting regions that didn't match");
+            tx(WriteVerifyEvent::Repairing { attempt, bad_bytes });
+
+            self.rewrite(&mismatches.bad_ranges)?;
+            // Regions past the ones we kept track of are rewritten in the next attempt
+            match self.reverify(&mismatches.bad_ranges, mismatches.is_truncated()) {
+                Ok(()) => return Ok(()),
+                Err(WriteVerifyError::VerificationFailed(m)) => mismatches = m,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(WriteVerifyError::RepairFailed {
+            attempts: self.attempts,
+            unfixed: mismatches,
+        })
+    }
+
+    /// Write what the file has in `ranges` over them on the disk.
+    fn rewrite(&mut self, ranges: &[ByteRange]) -> Result<(), WriteVerifyError> {
+        self.file.seek(io::SeekFrom::Start(0))?;
+        let mut file = FileSourceReader::new(
+            self.cf,
+            self.archive_member,
+            self.android_sparse,
+            self.file_read_buf_size,
+            &mut self.file,
+            None,
+        );
+        let disk = &mut self.disk;
+        let bs = self.disk_block_size;
+        let mut buf = avec_rt![[bs] | 0u8; self.buf_size];
+
+        read_ranges(&mut file, ranges, &mut buf, |offset, buf, len| {
+            // Direct writes have to be whole blocks. Only the end of the file can be
+            // short, and what's on the disk after it doesn't matter.
+            let padded = len.next_multiple_of(bs);
+            buf[len..padded].fill(0);
+            trace!(offset, len, "Rewriting region");
+            disk.seek(io::SeekFrom::Start(offset))?;
+            disk.write_all(&buf[..padded]).map_err(|e| match e.kind() {
+                io::ErrorKind::WriteZero => WriteVerifyError::EndOfOutput,
+                _ => e.into(),
+            })
+        })?;
+        self.disk.flush()?;
+        Ok(())
+    }
+
+    /// Compare what the file has in `ranges` against them on the disk. If `and_rest`,
+    /// everything after the last of them is compared too.
+    fn reverify(&mut self, ranges: &[ByteRange], and_rest: bool) -> Result<(), WriteVerifyError> {
+        self.file.seek(io::SeekFrom::Start(0))?;
+        let mut file = FileSourceReader::new(
+            self.cf,
+            self.archive_member,
+            self.android_sparse,
+            self.file_read_buf_size,
+            &mut self.file,
+            None,
+        );
+        let disk = &mut self.disk;
+        let bs = self.disk_block_size;
+        let mut file_buf = avec_rt![[bs] | 0u8; self.buf_size];
+        let mut disk_buf = avec_rt![[bs] | 0u8; self.buf_size];
+        let mut mismatches = MismatchFinder::new(bs);
+
+        read_ranges(&mut file, ranges, &mut file_buf, |offset, buf, len| {
+            disk.seek(io::SeekFrom::Start(offset))?;
+            if try_read_exact(disk, &mut disk_buf[..len.next_multiple_of(bs)])? < len {
+                return Err(WriteVerifyError::EndOfOutput);
+            }
+            mismatches.compare(offset, &buf[..len], &disk_buf[..len]);
+            Ok(())
+        })?;
+
+        if and_rest {
+            let mut offset = ranges.last().map_or(0, |r| r.offset + r.len);
+            loop {
+                // Read in the same chunks as writing did, so we can tell which it discarded
+                let chunk = self.buf_size - (offset % self.buf_size as u64) as usize;
+                let len = try_read_exact(&mut file, &mut file_buf[..chunk])?;
+                if len == 0 {
+                    break;
+                }
+                // The rest of a chunk that a region ended in wasn't discarded, or there
+                // would have been nothing in it to mismatch
+                let discarded = self.ignore_zero_blocks
+                    && chunk == self.buf_size
+                    && is_zeroes(&file_buf[..len]);
+                if !discarded {
+                    disk.seek(io::SeekFrom::Start(offset))?;
+                    if try_read_exact(disk, &mut disk_buf[..len.next_multiple_of(bs)])? < len {
+                        return Err(WriteVerifyError::EndOfOutput);
+                    }
+                    mismatches.compare(offset, &file_buf[..len], &disk_buf[..len]);
+                }
+                offset += len as u64;
+            }
+        }
+        mismatches.finish(true)
+    }
+}
+
+/// Reads `file` from where it is up to the end of the last of `ranges`, which must be
+/// in order, and calls `f` with each chunk of it that is in one of them. `f` gets the
+/// offset of the chunk, `buf` with the chunk at the start of it, and its length.
+fn read_ranges<R: Read>(
+    file: &mut R,
+    ranges: &[ByteRange],
+    buf: &mut [u8],
+    mut f: impl FnMut(u64, &mut [u8], usize) -> Result<(), WriteVerifyError>,
+) -> Result<(), WriteVerifyError> {
+    let mut pos = 0;
+    for range in ranges {
+        // Decompressors can't seek, so we have to read our way up to the range
+        let skip = range.offset - pos;
+        if io::copy(&mut file.by_ref().take(skip), &mut io::sink())? < skip {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        pos = range.offset;
+
+        let end = range.offset + range.len;
+        while pos < end {
+            let len = (buf.len() as u64).min(end - pos) as usize;
+            if try_read_exact(file, &mut buf[..len])? < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            f(pos, buf, len)?;
+            pos += len as u64;
+        }
+    }
+    Ok(())
+}
+
+/// Wraps a bunch of parameters for a big complicated operation where we:
+///
+/// - read back the written region of a disk
+/// - hash it, and compare it against the expected hash
+/// - write stats down a pipe
+struct HashVerifyOp<'a, D: Read> {
+    /// Disk to validate
+    disk: D,
+    /// The hash the disk's contents should have
+    expected: &'a ExpectedHash,
+    /// How many bytes from the start of the disk to hash
+    len: u64,
+    /// Buffer size to use when reading
+    buf_size: usize,
+    /// Block size of the disk
+    disk_block_size: usize,
+    /// How many reads of size [`Self::buf_size`] before we report back
+    checkpoint_period: usize,
+}
+
+impl<D: Read> HashVerifyOp<'_, D> {
+    #[inline(always)]
+    fn execute(&mut self, tx: impl FnMut(WriteVerifyEvent)) -> Result<(), WriteVerifyError> {
+        let actual = self.hash(tx)?.finalize();
+        if actual != self.expected.hash {
+            trace!(?actual, "hash verification failed");
+            return Err(WriteVerifyError::HashMismatch {
+                expected: self.expected.hash.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Hash the region of the disk, without checking it against the expected hash.
+    #[inline(always)]
+    fn hash(&mut self, mut tx: impl FnMut(WriteVerifyEvent)) -> Result<Hasher, WriteVerifyError> {
+        let mut disk = CountRead::new(&mut self.disk);
+        let mut hasher = Hasher::new(self.expected.alg);
+        let mut remaining = self.len;
+
+        // Reads still have to be whole aligned blocks, so we may read past the end of
+        // the region and simply not hash the excess.
+        let mut buf = avec_rt![[self.disk_block_size] | 0u8; self.buf_size];
+
+        macro_rules! checkpoint {
+            () => {
+                tx(WriteVerifyEvent::TotalBytes {
+                    src: self.len - remaining,
+                    dest: disk.count(),
+                });
+            };
+        }
+
+        loop {
+            for _ in 0..self.checkpoint_period {
+                if remaining == 0 {
+                    checkpoint!();
+                    return Ok(hasher);
+                }
+
+                let read_bytes = try_read_exact(&mut disk, &mut buf)?;
+                if read_bytes == 0 {
+                    checkpoint!();
+                    return Err(WriteVerifyError::EndOfOutput);
+                }
+
+                let hashed_bytes = (read_bytes as u64).min(remaining);
+                hasher.update(&buf[..hashed_bytes as usize]);
+                remaining -= hashed_bytes;
             }
             checkpoint!();
         }
@@ -1,5 +1,9 @@
 use self::helpers::*;
 use super::*;
+use crate::android_sparse::tests::{TestChunk, make_image};
+use crate::archive::tests::{TarEntry, make_tar, make_zip};
+use crate::archive::{list_tar, list_zip};
+use crate::compression::decompress;
 use assert_matches::assert_matches;
 use pretty_assertions::assert_eq;
 use rand::{SeedableRng, rngs::SmallRng};
@@ -171,6 +175,8 @@ fn verify_happy_case_works() {
         disk_block_size: 128,
         checkpoint_period: 32,
         file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: false,
     };
     let result = test.execute();
 
@@ -191,10 +197,15 @@ fn verify_sad_case_works() {
         disk_block_size: 128,
         checkpoint_period: 32,
         file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: false,
     };
     let result = test.execute();
 
-    assert_eq!(result.return_val, Err(WriteVerifyError::VerificationFailed));
+    assert_eq!(
+        result.return_val,
+        Err(verification_failed(10, 1, 128, &[(0, 128)], false))
+    );
 }
 
 #[rstest]
@@ -204,62 +215,927 @@ fn verify_misaligned_case_happy_path_works(#[values(101, 103, 4348, 8337)] file_
     let mut disk = make_random(&mut rng, 16384);
     disk[..file_size].copy_from_slice(&file);
 
-    let test = VerifyTest {
-        buf_size: 128,
-        file,
-        disk,
-        disk_block_size: 128,
-        checkpoint_period: 32,
-        file_read_buf_size: 8192,
+    let test = VerifyTest {
+        buf_size: 128,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 32,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+}
+
+#[rstest]
+#[case(4231, 0)]
+#[case(4231, 1)]
+#[case(4231, 834)]
+#[case(4231, 4210)]
+#[case(4231, 4213)]
+#[case(4232, 4231)]
+fn verify_misaligned_case_sad_path_works(#[case] file_size: usize, #[case] flip_offset: usize) {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let file = make_random(&mut rng, file_size);
+    let mut disk = make_random(&mut rng, 16000);
+    disk[..file_size].copy_from_slice(&file);
+    disk[flip_offset] = !disk[flip_offset];
+
+    let test = VerifyTest {
+        buf_size: 128,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 25,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: false,
+    };
+    let result = test.execute();
+
+    let block = flip_offset / 128 * 128;
+    assert_eq!(
+        result.return_val,
+        Err(verification_failed(
+            flip_offset as u64,
+            1,
+            128,
+            &[(block as u64, (file_size - block).min(128) as u64)],
+            false
+        ))
+    );
+}
+
+#[test]
+fn verify_scanning_all_counts_mismatched_blocks() {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let file = make_random(&mut rng, 4231);
+    let mut disk = make_random(&mut rng, 16000);
+    disk[..file.len()].copy_from_slice(&file);
+    for i in [10, 100, 1000, 4230] {
+        disk[i] = !disk[i];
+    }
+
+    let test = VerifyTest {
+        buf_size: 256,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 4,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: true,
+    };
+    let result = test.execute();
+
+    assert_eq!(
+        result.return_val,
+        Err(verification_failed(
+            10,
+            2,
+            128,
+            &[(0, 128), (896, 128), (4224, 7)],
+            true
+        ))
+    );
+}
+
+#[rstest]
+#[case(true, &[(0, 384), (896, 128)])]
+#[case(false, &[(0, 256)])]
+fn verify_merges_adjacent_mismatched_blocks(
+    #[case] scan_all: bool,
+    #[case] bad_ranges: &[(u64, u64)],
+) {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let file = make_random(&mut rng, 4096);
+    let mut disk = file.clone();
+    for i in [127, 128, 255, 256, 1000, 1001] {
+        disk[i] = !disk[i];
+    }
+
+    let test = VerifyTest {
+        buf_size: 256,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 4,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all,
+    };
+    let result = test.execute();
+
+    assert_eq!(
+        result.return_val,
+        Err(verification_failed(127, 1, 128, bad_ranges, scan_all))
+    );
+}
+
+#[test]
+fn verify_fails_if_disk_ends_before_file() {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let file = make_random(&mut rng, 4096);
+    let disk = file[..2048].to_vec();
+
+    let test = VerifyTest {
+        buf_size: 128,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 4,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: false,
+        scan_all: true,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Err(WriteVerifyError::EndOfOutput));
+}
+
+#[rstest]
+fn read_op_captures_disk(
+    #[values(
+        CompressionFormat::Identity,
+        CompressionFormat::Gz,
+        CompressionFormat::Xz,
+        CompressionFormat::Zst
+    )]
+    cf: CompressionFormat,
+    #[values(128, 4096)] disk_size: usize,
+) {
+    let test = ReadTest {
+        cf,
+        buf_size: 64,
+        disk_size,
+        disk_block_size: 16,
+        checkpoint_period: 4,
+    };
+
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(disk_size as u64));
+    let mut decompressed = vec![];
+    decompress(cf, &result.file[..])
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, result.disk);
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::TotalBytes {
+            src: disk_size as u64,
+            dest: result.file.len() as u64
+        })
+    );
+}
+
+#[rstest]
+fn hash_verify_happy_path_works(#[values(0, 101, 128, 4348, 8337)] len: usize) {
+    let mut rng = SmallRng::seed_from_u64(102);
+    let disk = make_random(&mut rng, 16384);
+
+    let test = HashVerifyTest {
+        expected: sha256(&disk[..len]),
+        len: len as u64,
+        disk,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::TotalBytes {
+            src: len as u64,
+            dest: len.next_multiple_of(128) as u64
+        })
+    );
+}
+
+#[rstest]
+#[case(4231, 0)]
+#[case(4231, 834)]
+#[case(4231, 4230)]
+fn hash_verify_sad_path_works(#[case] len: usize, #[case] flip_offset: usize) {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let mut disk = make_random(&mut rng, 16000);
+    let expected = sha256(&disk[..len]);
+    disk[flip_offset] = !disk[flip_offset];
+
+    let test = HashVerifyTest {
+        expected: expected.clone(),
+        len: len as u64,
+        disk,
+    };
+    let result = test.execute();
+
+    assert_matches!(
+        result.return_val,
+        Err(WriteVerifyError::HashMismatch { expected: e, .. }) if e == expected
+    );
+}
+
+#[test]
+fn hash_verify_ignores_data_past_written_region() {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let mut disk = make_random(&mut rng, 16000);
+    let expected = sha256(&disk[..4231]);
+    disk[4231] = !disk[4231];
+
+    let test = HashVerifyTest {
+        expected,
+        len: 4231,
+        disk,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+}
+
+#[test]
+fn hash_verify_fails_if_disk_is_too_small() {
+    let mut rng = SmallRng::seed_from_u64(16);
+    let disk = make_random(&mut rng, 1024);
+
+    let test = HashVerifyTest {
+        expected: sha256(&disk),
+        len: 4096,
+        disk,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Err(WriteVerifyError::EndOfOutput));
+}
+
+#[rstest]
+fn write_op_hashes_input_inline(
+    #[values(
+        CompressionFormat::Identity,
+        CompressionFormat::Gz,
+        CompressionFormat::Zst
+    )]
+    cf: CompressionFormat,
+    #[values(false, true)] of_compressed: bool,
+    #[values(0, 1000, 4096)] raw_size: usize,
+) {
+    let test = InlineHashTest {
+        cf,
+        raw_size,
+        of_compressed,
+        corrupt_expected_hash: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(raw_size as u64));
+    assert_eq!(&result.disk[..raw_size], &result.raw[..]);
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::InputHashed {
+            hash: result.expected_hash
+        })
+    );
+}
+
+#[rstest]
+fn write_op_fails_on_inline_hash_mismatch(#[values(false, true)] of_compressed: bool) {
+    let test = InlineHashTest {
+        cf: CompressionFormat::Gz,
+        raw_size: 4096,
+        of_compressed,
+        corrupt_expected_hash: true,
+    };
+    let result = test.execute();
+
+    assert_matches!(
+        result.execute_result,
+        Err(WriteVerifyError::InputHashMismatch { expected, .. }) if expected == result.expected_hash
+    );
+    assert_matches!(
+        result.events.last(),
+        Some(WriteVerifyEvent::InputHashed { .. })
+    );
+}
+
+#[test]
+fn sparse_write_skips_zero_blocks() {
+    let test = SparseWriteTest {
+        file_size: 64 * 10,
+        zero_blocks: vec![0, 3, 4, 9],
+        hash_output: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(640));
+    assert_eq!(
+        result.skipped_regions,
+        vec![(0, 64), (192, 64), (256, 64), (576, 64)]
+    );
+    assert_eq!(result.requested_writes.len(), 6);
+    for (i, block) in result.disk[..640].chunks(64).enumerate() {
+        if test.zero_blocks.contains(&i) {
+            assert!(block.iter().all(|b| *b == 0xaa), "block {i} was written");
+        } else {
+            assert_eq!(block, &result.file[i * 64..(i + 1) * 64]);
+        }
+    }
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::TotalBytes {
+            src: 640,
+            dest: 640
+        })
+    );
+}
+
+#[rstest]
+#[case(600, 32)]
+#[case(601, 32)]
+#[case(615, 48)]
+fn sparse_write_rounds_misaligned_final_skip_to_block(
+    #[case] file_size: usize,
+    #[case] skipped_len: u64,
+) {
+    let test = SparseWriteTest {
+        file_size,
+        zero_blocks: vec![9],
+        hash_output: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(file_size as u64));
+    assert_eq!(result.skipped_regions, vec![(576, skipped_len)]);
+    assert_eq!(&result.disk[..576], &result.file[..576]);
+}
+
+#[test]
+fn output_hash_includes_skipped_zero_blocks() {
+    let test = SparseWriteTest {
+        file_size: 615,
+        zero_blocks: vec![0, 3, 9],
+        hash_output: true,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(615));
+    assert_eq!(result.skipped_regions.len(), 3);
+    let mut expected = Hasher::new(HashAlg::Sha256);
+    expected.update(&result.file);
+    assert_eq!(result.output_hash, Some(expected.finalize()));
+}
+
+#[rstest]
+#[case(false, Err(verification_failed(1500, 1, 128, &[(1408, 128)], false)))]
+#[case(true, Ok(()))]
+fn verify_ignores_garbage_in_discarded_blocks(
+    #[case] ignore_zero_blocks: bool,
+    #[case] expected: Result<(), WriteVerifyError>,
+) {
+    let mut rng = SmallRng::seed_from_u64(102);
+    let mut file = make_random(&mut rng, 4096);
+    file[1024..2048].fill(0);
+    let mut disk = file.clone();
+    disk[1500] = 0xaa;
+
+    let test = VerifyTest {
+        buf_size: 128,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 32,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks,
+        scan_all: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, expected);
+}
+
+#[test]
+fn verify_ignoring_zero_blocks_still_catches_other_corruption() {
+    let mut rng = SmallRng::seed_from_u64(102);
+    let mut file = make_random(&mut rng, 4096);
+    file[1024..2048].fill(0);
+    let mut disk = file.clone();
+    disk[3000] = !disk[3000];
+
+    let test = VerifyTest {
+        buf_size: 128,
+        file,
+        disk,
+        disk_block_size: 128,
+        checkpoint_period: 32,
+        file_read_buf_size: 8192,
+        ignore_zero_blocks: true,
+        scan_all: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(
+        result.return_val,
+        Err(verification_failed(3000, 1, 128, &[(2944, 128)], false))
+    );
+}
+
+#[test]
+fn write_and_verify_expand_android_sparse_image() {
+    let raw: Vec<u8> = (0..64).collect();
+    let image = make_image(&[
+        TestChunk::Raw(&raw),
+        TestChunk::Fill([1, 2, 3, 4], 8),
+        TestChunk::DontCare(4),
+        TestChunk::Raw(&raw[..16]),
+    ]);
+    let mut expected = raw.clone();
+    expected.extend([1, 2, 3, 4].repeat(16));
+    expected.extend([0; 32]);
+    expected.extend(&raw[..16]);
+
+    let mut disk = vec![0xaa; 256];
+    let written = WriteOp {
+        file: &image[..],
+        disk: MockWrite::new(&mut disk, 16),
+        cf: CompressionFormat::Identity,
+        archive_member: None,
+        android_sparse: true,
+        buf_size: 32,
+        disk_block_size: 16,
+        checkpoint_period: 4,
+        file_read_buf_size: 128,
+        input_hash: None,
+        skip_zeroes: None,
+        output_hash: None,
+        checkpoints: false,
+        resume_from: None,
+        file_at_checkpoint: false,
+    }
+    .execute(|_| {})
+    .unwrap();
+
+    assert_eq!(written, expected.len() as u64);
+    assert_eq!(&disk[..expected.len()], &expected[..]);
+
+    let verified = VerifyOp {
+        file: &image[..],
+        disk: &disk[..],
+        cf: CompressionFormat::Identity,
+        archive_member: None,
+        android_sparse: true,
+        buf_size: 32,
+        disk_block_size: 16,
+        checkpoint_period: 4,
+        file_read_buf_size: 128,
+        ignore_zero_blocks: false,
+        scan_all: false,
+    }
+    .execute(|_| {});
+
+    assert_eq!(verified, Ok(()));
+}
+
+#[rstest]
+fn write_and_verify_archive_member(#[values(false, true)] zip: bool) {
+    let image: Vec<u8> = (0..1000).map(|i| (i / 3) as u8).collect();
+    let (archive, members) = if zip {
+        let archive = make_zip(
+            &[
+                ("README", b"not this", true),
+                ("disk.img", &image, true),
+                ("LICENSE", b"nor this", false),
+            ],
+            false,
+        );
+        let members = list_zip(std::io::Cursor::new(&archive)).unwrap();
+        (archive, members)
+    } else {
+        let archive = make_tar(&[
+            TarEntry::File("README", b"not this"),
+            TarEntry::File("disk.img", &image),
+            TarEntry::File("LICENSE", b"nor this"),
+        ]);
+        let members = list_tar(&archive[..]).unwrap();
+        (archive, members)
+    };
+    let member = members.iter().find(|m| m.name == "disk.img").unwrap();
+
+    let mut disk = vec![0xaa; 1024];
+    let written = WriteOp {
+        file: &archive[..],
+        disk: MockWrite::new(&mut disk, 16),
+        cf: CompressionFormat::Identity,
+        archive_member: Some(member),
+        android_sparse: false,
+        buf_size: 32,
+        disk_block_size: 16,
+        checkpoint_period: 4,
+        file_read_buf_size: 128,
+        input_hash: None,
+        skip_zeroes: None,
+        output_hash: None,
+        checkpoints: false,
+        resume_from: None,
+        file_at_checkpoint: false,
+    }
+    .execute(|_| {})
+    .unwrap();
+
+    assert_eq!(written, image.len() as u64);
+    assert_eq!(&disk[..image.len()], &image[..]);
+
+    let verified = VerifyOp {
+        file: &archive[..],
+        disk: &disk[..],
+        cf: CompressionFormat::Identity,
+        archive_member: Some(member),
+        android_sparse: false,
+        buf_size: 32,
+        disk_block_size: 16,
+        checkpoint_period: 4,
+        file_read_buf_size: 128,
+        ignore_zero_blocks: false,
+        scan_all: false,
+    }
+    .execute(|_| {});
+
+    assert_eq!(verified, Ok(()));
+}
+
+#[rstest]
+#[case(CompressionFormat::Identity, false)]
+#[case(CompressionFormat::Identity, true)]
+#[case(CompressionFormat::Gz, false)]
+#[case(CompressionFormat::Zst, false)]
+fn write_resumes_from_checkpoint(#[case] cf: CompressionFormat, #[case] file_at_checkpoint: bool) {
+    let test = ResumeTest {
+        cf,
+        file_at_checkpoint,
+        resume_at: 2,
+        change_image: false,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.execute_result, Ok(result.raw.len() as u64));
+    assert_eq!(&result.disk[..result.raw.len()], &result.raw[..]);
+    // Later checkpoints come out the same as if we had never stopped
+    let positions = |cps: &[WriteCheckpoint]| {
+        cps.iter()
+            .map(|cp| {
+                (
+                    cp.decompressed_offset,
+                    cp.dest_offset,
+                    cp.written_hash.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        positions(&result.resumed_checkpoints),
+        positions(&result.first_checkpoints[test.resume_at + 1..])
+    );
+}
+
+#[test]
+fn write_refuses_to_resume_if_image_changed() {
+    let test = ResumeTest {
+        cf: CompressionFormat::Gz,
+        file_at_checkpoint: false,
+        resume_at: 1,
+        change_image: true,
+    };
+    let result = test.execute();
+
+    assert_eq!(
+        result.execute_result,
+        Err(WriteVerifyError::CheckpointMismatch)
+    );
+}
+
+#[rstest]
+#[case(None, Ok(()))]
+#[case(Some(100), Err(WriteVerifyError::CheckpointMismatch))]
+#[case(Some(511), Err(WriteVerifyError::CheckpointMismatch))]
+#[case(Some(512), Ok(()))]
+fn check_written_compares_prefix_of_disk(
+    #[case] flip_offset: Option<usize>,
+    #[case] expected: Result<(), WriteVerifyError>,
+) {
+    let mut disk: Vec<u8> = (0..1024).map(|i| (i * 7) as u8).collect();
+    let cp = WriteCheckpoint {
+        src_offset: 512,
+        decompressed_offset: 512,
+        dest_offset: 512,
+        written_hash: ExpectedHash {
+            alg: HashAlg::Sha256,
+            hash: {
+                let mut h = Hasher::new(HashAlg::Sha256);
+                h.update(&disk[..512]);
+                h.finalize()
+            },
+        },
+    };
+    if let Some(o) = flip_offset {
+        disk[o] = !disk[o];
+    }
+
+    let result = check_written(&disk[..], &cp, 64, 16, 4);
+
+    assert_eq!(
+        result.map(|h| h.finalize()),
+        expected.map(|()| cp.written_hash.hash)
+    );
+}
+
+#[test]
+fn check_written_fails_if_disk_is_too_small() {
+    let cp = WriteCheckpoint {
+        src_offset: 512,
+        decompressed_offset: 512,
+        dest_offset: 512,
+        written_hash: ExpectedHash {
+            alg: HashAlg::Sha256,
+            hash: vec![0; 32],
+        },
+    };
+
+    assert_matches!(
+        check_written(&[0u8; 256][..], &cp, 64, 16, 4).map(|h| h.finalize()),
+        Err(WriteVerifyError::CheckpointMismatch)
+    );
+}
+
+#[rstest]
+fn wipe_fills_and_verifies(#[values(WipeMode::Zero, WipeMode::Random)] mode: WipeMode) {
+    let test = WipeTest {
+        mode,
+        disk_size: 1008,
+        corrupt_at: None,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.wipe_result, Ok(()));
+    assert_eq!(result.verify_result, Some(Ok(())));
+    assert!(result.skipped_regions.is_empty());
+    match mode {
+        WipeMode::Zero => assert!(result.disk.iter().all(|b| *b == 0)),
+        _ => assert!(result.disk.chunks(16).all(|b| b != [0xaa; 16])),
+    }
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::TotalBytes {
+            src: 1008,
+            dest: 1008
+        })
+    );
+}
+
+#[rstest]
+fn wipe_verification_catches_corruption(
+    #[values(WipeMode::Zero, WipeMode::Random)] mode: WipeMode,
+) {
+    let test = WipeTest {
+        mode,
+        disk_size: 1008,
+        corrupt_at: Some(700),
+    };
+    let result = test.execute();
+
+    assert_eq!(result.wipe_result, Ok(()));
+    assert_eq!(
+        result.verify_result,
+        Some(Err(verification_failed(700, 1, 16, &[(688, 16)], false)))
+    );
+}
+
+#[rstest]
+fn wipe_discards_whole_disk(#[values(WipeMode::Discard, WipeMode::SecureDiscard)] mode: WipeMode) {
+    let test = WipeTest {
+        mode,
+        disk_size: 1008,
+        corrupt_at: None,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.wipe_result, Ok(()));
+    assert_eq!(result.verify_result, None);
+    assert_eq!(
+        result.skipped_regions,
+        vec![(0, 256), (256, 256), (512, 256), (768, 240)]
+    );
+    assert!(result.disk.iter().all(|b| *b == 0xaa), "disk was written");
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::TotalBytes {
+            src: 1008,
+            dest: 1008
+        })
+    );
+}
+
+#[rstest]
+fn repair_rewrites_only_mismatched_regions(
+    #[values(CompressionFormat::Identity, CompressionFormat::Gz)] cf: CompressionFormat,
+) {
+    let test = RepairTest {
+        cf,
+        corrupt_at: vec![100, 1000, 2596],
+        stuck: vec![],
+        dropped_writes: 0,
+        kept_ranges: None,
+        discarded: vec![],
+        attempts: 3,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+    assert_eq!(&result.disk[..result.raw.len()], &result.raw[..]);
+    assert_eq!(result.writes, vec![(64, 64), (960, 64), (2560, 64)]);
+    assert_eq!(
+        result.events,
+        vec![WriteVerifyEvent::Repairing {
+            attempt: 1,
+            bad_bytes: 64 + 64 + 37
+        }]
+    );
+}
+
+#[test]
+fn repair_retries_dropped_writes() {
+    let test = RepairTest {
+        cf: CompressionFormat::Gz,
+        corrupt_at: vec![100, 1000, 2596],
+        stuck: vec![],
+        dropped_writes: 2,
+        kept_ranges: None,
+        discarded: vec![],
+        attempts: 3,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+    assert_eq!(&result.disk[..result.raw.len()], &result.raw[..]);
+    assert_eq!(
+        result.events,
+        vec![
+            WriteVerifyEvent::Repairing {
+                attempt: 1,
+                bad_bytes: 165
+            },
+            WriteVerifyEvent::Repairing {
+                attempt: 2,
+                bad_bytes: 128
+            },
+        ]
+    );
+}
+
+#[test]
+fn repair_gives_up_on_regions_that_never_stick() {
+    let test = RepairTest {
+        cf: CompressionFormat::Identity,
+        corrupt_at: vec![100, 1000, 2596],
+        stuck: vec![(960, 64)],
+        dropped_writes: 0,
+        kept_ranges: None,
+        discarded: vec![],
+        attempts: 3,
+    };
+    let result = test.execute();
+
+    assert_eq!(
+        result.return_val,
+        Err(WriteVerifyError::RepairFailed {
+            attempts: 3,
+            unfixed: mismatches(1000, 1, 64, &[(960, 64)], true)
+        })
+    );
+    assert_eq!(&result.disk[..960], &result.raw[..960]);
+    assert_eq!(&result.disk[1024..result.raw.len()], &result.raw[1024..]);
+    assert_eq!(
+        result.events.last(),
+        Some(&WriteVerifyEvent::Repairing {
+            attempt: 3,
+            bad_bytes: 64
+        })
+    );
+}
+
+#[test]
+fn repair_finds_regions_that_were_not_kept_track_of() {
+    let test = RepairTest {
+        cf: CompressionFormat::Gz,
+        corrupt_at: vec![100, 1000, 2596],
+        stuck: vec![],
+        dropped_writes: 0,
+        kept_ranges: Some(1),
+        discarded: vec![],
+        attempts: 3,
+    };
+    let result = test.execute();
+
+    assert_eq!(result.return_val, Ok(()));
+    assert_eq!(&result.disk[..result.raw.len()], &result.raw[..]);
+    assert_eq!(
+        result.events,
+        vec![
+            WriteVerifyEvent::Repairing {
+                attempt: 1,
+                bad_bytes: 64
+            },
+            WriteVerifyEvent::Repairing {
+                attempt: 2,
+                bad_bytes: 64 + 37
+            },
+        ]
+    );
+}
+
+#[test]
+fn repair_leaves_discarded_regions_alone() {
+    let test = RepairTest {
+        cf: CompressionFormat::Gz,
+        corrupt_at: vec![100, 2596],
+        stuck: vec![],
+        dropped_writes: 0,
+        kept_ranges: Some(1),
+        discarded: vec![(512, 512)],
+        attempts: 3,
     };
     let result = test.execute();
 
     assert_eq!(result.return_val, Ok(()));
+    assert_eq!(result.writes, vec![(64, 64), (2560, 64)]);
+    assert_eq!(
+        result.events,
+        vec![
+            WriteVerifyEvent::Repairing {
+                attempt: 1,
+                bad_bytes: 64
+            },
+            WriteVerifyEvent::Repairing {
+                attempt: 2,
+                bad_bytes: 37
+            },
+        ]
+    );
 }
 
-#[rstest]
-#[case(4231, 0)]
-#[case(4231, 1)]
-#[case(4231, 834)]
-#[case(4231, 4210)]
-#[case(4231, 4213)]
-#[case(4232, 4231)]
-fn verify_misaligned_case_sad_path_works(#[case] file_size: usize, #[case] flip_offset: usize) {
-    let mut rng = SmallRng::seed_from_u64(16);
-    let file = make_random(&mut rng, file_size);
-    let mut disk = make_random(&mut rng, 16000);
-    disk[..file_size].copy_from_slice(&file);
-    disk[flip_offset] = !disk[flip_offset];
+#[test]
+fn mismatch_finder_counts_ranges_past_the_ones_it_keeps() {
+    let expected = vec![0u8; 3 * Mismatches::MAX_BAD_RANGES * 2];
+    // Every other byte differs, except for a run of 3 right at the end
+    let mut actual: Vec<u8> = (0..expected.len()).map(|i| (i % 2) as u8).collect();
+    let len = actual.len();
+    actual[len - 3..].fill(1);
 
-    let test = VerifyTest {
-        buf_size: 128,
-        file,
-        disk,
-        disk_block_size: 128,
-        checkpoint_period: 25,
-        file_read_buf_size: 8192,
+    let mut finder = MismatchFinder::new(1);
+    for (i, (e, a)) in expected.chunks(100).zip(actual.chunks(100)).enumerate() {
+        finder.compare(i as u64 * 100, e, a);
+    }
+    let Err(WriteVerifyError::VerificationFailed(m)) = finder.finish(true) else {
+        panic!("mismatches should have been found");
     };
-    let result = test.execute();
 
-    assert_eq!(result.return_val, Err(WriteVerifyError::VerificationFailed));
+    assert_eq!(m.bad_ranges.len(), Mismatches::MAX_BAD_RANGES);
+    assert_eq!(m.bad_ranges[1], ByteRange { offset: 3, len: 1 });
+    assert_eq!(m.bad_range_count, 3 * Mismatches::MAX_BAD_RANGES as u64 - 1);
+    assert_eq!(m.bad_blocks, 3 * Mismatches::MAX_BAD_RANGES as u64 + 1);
+    assert_eq!(m.bad_bytes, m.bad_blocks);
+    assert!(m.is_truncated());
+    assert!(
+        m.to_string()
+            .contains("\n3073 blocks (3073 bytes) differ in 3071 ranges:\n  bytes 1..2 (1 bytes)")
+    );
+    assert!(m.to_string().ends_with("\n  ... and 3061 more"));
 }
 
 /// Helpers for these tests. These go in their own little module to enforce
 /// visibility.
 mod helpers {
-    use std::io::{self, Cursor, Read, Write};
+    use std::io::{self, Cursor, Read, Seek, Write};
 
     use rand::{Rng, SeedableRng, rngs::SmallRng};
 
     use super::{
-        CompressionFormat, VerifyOp, WriteOp,
-        ipc::{WriteVerifyError, WriteVerifyEvent},
+        CompressionFormat, CompressionLevel, HashVerifyOp, ReadOp, RepairOp, SkipHow, SkipRegion,
+        VerifyOp, WriteOp,
+        ipc::{
+            ByteRange, Mismatches, WipeMode, WriteCheckpoint, WriteVerifyError, WriteVerifyEvent,
+        },
+        wipe::{Fill, WipeOp, WipeVerifyOp},
     };
+    use crate::compression::compress;
+    use crate::hash::{ExpectedHash, HashAlg, Hasher};
+    use crate::herder_daemon::ipc::ImageHash;
 
     /// Wraps an in-memory buffer and logs every single chunk of data written to it.
-    struct MockWrite<'a> {
+    pub struct MockWrite<'a> {
         cursor: Cursor<&'a mut [u8]>,
         requested_writes: Vec<Vec<u8>>,
+        /// Every region skipped, as (offset, length)
+        skipped_regions: Vec<(u64, u64)>,
         enforced_block_size: usize,
     }
 
@@ -268,22 +1144,37 @@ mod helpers {
             Self {
                 cursor: Cursor::new(data),
                 requested_writes: vec![],
+                skipped_regions: vec![],
                 enforced_block_size,
             }
         }
     }
 
+    impl<'a> SkipRegion for MockWrite<'a> {
+        fn skip_region(&mut self, len: u64, _how: SkipHow) -> io::Result<()> {
+            assert!(
+                len.is_multiple_of(self.enforced_block_size as u64),
+                "Received a skip (size {len} = {len:#x}) that was not aligned to block (size {bs} = {bs:#x})!",
+                bs = self.enforced_block_size,
+            );
+            let offset = self.cursor.position();
+            self.skipped_regions.push((offset, len));
+            self.cursor.seek(io::SeekFrom::Current(len as i64))?;
+            Ok(())
+        }
+    }
+
     impl<'a> Write for MockWrite<'a> {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
             assert!(
-                buf.len() % self.enforced_block_size == 0,
+                buf.len().is_multiple_of(self.enforced_block_size),
                 "Received a write (size {len} = {len:#x}) that was not aligned to block (size {bs} = {bs:#x})!",
                 len = buf.len(),
                 bs = self.enforced_block_size,
             );
             let addr = buf.as_ptr();
             assert!(
-                addr as usize % self.enforced_block_size == 0,
+                (addr as usize).is_multiple_of(self.enforced_block_size),
                 "Received a write from address {len:?} that was not aligned to block (size {bs} = {bs:#x})!",
                 len = addr,
                 bs = self.enforced_block_size,
@@ -318,13 +1209,13 @@ mod helpers {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
             if let Some(bs) = &self.enforced_block_size {
                 assert!(
-                    buf.len() % bs == 0,
+                    buf.len().is_multiple_of(*bs),
                     "Received a read (size {len} = {len:#x}) that was not aligned to blocks (size {bs} = {bs:#x})!",
                     len = buf.len(),
                     bs = bs,
                 );
                 assert!(
-                    ((&buf[0] as *const u8) as usize) % bs == 0,
+                    ((&buf[0] as *const u8) as usize).is_multiple_of(*bs),
                     "Received a read to address {len:?} that was not aligned to block (size {bs} = {bs:#x})!",
                     len = &buf[0] as *const u8,
                     bs = bs,
@@ -367,10 +1258,18 @@ mod helpers {
                 file: &mut file,
                 disk: &mut disk,
                 cf: CompressionFormat::Identity,
+                archive_member: None,
+                android_sparse: false,
                 buf_size: self.buf_size,
                 disk_block_size: self.disk_block_size,
                 checkpoint_period: self.checkpoint_period,
                 file_read_buf_size: self.file_read_buf_size,
+                input_hash: None,
+                skip_zeroes: None,
+                output_hash: None,
+                checkpoints: false,
+                resume_from: None,
+                file_at_checkpoint: false,
             }
             .execute(|e| events.push(e));
 
@@ -394,6 +1293,146 @@ mod helpers {
         }
     }
 
+    /// Writes a (possibly compressed) random file of `raw_size` bytes while hashing it
+    /// inline with SHA-256.
+    pub struct InlineHashTest {
+        pub cf: CompressionFormat,
+        pub raw_size: usize,
+        /// If true, hash the compressed file rather than the raw data
+        pub of_compressed: bool,
+        /// If true, give WriteOp a hash that will not match
+        pub corrupt_expected_hash: bool,
+    }
+
+    pub struct InlineHashTestResult {
+        pub raw: Vec<u8>,
+        pub disk: Vec<u8>,
+        pub expected_hash: Vec<u8>,
+        pub events: Vec<WriteVerifyEvent>,
+        pub execute_result: Result<u64, WriteVerifyError>,
+    }
+
+    impl InlineHashTest {
+        pub fn execute(&self) -> InlineHashTestResult {
+            let mut events = vec![];
+
+            let mut rng = SmallRng::seed_from_u64(16);
+            let raw = make_random(&mut rng, self.raw_size);
+            let mut compressor = compress(self.cf, CompressionLevel::Fast, vec![]).unwrap();
+            compressor.write_all(&raw).unwrap();
+            let file_data = compressor.finish().unwrap();
+
+            let mut expected_hash = sha256(if self.of_compressed { &file_data } else { &raw });
+            if self.corrupt_expected_hash {
+                expected_hash[0] = !expected_hash[0];
+            }
+            let input_hash = ImageHash {
+                expected: ExpectedHash {
+                    alg: HashAlg::Sha256,
+                    hash: expected_hash.clone(),
+                },
+                of_compressed: self.of_compressed,
+            };
+
+            let mut file = MockRead::new(&file_data, None);
+            let mut disk_data = vec![0; self.raw_size.next_multiple_of(64) + 64];
+            let mut disk = MockWrite::new(&mut disk_data, 16);
+
+            let execute_result = WriteOp {
+                file: &mut file,
+                disk: &mut disk,
+                cf: self.cf,
+                archive_member: None,
+                android_sparse: false,
+                buf_size: 64,
+                disk_block_size: 16,
+                checkpoint_period: 4,
+                file_read_buf_size: 128,
+                input_hash: Some(&input_hash),
+                skip_zeroes: None,
+                output_hash: None,
+                checkpoints: false,
+                resume_from: None,
+                file_at_checkpoint: false,
+            }
+            .execute(|e| events.push(e));
+
+            InlineHashTestResult {
+                raw,
+                disk: disk_data,
+                expected_hash,
+                events,
+                execute_result,
+            }
+        }
+    }
+
+    /// Writes a file made of 64-byte blocks, where the blocks in `zero_blocks` are
+    /// zeroes and the rest are random, while skipping blocks of zeroes.
+    pub struct SparseWriteTest {
+        pub file_size: usize,
+        pub zero_blocks: Vec<usize>,
+        pub hash_output: bool,
+    }
+
+    pub struct SparseWriteTestResult {
+        pub requested_writes: Vec<Vec<u8>>,
+        pub skipped_regions: Vec<(u64, u64)>,
+        pub file: Vec<u8>,
+        pub disk: Vec<u8>,
+        pub events: Vec<WriteVerifyEvent>,
+        pub execute_result: Result<u64, WriteVerifyError>,
+        pub output_hash: Option<Vec<u8>>,
+    }
+
+    impl SparseWriteTest {
+        pub fn execute(&self) -> SparseWriteTestResult {
+            let mut events = vec![];
+
+            let mut rng = SmallRng::seed_from_u64(16);
+            let mut file_data = make_random(&mut rng, self.file_size);
+            for &b in &self.zero_blocks {
+                let end = ((b + 1) * 64).min(self.file_size);
+                file_data[b * 64..end].fill(0);
+            }
+            let mut file = MockRead::new(&file_data, None);
+            // Fill the disk with something other than zeroes, so we can tell if a block
+            // was written
+            let mut disk_data = vec![0xaa; self.file_size.next_multiple_of(64) + 64];
+            let mut disk = MockWrite::new(&mut disk_data, 16);
+            let mut output_hash = self.hash_output.then(|| Hasher::new(HashAlg::Sha256));
+
+            let execute_result = WriteOp {
+                file: &mut file,
+                disk: &mut disk,
+                cf: CompressionFormat::Identity,
+                archive_member: None,
+                android_sparse: false,
+                buf_size: 64,
+                disk_block_size: 16,
+                checkpoint_period: 4,
+                file_read_buf_size: 128,
+                input_hash: None,
+                skip_zeroes: Some(SkipHow::ZeroOut),
+                output_hash: output_hash.as_mut(),
+                checkpoints: false,
+                resume_from: None,
+                file_at_checkpoint: false,
+            }
+            .execute(|e| events.push(e));
+
+            SparseWriteTestResult {
+                requested_writes: disk.requested_writes,
+                skipped_regions: disk.skipped_regions,
+                file: file_data,
+                disk: disk_data,
+                events,
+                execute_result,
+                output_hash: output_hash.map(|h| h.finalize()),
+            }
+        }
+    }
+
     pub struct VerifyTest {
         pub buf_size: usize,
         pub file: Vec<u8>,
@@ -401,6 +1440,8 @@ mod helpers {
         pub disk_block_size: usize,
         pub checkpoint_period: usize,
         pub file_read_buf_size: usize,
+        pub ignore_zero_blocks: bool,
+        pub scan_all: bool,
     }
 
     pub struct VerifyTestResult {
@@ -421,10 +1462,14 @@ mod helpers {
                 file: &mut file,
                 disk: &mut disk,
                 cf: CompressionFormat::Identity,
+                archive_member: None,
+                android_sparse: false,
                 buf_size: self.buf_size,
                 disk_block_size: self.disk_block_size,
                 checkpoint_period: self.checkpoint_period,
                 file_read_buf_size: self.file_read_buf_size,
+                ignore_zero_blocks: self.ignore_zero_blocks,
+                scan_all: self.scan_all,
             }
             .execute(|e| events.push(e));
 
@@ -437,9 +1482,454 @@ mod helpers {
         }
     }
 
+    pub fn sha256(data: &[u8]) -> Vec<u8> {
+        use sha2::Digest;
+        sha2::Sha256::digest(data).to_vec()
+    }
+
+    /// Hashes the first `len` bytes of `disk` with SHA-256, in blocks of 128.
+    pub struct HashVerifyTest {
+        pub expected: Vec<u8>,
+        pub len: u64,
+        pub disk: Vec<u8>,
+    }
+
+    pub struct HashVerifyTestResult {
+        pub events: Vec<WriteVerifyEvent>,
+        pub return_val: Result<(), WriteVerifyError>,
+    }
+
+    impl HashVerifyTest {
+        pub fn execute(&self) -> HashVerifyTestResult {
+            let mut events = vec![];
+
+            let mut disk = MockRead::new(&self.disk, Some(128));
+            let expected = ExpectedHash {
+                alg: HashAlg::Sha256,
+                hash: self.expected.clone(),
+            };
+
+            let return_val = HashVerifyOp {
+                disk: &mut disk,
+                expected: &expected,
+                len: self.len,
+                buf_size: 128,
+                disk_block_size: 128,
+                checkpoint_period: 4,
+            }
+            .execute(|e| events.push(e));
+
+            HashVerifyTestResult { events, return_val }
+        }
+    }
+
+    pub struct ReadTest {
+        pub cf: CompressionFormat,
+        pub buf_size: usize,
+        pub disk_size: usize,
+        pub disk_block_size: usize,
+        pub checkpoint_period: usize,
+    }
+
+    pub struct ReadTestResult {
+        pub disk: Vec<u8>,
+        pub file: Vec<u8>,
+        pub events: Vec<WriteVerifyEvent>,
+        pub execute_result: Result<u64, WriteVerifyError>,
+    }
+
+    impl ReadTest {
+        pub fn execute(&self) -> ReadTestResult {
+            let mut events = vec![];
+
+            let mut rng = SmallRng::seed_from_u64(16);
+            let disk_data = make_random(&mut rng, self.disk_size);
+            let mut disk = MockRead::new(&disk_data, Some(self.disk_block_size));
+            let mut file = vec![];
+
+            let execute_result = ReadOp {
+                disk: &mut disk,
+                file: &mut file,
+                cf: self.cf,
+                level: CompressionLevel::Fast,
+                buf_size: self.buf_size,
+                disk_block_size: self.disk_block_size,
+                checkpoint_period: self.checkpoint_period,
+            }
+            .execute(|e| events.push(e));
+
+            ReadTestResult {
+                disk: disk_data,
+                file,
+                events,
+                execute_result,
+            }
+        }
+    }
+
+    pub struct ResumeTest {
+        pub cf: CompressionFormat,
+        /// If true, the file is positioned at the checkpoint rather than read from the
+        /// start when resuming
+        pub file_at_checkpoint: bool,
+        /// Which checkpoint of the first write to resume from
+        pub resume_at: usize,
+        /// If true, the image is changed before resuming
+        pub change_image: bool,
+    }
+
+    pub struct ResumeTestResult {
+        pub raw: Vec<u8>,
+        pub disk: Vec<u8>,
+        pub first_checkpoints: Vec<WriteCheckpoint>,
+        pub resumed_checkpoints: Vec<WriteCheckpoint>,
+        pub execute_result: Result<u64, WriteVerifyError>,
+    }
+
+    impl ResumeTest {
+        pub fn execute(&self) -> ResumeTestResult {
+            let mut rng = SmallRng::seed_from_u64(16);
+            let mut raw = make_random(&mut rng, 64 * 40 + 37);
+            let mut disk_data = vec![0xaa; raw.len().next_multiple_of(64) + 64];
+            let (_, first_checkpoints) = self.write(
+                &self.compress(&raw),
+                &mut disk_data,
+                &mut Hasher::new(HashAlg::Sha256),
+                None,
+            );
+
+            // Pretend we got interrupted right after the checkpoint
+            let cp = first_checkpoints[self.resume_at].clone();
+            let dest = cp.dest_offset as usize;
+            disk_data[dest..].fill(0xaa);
+            if self.change_image {
+                raw[10] = !raw[10];
+            }
+            let file_data = self.compress(&raw);
+            let file = if self.file_at_checkpoint {
+                &file_data[cp.decompressed_offset as usize..]
+            } else {
+                &file_data[..]
+            };
+
+            let mut output_hash = Hasher::new(HashAlg::Sha256);
+            output_hash.update(&disk_data[..dest]);
+            let (execute_result, resumed_checkpoints) =
+                self.write(file, &mut disk_data[dest..], &mut output_hash, Some(&cp));
+
+            ResumeTestResult {
+                raw,
+                disk: disk_data,
+                first_checkpoints,
+                resumed_checkpoints,
+                execute_result,
+            }
+        }
+
+        fn compress(&self, raw: &[u8]) -> Vec<u8> {
+            let mut compressor = compress(self.cf, CompressionLevel::Fast, vec![]).unwrap();
+            compressor.write_all(raw).unwrap();
+            compressor.finish().unwrap()
+        }
+
+        fn write(
+            &self,
+            file: &[u8],
+            disk: &mut [u8],
+            output_hash: &mut Hasher,
+            resume_from: Option<&WriteCheckpoint>,
+        ) -> (Result<u64, WriteVerifyError>, Vec<WriteCheckpoint>) {
+            let mut checkpoints = vec![];
+            let result = WriteOp {
+                file,
+                disk: MockWrite::new(disk, 16),
+                cf: self.cf,
+                archive_member: None,
+                android_sparse: false,
+                buf_size: 64,
+                disk_block_size: 16,
+                checkpoint_period: 4,
+                file_read_buf_size: 128,
+                input_hash: None,
+                skip_zeroes: None,
+                output_hash: Some(output_hash),
+                checkpoints: true,
+                resume_from,
+                file_at_checkpoint: resume_from.is_some() && self.file_at_checkpoint,
+            }
+            .execute(|e| {
+                if let WriteVerifyEvent::Checkpoint(cp) = e {
+                    checkpoints.push(cp);
+                }
+            });
+            (result, checkpoints)
+        }
+    }
+
+    /// Wipes a disk that starts out filled with `0xaa`, then corrupts the byte at
+    /// `corrupt_at` if given, then verifies the disk if the mode can be verified.
+    pub struct WipeTest {
+        pub mode: WipeMode,
+        pub disk_size: usize,
+        pub corrupt_at: Option<usize>,
+    }
+
+    pub struct WipeTestResult {
+        pub disk: Vec<u8>,
+        pub skipped_regions: Vec<(u64, u64)>,
+        pub events: Vec<WriteVerifyEvent>,
+        pub wipe_result: Result<(), WriteVerifyError>,
+        pub verify_result: Option<Result<(), WriteVerifyError>>,
+    }
+
+    impl WipeTest {
+        pub fn execute(&self) -> WipeTestResult {
+            let mut events = vec![];
+            let seed = [7; 32];
+
+            let mut disk_data = vec![0xaa; self.disk_size];
+            let mut disk = MockWrite::new(&mut disk_data, 16);
+            let wipe_result = WipeOp {
+                disk: &mut disk,
+                fill: Fill::new(self.mode, seed),
+                len: self.disk_size as u64,
+                buf_size: 64,
+                disk_block_size: 16,
+                checkpoint_period: 4,
+            }
+            .execute(|e| events.push(e));
+            let skipped_regions = disk.skipped_regions;
+
+            if let Some(i) = self.corrupt_at {
+                disk_data[i] ^= 0xff;
+            }
+            let verify_result = self.mode.can_verify().then(|| {
+                WipeVerifyOp {
+                    disk: MockRead::new(&disk_data, Some(16)),
+                    fill: Fill::new(self.mode, seed),
+                    len: self.disk_size as u64,
+                    buf_size: 64,
+                    disk_block_size: 16,
+                    checkpoint_period: 4,
+                }
+                .execute(|e| events.push(e))
+            });
+
+            WipeTestResult {
+                disk: disk_data,
+                skipped_regions,
+                events,
+                wipe_result,
+                verify_result,
+            }
+        }
+    }
+
+    /// An in-memory disk that loses some of what is written to it.
+    pub struct FlakyDisk {
+        cursor: Cursor<Vec<u8>>,
+        /// Regions, as (offset, length), that writes never change
+        stuck: Vec<(u64, u64)>,
+        /// How many of the next writes get lost entirely
+        dropped_writes: usize,
+        /// Every write, as (offset, length)
+        writes: Vec<(u64, usize)>,
+        enforced_block_size: usize,
+    }
+
+    impl FlakyDisk {
+        fn assert_aligned(&self, len: usize) {
+            let (offset, bs) = (self.cursor.position(), self.enforced_block_size);
+            assert!(
+                offset.is_multiple_of(bs as u64) && len.is_multiple_of(bs),
+                "Received an access (offset {offset}, size {len}) that was not aligned to block (size {bs})!",
+            );
+        }
+    }
+
+    impl Read for FlakyDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.assert_aligned(buf.len());
+            self.cursor.read(buf)
+        }
+    }
+
+    impl Write for FlakyDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.assert_aligned(buf.len());
+            let offset = self.cursor.position();
+            self.writes.push((offset, buf.len()));
+            if self.dropped_writes > 0 {
+                self.dropped_writes -= 1;
+                self.cursor.seek(io::SeekFrom::Current(buf.len() as i64))?;
+                return Ok(buf.len());
+            }
+            let data = self.cursor.get_mut();
+            for (i, b) in buf.iter().enumerate() {
+                let at = offset + i as u64;
+                if !(self.stuck.iter()).any(|&(o, l)| (o..o + l).contains(&at)) {
+                    data[at as usize] = *b;
+                }
+            }
+            self.cursor.seek(io::SeekFrom::Current(buf.len() as i64))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FlakyDisk {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    /// Writes an image of 64 * 40 + 37 bytes to a disk, corrupts it, and repairs it in
+    /// blocks of 64.
+    pub struct RepairTest {
+        pub cf: CompressionFormat,
+        /// Offsets of bytes to flip on the disk before repairing it
+        pub corrupt_at: Vec<usize>,
+        /// Regions, as (offset, length), that writes never change
+        pub stuck: Vec<(u64, u64)>,
+        /// How many of the first writes get lost entirely
+        pub dropped_writes: usize,
+        /// If provided, verifying only kept track of this many of the bad ranges
+        pub kept_ranges: Option<usize>,
+        /// Regions, as (offset, length), that are zeroes in the image and were
+        /// discarded rather than written, so the disk has garbage there
+        pub discarded: Vec<(usize, usize)>,
+        pub attempts: u32,
+    }
+
+    pub struct RepairTestResult {
+        pub raw: Vec<u8>,
+        pub disk: Vec<u8>,
+        /// Every write, as (offset, length)
+        pub writes: Vec<(u64, usize)>,
+        pub events: Vec<WriteVerifyEvent>,
+        pub return_val: Result<(), WriteVerifyError>,
+    }
+
+    impl RepairTest {
+        pub fn execute(&self) -> RepairTestResult {
+            let mut rng = SmallRng::seed_from_u64(16);
+            let mut raw = make_random(&mut rng, 64 * 40 + 37);
+            for &(offset, len) in &self.discarded {
+                raw[offset..offset + len].fill(0);
+            }
+            let mut compressor = compress(self.cf, CompressionLevel::Fast, vec![]).unwrap();
+            compressor.write_all(&raw).unwrap();
+            let file_data = compressor.finish().unwrap();
+
+            let mut disk_data = make_random(&mut rng, raw.len().next_multiple_of(64) + 64);
+            disk_data[..raw.len()].copy_from_slice(&raw);
+            for &(offset, len) in &self.discarded {
+                rng.fill(&mut disk_data[offset..offset + len]);
+            }
+            for &i in &self.corrupt_at {
+                disk_data[i] = !disk_data[i];
+            }
+
+            let verified = VerifyOp {
+                file: &file_data[..],
+                disk: MockRead::new(&disk_data, Some(64)),
+                cf: self.cf,
+                archive_member: None,
+                android_sparse: false,
+                buf_size: 256,
+                disk_block_size: 64,
+                checkpoint_period: 4,
+                file_read_buf_size: 8192,
+                ignore_zero_blocks: !self.discarded.is_empty(),
+                scan_all: true,
+            }
+            .execute(|_| {});
+            let Err(WriteVerifyError::VerificationFailed(mut mismatches)) = verified else {
+                panic!("the corrupted disk should not have verified");
+            };
+            if let Some(n) = self.kept_ranges {
+                mismatches.bad_ranges.truncate(n);
+            }
+
+            let mut events = vec![];
+            let mut disk = FlakyDisk {
+                cursor: Cursor::new(disk_data),
+                stuck: self.stuck.clone(),
+                dropped_writes: self.dropped_writes,
+                writes: vec![],
+                enforced_block_size: 64,
+            };
+            let return_val = RepairOp {
+                file: Cursor::new(&file_data),
+                disk: &mut disk,
+                cf: self.cf,
+                archive_member: None,
+                android_sparse: false,
+                buf_size: 256,
+                disk_block_size: 64,
+                file_read_buf_size: 8192,
+                ignore_zero_blocks: !self.discarded.is_empty(),
+                attempts: self.attempts,
+            }
+            .execute(mismatches, |e| events.push(e));
+
+            RepairTestResult {
+                raw,
+                disk: disk.cursor.into_inner(),
+                writes: disk.writes,
+                events,
+                return_val,
+            }
+        }
+    }
+
     pub fn make_random(mut rng: impl Rng, n: usize) -> Vec<u8> {
         let mut dest = vec![0; n];
         rng.fill_bytes(&mut dest);
         dest
     }
+
+    /// Where a disk differs, with `bad_ranges` given as (offset, len).
+    pub fn mismatches(
+        first_offset: u64,
+        first_block_differing_bytes: u64,
+        block_size: u64,
+        bad_ranges: &[(u64, u64)],
+        scanned_all: bool,
+    ) -> Mismatches {
+        Mismatches {
+            first_offset,
+            first_block_differing_bytes,
+            block_size,
+            bad_ranges: (bad_ranges.iter())
+                .map(|&(offset, len)| ByteRange { offset, len })
+                .collect(),
+            bad_range_count: bad_ranges.len() as u64,
+            bad_blocks: (bad_ranges.iter())
+                .map(|(_, len)| len.div_ceil(block_size))
+                .sum(),
+            bad_bytes: bad_ranges.iter().map(|(_, len)| len).sum(),
+            scanned_all,
+        }
+    }
+
+    /// The error for a disk that differs like [mismatches] says.
+    pub fn verification_failed(
+        first_offset: u64,
+        first_block_differing_bytes: u64,
+        block_size: u64,
+        bad_ranges: &[(u64, u64)],
+        scanned_all: bool,
+    ) -> WriteVerifyError {
+        WriteVerifyError::VerificationFailed(mismatches(
+            first_offset,
+            first_block_differing_bytes,
+            block_size,
+            bad_ranges,
+            scanned_all,
+        ))
+    }
 }
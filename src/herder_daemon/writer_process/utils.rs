@@ -1,9 +1,125 @@
 use std::{
     fs::File,
-    io::{BufReader, Read, Seek, Write},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
 };
 
+use crate::android_sparse::SparseImageRead;
+use crate::archive::{ArchiveMember, ArchiveMemberRead};
 use crate::compression::{CompressionFormat, DecompressRead, decompress};
+use crate::hash::Hasher;
+
+use super::ipc::{ByteRange, ImageHash, Mismatches, WriteVerifyError};
+use super::xplat::{discard_range, secure_discard_range, zero_out_range};
+
+/// What to do with a region of a disk that we are skipping over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipHow {
+    /// Just move past it. This is only correct for freshly-created files, where the
+    /// skipped region becomes a hole that reads back as zeroes.
+    Seek,
+    /// Ask the disk to zero it out.
+    ZeroOut,
+    /// Tell the disk it is unused.
+    Discard,
+    /// Tell the disk it is unused, and to erase it for good.
+    SecureDiscard,
+}
+
+/// A disk that we can skip over regions of, without writing data to them.
+pub trait SkipRegion {
+    /// Move `len` bytes forward from the current position, handling the region we
+    /// moved past as specified.
+    fn skip_region(&mut self, len: u64, how: SkipHow) -> std::io::Result<()>;
+}
+
+impl<T: SkipRegion> SkipRegion for &mut T {
+    #[inline(always)]
+    fn skip_region(&mut self, len: u64, how: SkipHow) -> std::io::Result<()> {
+        (**self).skip_region(len, how)
+    }
+}
+
+/// Returns true if the buffer consists entirely of zeroes.
+#[inline(always)]
+pub fn is_zeroes(buf: &[u8]) -> bool {
+    buf.iter().all(|b| *b == 0)
+}
+
+/// Compares what was read off a disk against what should be on it, and keeps track of
+/// where they differ.
+pub struct MismatchFinder {
+    block_size: usize,
+    found: Option<Mismatches>,
+    /// Where the last block that differed ends, to tell if the next one continues its
+    /// range
+    last_end: Option<u64>,
+}
+
+impl MismatchFinder {
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            found: None,
+            last_end: None,
+        }
+    }
+
+    /// Compares `actual`, which was read from `offset` on the disk, against `expected`.
+    /// Returns true if any of it differs.
+    #[inline(always)]
+    pub fn compare(&mut self, offset: u64, expected: &[u8], actual: &[u8]) -> bool {
+        if expected == actual {
+            return false;
+        }
+        let blocks = (expected.chunks(self.block_size)).zip(actual.chunks(self.block_size));
+        for (i, (e, a)) in blocks.enumerate() {
+            if e == a {
+                continue;
+            }
+            let block_offset = offset + (i * self.block_size) as u64;
+            let found = self.found.get_or_insert_with(|| Mismatches {
+                first_offset: block_offset
+                    + e.iter().zip(a).position(|(e, a)| e != a).unwrap() as u64,
+                first_block_differing_bytes: e.iter().zip(a).filter(|(e, a)| e != a).count() as u64,
+                block_size: self.block_size as u64,
+                bad_ranges: vec![],
+                bad_range_count: 0,
+                bad_blocks: 0,
+                bad_bytes: 0,
+                scanned_all: false,
+            });
+            found.bad_blocks += 1;
+            found.bad_bytes += e.len() as u64;
+            if self.last_end == Some(block_offset) {
+                if !found.is_truncated() {
+                    found.bad_ranges.last_mut().unwrap().len += e.len() as u64;
+                }
+            } else {
+                found.bad_range_count += 1;
+                if found.bad_ranges.len() < Mismatches::MAX_BAD_RANGES {
+                    found.bad_ranges.push(ByteRange {
+                        offset: block_offset,
+                        len: e.len() as u64,
+                    });
+                }
+            }
+            self.last_end = Some(block_offset + e.len() as u64);
+        }
+        true
+    }
+
+    /// Fails with everything that differed, if anything did. `scanned_all` is whether
+    /// the whole disk was compared.
+    pub fn finish(self, scanned_all: bool) -> Result<(), WriteVerifyError> {
+        match self.found {
+            None => Ok(()),
+            Some(m) => Err(WriteVerifyError::VerificationFailed(Mismatches {
+                scanned_all,
+                ..m
+            })),
+        }
+    }
+}
 
 /// Wraps a reader and counts how many bytes we've read in total, without
 /// making any system calls.
@@ -27,6 +143,11 @@ impl<R: Read> CountRead<R> {
     pub fn get_ref(&self) -> &R {
         &self.r
     }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
 }
 
 impl<R: Read> Read for CountRead<R> {
@@ -38,6 +159,50 @@ impl<R: Read> Read for CountRead<R> {
     }
 }
 
+/// Wraps a reader and, if given a [Hasher], hashes everything read through it.
+pub struct HashRead<R: Read> {
+    r: R,
+    hasher: Option<Hasher>,
+}
+
+impl<R: Read> HashRead<R> {
+    #[inline(always)]
+    pub fn new(r: R, hasher: Option<Hasher>) -> Self {
+        Self { r, hasher }
+    }
+
+    #[inline(always)]
+    pub fn get_ref(&self) -> &R {
+        &self.r
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    #[inline(always)]
+    pub fn is_hashing(&self) -> bool {
+        self.hasher.is_some()
+    }
+
+    /// Finishes hashing, and returns the hash if we were hashing.
+    pub fn take_hash(&mut self) -> Option<Vec<u8>> {
+        self.hasher.take().map(Hasher::finalize)
+    }
+}
+
+impl<R: Read> Read for HashRead<R> {
+    #[inline(always)]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes = self.r.read(buf)?;
+        if let Some(h) = &mut self.hasher {
+            h.update(&buf[..bytes]);
+        }
+        Ok(bytes)
+    }
+}
+
 /// Wraps a writer and counts how many bytes we've written in total, without
 /// making any system calls.
 pub struct CountWrite<W: Write> {
@@ -57,6 +222,16 @@ impl<W: Write> CountWrite<W> {
     }
 }
 
+impl<W: Write + SkipRegion> SkipRegion for CountWrite<W> {
+    /// Skipped bytes count as written.
+    #[inline(always)]
+    fn skip_region(&mut self, len: u64, how: SkipHow) -> std::io::Result<()> {
+        self.w.skip_region(len, how)?;
+        self.count += len;
+        Ok(())
+    }
+}
+
 impl<W: Write> Write for CountWrite<W> {
     #[inline(always)]
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
@@ -109,6 +284,20 @@ impl Write for SyncDataFile {
     }
 }
 
+impl SkipRegion for SyncDataFile {
+    fn skip_region(&mut self, len: u64, how: SkipHow) -> std::io::Result<()> {
+        let start = self.0.stream_position()?;
+        match how {
+            SkipHow::Seek => {}
+            SkipHow::ZeroOut => zero_out_range(&self.0, start, len)?,
+            SkipHow::Discard => discard_range(&self.0, start, len)?,
+            SkipHow::SecureDiscard => secure_discard_range(&self.0, start, len)?,
+        }
+        self.0.seek(SeekFrom::Start(start + len))?;
+        Ok(())
+    }
+}
+
 impl Seek for SyncDataFile {
     #[inline(always)]
     fn seek(&mut self, pos: futures_io::SeekFrom) -> std::io::Result<u64> {
@@ -119,21 +308,82 @@ impl Seek for SyncDataFile {
 /// A reader type specifically for [`super::WriteOp`] and [`super::VerifyOp`] to
 /// read stuff off of files.
 ///
-/// It provides decompression, buffering, and instrumentation of read stats.
-pub struct FileSourceReader<R: Read>(CountRead<DecompressRead<BufReader<CountRead<R>>>>);
+/// It provides decompression, expansion of Android sparse images, buffering,
+/// instrumentation of read stats, and optionally hashing of the file as it is read.
+pub struct FileSourceReader<R: Read>(CountRead<SparseImageRead<HashRead<UnpackedReader<R>>>>);
+
+/// The layers of [FileSourceReader] that get the image out of the file.
+type UnpackedReader<R> = ArchiveMemberRead<DecompressRead<RawFileReader<R>>>;
+
+/// The layers of [FileSourceReader] below decompression.
+type RawFileReader<R> = BufReader<CountRead<HashRead<R>>>;
 
 impl<R: Read> FileSourceReader<R> {
+    /// Create a new reader. If `hash` is provided, the file is hashed as it is read,
+    /// either before or after decompression depending on what the hash is of. Sparse
+    /// images are hashed before they are expanded, and archive members after they are
+    /// extracted.
     #[inline(always)]
-    pub fn new(cf: CompressionFormat, buf_size: usize, r: R) -> Self {
-        FileSourceReader(CountRead::new(
-            decompress(cf, BufReader::with_capacity(buf_size, CountRead::new(r))).unwrap(),
-        ))
+    pub fn new(
+        cf: CompressionFormat,
+        archive_member: Option<&ArchiveMember>,
+        android_sparse: bool,
+        buf_size: usize,
+        r: R,
+        hash: Option<&ImageHash>,
+    ) -> Self {
+        let (raw_hasher, decompressed_hasher) = match hash {
+            Some(h) if h.of_compressed => (Some(Hasher::new(h.expected.alg)), None),
+            Some(h) => (None, Some(Hasher::new(h.expected.alg))),
+            None => (None, None),
+        };
+        let decompressed = decompress(
+            cf,
+            BufReader::with_capacity(buf_size, CountRead::new(HashRead::new(r, raw_hasher))),
+        )
+        .unwrap();
+        let decompressed = HashRead::new(
+            match archive_member {
+                Some(m) => ArchiveMemberRead::new(decompressed, m),
+                None => ArchiveMemberRead::passthrough(decompressed),
+            },
+            decompressed_hasher,
+        );
+        FileSourceReader(CountRead::new(if android_sparse {
+            SparseImageRead::new(decompressed)
+        } else {
+            SparseImageRead::passthrough(decompressed)
+        }))
     }
 
     /// How many bytes we've read from the file. In other words, pre-decompression size.
     #[inline(always)]
     pub fn read_file_bytes(&self) -> u64 {
-        self.0.get_ref().get_ref().get_ref().count()
+        (self.0.get_ref().get_ref().get_ref().get_ref())
+            .get_ref()
+            .get_ref()
+            .count()
+    }
+
+    /// Finishes hashing, and returns the hash if we were asked to hash the file.
+    ///
+    /// This should only be called once the decompressed data has been read to the end.
+    pub fn take_hash(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(hash) = self.0.get_mut().get_mut().take_hash() {
+            return Ok(Some(hash));
+        }
+
+        let raw = (self.0.get_mut().get_mut().get_mut().get_mut())
+            .get_mut()
+            .get_mut();
+        if !raw.get_ref().is_hashing() {
+            return Ok(None);
+        }
+
+        // The decompressor may stop at the end of its stream without reading any
+        // trailing bytes, but the hash is of the entire file.
+        std::io::copy(raw, &mut std::io::sink())?;
+        Ok(raw.get_mut().take_hash())
     }
 
     /// How many bytes we've read after decompression.
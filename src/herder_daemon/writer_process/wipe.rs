@@ -0,0 +1,285 @@
+//! Wiping disks, by filling them with something or discarding everything on them.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, Write};
+use std::thread::JoinHandle;
+
+use aligned_vec::avec_rt;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{OsRng, RngCore, SeedableRng};
+use tracing::{debug, info, trace};
+
+use crate::compression::CompressionFormat;
+use crate::device;
+
+use super::ipc::*;
+use super::utils::{CountRead, CountWrite, MismatchFinder, SkipHow, SkipRegion, SyncDataFile};
+use super::xplat::open_blockdev;
+use super::{CHECKPOINT_BYTES, MAX_BUF_SIZE, try_read_exact, unmount_dest};
+
+pub fn spawn_wiper(
+    id: u64,
+    mut tx: impl FnMut(WriteVerifyEvent) + Send + 'static,
+    init_config: WipeAction,
+) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .name(format!("wiper/{id}"))
+        .spawn(move || {
+            debug!("Spawned child thread {:?}", std::thread::current().id());
+            let final_msg = match run_wipe(&mut tx, &init_config) {
+                Ok(_) => WriteVerifyEvent::Success,
+                Err(e) => WriteVerifyEvent::Error(e),
+            };
+            info!(?final_msg, "Completed");
+            tx(final_msg);
+        })
+        .unwrap()
+}
+
+fn run_wipe(
+    mut tx: impl FnMut(WriteVerifyEvent),
+    args: &WipeAction,
+) -> Result<(), WriteVerifyError> {
+    unmount_dest(&args.dest, args.target_type, args.unmount)?;
+
+    info!("Opening {} for wiping", args.dest.to_string_lossy());
+    let mut disk = SyncDataFile(match args.target_type {
+        device::Type::File => OpenOptions::new().read(true).write(true).open(&args.dest)?,
+        device::Type::Disk | device::Type::Partition => {
+            open_blockdev(&args.dest, CompressionFormat::Identity)?
+        }
+    });
+
+    let size = match args.size {
+        Some(size) => size,
+        None => {
+            let size = disk.seek(io::SeekFrom::End(0))?;
+            disk.seek(io::SeekFrom::Start(0))?;
+            size
+        }
+    };
+    info!(size, "Got disk size");
+
+    tx(WriteVerifyEvent::InitSuccess(WriteVerifyStart {
+        input_file_bytes: Some(size),
+    }));
+
+    let bs = match args.block_size {
+        Some(bs) => bs,
+        None => {
+            info!("Unknown block size, assuming 512");
+            512
+        }
+    };
+    let buf_size = ((bs * 2048) as usize).min(MAX_BUF_SIZE);
+    let checkpoint_period = CHECKPOINT_BYTES / buf_size;
+
+    // Random data can't be read back to compare against, so we generate it again
+    let mut seed = <ChaCha20Rng as SeedableRng>::Seed::default();
+    OsRng.fill_bytes(&mut seed);
+
+    WipeOp {
+        disk: &mut disk,
+        fill: Fill::new(args.mode, seed),
+        len: size,
+        buf_size,
+        disk_block_size: bs as usize,
+        checkpoint_period,
+    }
+    .execute(&mut tx)?;
+
+    let verifying = args.verify && args.mode.can_verify();
+    tx(WriteVerifyEvent::FinishedWriting { verifying });
+    if !verifying {
+        info!("Verification skip was requested, stopping");
+        return Ok(());
+    }
+
+    info!("Rewinding disk to beginning");
+    disk.seek(io::SeekFrom::Start(0))?;
+
+    info!("Executing verification");
+    WipeVerifyOp {
+        disk: &mut disk,
+        fill: Fill::new(args.mode, seed),
+        len: size,
+        buf_size,
+        disk_block_size: bs as usize,
+        checkpoint_period,
+    }
+    .execute(tx)
+}
+
+/// What to wipe a disk with.
+pub(super) enum Fill {
+    Zeroes,
+    Random(Box<ChaCha20Rng>),
+    /// Don't write anything, and skip over the disk like this instead.
+    Skip(SkipHow),
+}
+
+impl Fill {
+    /// Random data is generated from `seed`, so the same data can be generated again.
+    pub fn new(mode: WipeMode, seed: <ChaCha20Rng as SeedableRng>::Seed) -> Self {
+        match mode {
+            WipeMode::Zero => Fill::Zeroes,
+            WipeMode::Random => Fill::Random(Box::new(ChaCha20Rng::from_seed(seed))),
+            WipeMode::Discard => Fill::Skip(SkipHow::Discard),
+            WipeMode::SecureDiscard => Fill::Skip(SkipHow::SecureDiscard),
+        }
+    }
+
+    /// Fill `buf` with what comes next. Must not be called for [Fill::Skip].
+    fn fill(&mut self, buf: &mut [u8]) {
+        match self {
+            Fill::Zeroes => buf.fill(0),
+            Fill::Random(rng) => rng.fill_bytes(buf),
+            Fill::Skip(_) => unreachable!("skipped regions aren't filled"),
+        }
+    }
+}
+
+/// Wraps a bunch of parameters for an operation where we:
+///
+/// - fill a disk with [Fill], or skip over all of it
+/// - write stats down a pipe
+pub(super) struct WipeOp<D: Write + SkipRegion> {
+    /// Disk to wipe
+    pub disk: D,
+    pub fill: Fill,
+    /// How many bytes from the start of the disk to wipe
+    pub len: u64,
+    /// Buffer size to use when writing
+    pub buf_size: usize,
+    /// Block size of the disk
+    pub disk_block_size: usize,
+    /// How many writes of size [`Self::buf_size`] before we report back
+    pub checkpoint_period: usize,
+}
+
+impl<D: Write + SkipRegion> WipeOp<D> {
+    #[inline(always)]
+    pub fn execute(
+        &mut self,
+        mut tx: impl FnMut(WriteVerifyEvent),
+    ) -> Result<(), WriteVerifyError> {
+        let mut disk = CountWrite::new(&mut self.disk);
+        let skip = match self.fill {
+            Fill::Skip(how) => Some(how),
+            _ => None,
+        };
+        // Skipping doesn't need a buffer, and goes a whole checkpoint at a time
+        let (mut buf, step, checkpoint_period) = match skip {
+            Some(_) => (
+                avec_rt![[self.disk_block_size] | 0u8; 0],
+                self.buf_size * self.checkpoint_period,
+                1,
+            ),
+            None => (
+                avec_rt![[self.disk_block_size] | 0u8; self.buf_size],
+                self.buf_size,
+                self.checkpoint_period,
+            ),
+        };
+
+        macro_rules! checkpoint {
+            () => {
+                tx(WriteVerifyEvent::TotalBytes {
+                    src: disk.count(),
+                    dest: disk.count(),
+                });
+            };
+        }
+
+        loop {
+            for _ in 0..checkpoint_period {
+                let remaining = self.len - disk.count();
+                if remaining == 0 {
+                    disk.flush()?;
+                    checkpoint!();
+                    return Ok(());
+                }
+                let len = (step as u64).min(remaining) as usize;
+
+                if let Some(how) = skip {
+                    disk.skip_region(len as u64, how)?;
+                    continue;
+                }
+
+                self.fill.fill(&mut buf[..len]);
+                disk.write_all(&buf[..len]).map_err(|e| match e.kind() {
+                    io::ErrorKind::WriteZero => WriteVerifyError::EndOfOutput,
+                    _ => e.into(),
+                })?;
+            }
+            checkpoint!();
+        }
+    }
+}
+
+/// Wraps a bunch of parameters for an operation where we:
+///
+/// - read back a disk that was wiped by [WipeOp]
+/// - check that it has what [Fill] would have filled it with
+/// - write stats down a pipe
+pub(super) struct WipeVerifyOp<D: Read> {
+    /// Disk to validate
+    pub disk: D,
+    /// The same as what the disk was wiped with
+    pub fill: Fill,
+    /// How many bytes from the start of the disk to check
+    pub len: u64,
+    /// Buffer size to use when reading
+    pub buf_size: usize,
+    /// Block size of the disk
+    pub disk_block_size: usize,
+    /// How many reads of size [`Self::buf_size`] before we report back
+    pub checkpoint_period: usize,
+}
+
+impl<D: Read> WipeVerifyOp<D> {
+    #[inline(always)]
+    pub fn execute(
+        &mut self,
+        mut tx: impl FnMut(WriteVerifyEvent),
+    ) -> Result<(), WriteVerifyError> {
+        let mut disk = CountRead::new(&mut self.disk);
+        let mut expected = vec![0u8; self.buf_size];
+        let mut disk_buf = avec_rt![[self.disk_block_size] | 0u8; self.buf_size];
+        let mut mismatches = MismatchFinder::new(self.disk_block_size);
+
+        macro_rules! checkpoint {
+            () => {
+                tx(WriteVerifyEvent::TotalBytes {
+                    src: disk.count(),
+                    dest: disk.count(),
+                });
+            };
+        }
+
+        loop {
+            for _ in 0..self.checkpoint_period {
+                let remaining = self.len - disk.count();
+                if remaining == 0 {
+                    checkpoint!();
+                    return Ok(());
+                }
+                let len = (self.buf_size as u64).min(remaining) as usize;
+
+                let read_bytes = try_read_exact(&mut disk, &mut disk_buf[..len])?;
+                if read_bytes < len {
+                    checkpoint!();
+                    return Err(WriteVerifyError::EndOfOutput);
+                }
+
+                self.fill.fill(&mut expected[..len]);
+                let offset = disk.count() - len as u64;
+                if mismatches.compare(offset, &expected[..len], &disk_buf[..len]) {
+                    trace!(offset, "verification failed");
+                    return mismatches.finish(false);
+                }
+            }
+            checkpoint!();
+        }
+    }
+}
@@ -3,7 +3,12 @@ use std::{
     path::Path,
 };
 
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+
 use crate::compression::CompressionFormat;
+#[cfg(target_os = "linux")]
+use crate::{device::WriteTarget, herder_daemon::ipc::WriteVerifyError};
 
 #[cfg(target_os = "linux")]
 pub fn open_blockdev(path: impl AsRef<Path>, _cf: CompressionFormat) -> std::io::Result<File> {
@@ -34,3 +39,126 @@ pub fn open_blockdev(path: impl AsRef<Path>, _cf: CompressionFormat) -> std::io:
 
     Ok(file)
 }
+
+/// Open a block device without being able to write to it, so that write-protected
+/// disks can still be read.
+#[cfg(target_os = "linux")]
+pub fn open_blockdev_readonly(path: impl AsRef<Path>) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use libc::O_DIRECT;
+
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(O_DIRECT)
+        .open(path)
+}
+
+#[cfg(target_os = "macos")]
+pub fn open_blockdev_readonly(path: impl AsRef<Path>) -> std::io::Result<File> {
+    use libc::{F_NOCACHE, fcntl};
+    use std::os::fd::AsRawFd;
+
+    let file = OpenOptions::new().read(true).open(path)?;
+
+    unsafe {
+        // Make sure we read what's on the disk, and not what's in the cache
+        fcntl(file.as_raw_fd(), F_NOCACHE);
+    }
+
+    Ok(file)
+}
+
+/// Unmount the disk at `dest` and all of its partitions, so that nothing else writes to
+/// it while we do.
+#[cfg(target_os = "linux")]
+pub fn unmount_target(dest: &Path) -> Result<(), WriteVerifyError> {
+    use std::{cmp::Reverse, ffi::CString, os::unix::ffi::OsStrExt};
+
+    use tracing::info;
+
+    // Look again rather than trusting the UI, in case something got mounted since
+    let target = WriteTarget::try_from(dest).map_err(|e| {
+        WriteVerifyError::UnknownChildProcError(format!("Failed to find mounts of disk: {e:?}"))
+    })?;
+    let mut mountpoints: Vec<&Path> = target.all_mountpoints().collect();
+    // Anything mounted inside of another mount has to go first
+    mountpoints.sort_by_key(|p| Reverse(p.components().count()));
+
+    for mountpoint in mountpoints {
+        info!(?mountpoint, "Unmounting");
+        let path =
+            CString::new(mountpoint.as_os_str().as_bytes()).expect("mountpoints can't contain NUL");
+        if unsafe { libc::umount2(path.as_ptr(), 0) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(WriteVerifyError::PermissionDenied);
+            }
+            return Err(WriteVerifyError::UnmountFailed {
+                mountpoint: mountpoint.to_owned(),
+                error: error.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Ask a block device to make the given range read back as zeroes, without us having
+/// to send the zeroes over.
+#[cfg(target_os = "linux")]
+pub fn zero_out_range(file: &File, start: u64, len: u64) -> std::io::Result<()> {
+    blockdev_range_ioctl(file, libc::_IO(0x12, 127), start, len) // BLKZEROOUT
+}
+
+/// Tell a block device that the given range is unused. Its contents are unspecified
+/// afterwards.
+#[cfg(target_os = "linux")]
+pub fn discard_range(file: &File, start: u64, len: u64) -> std::io::Result<()> {
+    blockdev_range_ioctl(file, libc::_IO(0x12, 119), start, len) // BLKDISCARD
+}
+
+/// Tell a block device that the given range is unused, and that it must erase it so
+/// that it can't be recovered.
+#[cfg(target_os = "linux")]
+pub fn secure_discard_range(file: &File, start: u64, len: u64) -> std::io::Result<()> {
+    blockdev_range_ioctl(file, libc::_IO(0x12, 125), start, len) // BLKSECDISCARD
+}
+
+#[cfg(target_os = "linux")]
+fn blockdev_range_ioctl(
+    file: &File,
+    request: libc::Ioctl,
+    start: u64,
+    len: u64,
+) -> std::io::Result<()> {
+    let range: [u64; 2] = [start, len];
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request, range.as_ptr()) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn zero_out_range(_file: &File, _start: u64, _len: u64) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "zeroing out ranges of disks is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "macos")]
+pub fn discard_range(_file: &File, _start: u64, _len: u64) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "discarding ranges of disks is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "macos")]
+pub fn secure_discard_range(_file: &File, _start: u64, _len: u64) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "securely discarding ranges of disks is not supported on this platform",
+    ))
+}
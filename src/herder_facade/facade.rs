@@ -1,7 +1,7 @@
 use super::client::LazyHerderClient;
 use super::{HerdHandle, HerderFacade, StartWriterError};
 use crate::escalation::run_escalate;
-use crate::herder_daemon::ipc::{HerdAction, HerdEvent, TopLevelHerdEvent};
+use crate::herder_daemon::ipc::{HerdAction, HerdEvent, TopLevelHerdAction, TopLevelHerdEvent};
 use crate::herder_facade::DaemonError;
 use crate::herder_facade::client::{HerderClient, HerderClientFactory, RawHerderClient};
 use crate::ipc_common::read_msg_async;
@@ -22,8 +22,6 @@ use tracing_unwrap::ResultExt;
 /// Doing it this way with a function is so that we can hide all of those ugly ugly ugly
 /// type signatures under a nice `impl HerderFacade + 'static`!
 pub fn make_herder_facade_impl(log_path: &str) -> impl HerderFacade + 'static {
-    let event_demux = Arc::new(std::sync::Mutex::new(EventDemuxMap::new()));
-
     /// Simple implementor of [HerderClientFactory].
     struct ImplFactory {
         log_path: String,
@@ -35,27 +33,34 @@ pub fn make_herder_facade_impl(log_path: &str) -> impl HerderFacade + 'static {
         type Output = RawHerderClient<BufWriter<ChildStdin>>;
 
         async fn make(&mut self) -> Result<Self::Output, DaemonError> {
-            let event_demux = self.event_demux.clone();
-            let handler = move |e| {
-                event_demux.lock().unwrap().handle_event(e);
-            };
-            let f = spawn_herder(self.log_path.clone(), self.escalated, handler).await?;
+            let f = spawn_herder(
+                self.log_path.clone(),
+                self.escalated,
+                self.event_demux.clone(),
+            )
+            .await?;
             Ok(f)
         }
     }
+
+    // Each daemon gets its own demux, so that when one of them goes away, only the
+    // herds it was running are cut off.
+    let standard_events = Arc::new(std::sync::Mutex::new(EventDemuxMap::new()));
+    let escalated_events = Arc::new(std::sync::Mutex::new(EventDemuxMap::new()));
     let standard_daemon = LazyHerderClient::new(ImplFactory {
         log_path: log_path.to_owned(),
-        event_demux: event_demux.clone(),
+        event_demux: standard_events.clone(),
         escalated: false,
     });
     let escalated_daemon = LazyHerderClient::new(ImplFactory {
         log_path: log_path.to_owned(),
-        event_demux: event_demux.clone(),
+        event_demux: escalated_events.clone(),
         escalated: true,
     });
 
     HerderFacadeImpl {
-        event_demux,
+        standard_events,
+        escalated_events,
         next_writer_id: 0,
         standard_daemon,
         escalated_daemon,
@@ -64,7 +69,8 @@ pub fn make_herder_facade_impl(log_path: &str) -> impl HerderFacade + 'static {
 
 /// Implementation of the actual [HerderFacade] used by Caligula.
 struct HerderFacadeImpl<Std, Esc> {
-    event_demux: Arc<std::sync::Mutex<EventDemuxMap<u64, TopLevelHerdEvent>>>,
+    standard_events: Arc<std::sync::Mutex<EventDemuxMap<u64, TopLevelHerdEvent>>>,
+    escalated_events: Arc<std::sync::Mutex<EventDemuxMap<u64, TopLevelHerdEvent>>>,
     next_writer_id: u64,
 
     standard_daemon: Std,
@@ -84,14 +90,21 @@ where
         let id = self.next_writer_id;
         self.next_writer_id += 1;
 
-        match escalated {
-            true => self.escalated_daemon.start_writer(id, args).await?,
-            false => self.standard_daemon.start_writer(id, args).await?,
-        }
+        let action: TopLevelHerdAction = args.into();
+        let event_demux = match escalated {
+            true => {
+                self.escalated_daemon.start_writer(id, action).await?;
+                &self.escalated_events
+            }
+            false => {
+                self.standard_daemon.start_writer(id, action).await?;
+                &self.standard_events
+            }
+        };
 
         trace!("Reading results from child");
         let mut event_rx = UnboundedReceiverStream::new(
-            self.event_demux
+            event_demux
                 .lock()
                 .unwrap()
                 .take_receiver(id)
@@ -125,24 +138,33 @@ where
     }
 }
 
+/// The sender is dropped once the daemon is gone, ending the receiver's stream.
+type DemuxChannel<T> = (
+    Option<mpsc::UnboundedSender<T>>,
+    Option<mpsc::UnboundedReceiver<T>>,
+);
+
 #[derive(Debug)]
 struct EventDemuxMap<K, T> {
-    map: HashMap<K, (mpsc::UnboundedSender<T>, Option<mpsc::UnboundedReceiver<T>>)>,
+    map: HashMap<K, DemuxChannel<T>>,
+    closed: bool,
 }
 
 impl<K: Hash + Eq, T> EventDemuxMap<K, T> {
     fn new() -> Self {
         Self {
             map: Default::default(),
+            closed: false,
         }
     }
 
     fn take_receiver(&mut self, id: K) -> Option<mpsc::UnboundedReceiver<T>> {
+        let closed = self.closed;
         self.map
             .entry(id)
             .or_insert_with(|| {
                 let (tx, rx) = mpsc::unbounded_channel();
-                (tx, Some(rx))
+                ((!closed).then_some(tx), Some(rx))
             })
             .1
             .take()
@@ -151,29 +173,34 @@ impl<K: Hash + Eq, T> EventDemuxMap<K, T> {
     fn handle_event(&mut self, (k, t): (K, T)) {
         use std::collections::hash_map::Entry;
         match self.map.entry(k) {
-            Entry::Occupied(e) => match e.get().0.send(t) {
-                Ok(_) => (),
-                Err(_) => {
+            Entry::Occupied(e) => match &e.get().0 {
+                Some(tx) if tx.send(t).is_ok() => (),
+                _ => {
                     e.remove();
                 }
             },
             Entry::Vacant(e) => {
                 let (tx, rx) = mpsc::unbounded_channel();
-                match tx.send(t) {
-                    Ok(_) => {
-                        e.insert((tx, Some(rx)));
-                    }
-                    Err(_) => (),
+                if tx.send(t).is_ok() {
+                    e.insert((Some(tx), Some(rx)));
                 }
             }
         }
     }
+
+    /// No more events are coming, so end every stream once it has been drained.
+    fn close(&mut self) {
+        self.closed = true;
+        for (tx, _) in self.map.values_mut() {
+            *tx = None;
+        }
+    }
 }
 
 async fn spawn_herder(
     log_path: String,
     escalated: bool,
-    handle_event: impl Fn((u64, TopLevelHerdEvent)) + Send + 'static,
+    event_demux: Arc<std::sync::Mutex<EventDemuxMap<u64, TopLevelHerdEvent>>>,
 ) -> Result<RawHerderClient<BufWriter<ChildStdin>>, DaemonError> {
     let proc = process_path::get_executable_path().unwrap();
     let cmd = crate::escalation::Command {
@@ -206,11 +233,17 @@ async fn spawn_herder(
     tokio::spawn(async move {
         let mut child_rx = child_rx;
         loop {
-            // TODO dont error here
-            let msg = read_msg_async::<(u64, TopLevelHerdEvent)>(&mut child_rx)
-                .await
-                .unwrap();
-            handle_event(msg);
+            match read_msg_async::<(u64, TopLevelHerdEvent)>(&mut child_rx).await {
+                Ok(msg) => event_demux.lock().unwrap().handle_event(msg),
+                Err(error) => {
+                    // This is how it normally goes when the daemon is killed after
+                    // we're done with it. If it went away in the middle of a herd,
+                    // that herd will find out from its stream ending.
+                    debug!(?error, "Stopped receiving events from daemon");
+                    event_demux.lock().unwrap().close();
+                    return;
+                }
+            }
         }
     });
 
@@ -222,3 +255,26 @@ async fn spawn_herder(
         tx: BufWriter::new(child_tx),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EventDemuxMap;
+
+    #[tokio::test]
+    async fn closing_demux_ends_streams_after_draining() {
+        let mut demux = EventDemuxMap::new();
+        let mut taken = demux.take_receiver(1).unwrap();
+        demux.handle_event((1, "a"));
+        demux.handle_event((2, "b"));
+
+        demux.close();
+
+        assert_eq!(taken.recv().await, Some("a"));
+        assert_eq!(taken.recv().await, None);
+        let mut untaken = demux.take_receiver(2).unwrap();
+        assert_eq!(untaken.recv().await, Some("b"));
+        assert_eq!(untaken.recv().await, None);
+        // Herds that never got to say anything still find out
+        assert_eq!(demux.take_receiver(3).unwrap().recv().await, None);
+    }
+}
@@ -35,7 +35,7 @@ pub struct HerdHandle<E: HerdEvent> {
 pub enum StartWriterError<E: HerdEvent> {
     #[error("Unexpected first status: {0:?}")]
     UnexpectedFirstStatus(E),
-    #[error("Explicit error signaled: {0}")]
+    #[error("{0}")]
     Failed(E::Failure),
     #[error("Daemon management error: {0}")]
     DaemonError(#[from] DaemonError),
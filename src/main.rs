@@ -1,10 +1,18 @@
+use std::{path::Path, sync::Arc};
+
 use clap::{CommandFactory as _, Parser};
 use tracing::debug;
 
+use crate::exit_code::{AlreadyReported, ExitCode};
+
+mod android_sparse;
+mod archive;
 mod byteseries;
 mod compression;
 mod device;
+mod download;
 mod escalation;
+mod exit_code;
 mod hash;
 mod hashfile;
 mod herder_daemon;
@@ -28,6 +36,10 @@ pub struct Args {
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
     Burn(ui::BurnArgs),
+    Capture(ui::CaptureArgs),
+    Wipe(ui::WipeArgs),
+    Verify(ui::VerifyArgs),
+    List(ui::ListArgs),
 
     /// INTERNAL ONLY!
     ///
@@ -43,7 +55,7 @@ pub struct HerderDaemonArgs {
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
     let args: Args = match std::env::var("_CALIGULA_CONFIGURE_CLAP_FOR_README") {
         Ok(var) if var == "1" => parse_args_for_readme_generation(),
         _ => Args::parse(),
@@ -51,36 +63,66 @@ async fn main() {
 
     match args.command {
         Command::Burn(burn_args) => {
-            let state_dir = util::ensure_state_dir().await.unwrap();
-            let log_paths = logging::LogPaths::init(&state_dir);
-            logging::init_logging_parent(&log_paths);
-
-            debug!("Starting primary process");
-            match ui::main(&state_dir, log_paths.into(), &burn_args).await {
-                Ok(_) => (),
-                Err(e) => handle_toplevel_error(e),
-            }
+            run_primary(async |state_dir, log_paths| {
+                ui::main(state_dir, log_paths, &burn_args).await
+            })
+            .await
+        }
+        Command::Capture(capture_args) => {
+            run_primary(async |_, log_paths| ui::capture_main(log_paths, &capture_args).await).await
+        }
+        Command::Wipe(wipe_args) => {
+            run_primary(async |_, log_paths| ui::wipe_main(log_paths, &wipe_args).await).await
+        }
+        Command::Verify(verify_args) => {
+            run_primary(async |_, log_paths| ui::verify_main(log_paths, &verify_args).await).await
         }
+        // This only reads sysfs, so it doesn't need a state dir or a log file
+        Command::List(list_args) => match ui::list_main(&list_args) {
+            Ok(_) => ExitCode::Success.into(),
+            Err(e) => handle_toplevel_error(e, None).into(),
+        },
         Command::HerderDaemon(args) => {
             logging::init_logging_child(args.log_file);
             herder_daemon::main().await;
+            ExitCode::Success.into()
         }
     }
 }
 
-fn handle_toplevel_error(err: anyhow::Error) {
-    use inquire::InquireError;
+/// Set up what the primary process needs to run a command, run it, and work out what to
+/// exit with.
+async fn run_primary(
+    command: impl AsyncFnOnce(&Path, Arc<logging::LogPaths>) -> anyhow::Result<()>,
+) -> std::process::ExitCode {
+    let state_dir = util::ensure_state_dir().await.unwrap();
+    let log_paths = logging::LogPaths::init(&state_dir);
+    logging::init_logging_parent(&log_paths);
+
+    debug!("Starting primary process");
+    match command(&state_dir, log_paths.clone().into()).await {
+        Ok(_) => ExitCode::Success.into(),
+        Err(e) => handle_toplevel_error(e, Some(&log_paths)).into(),
+    }
+}
+
+/// Tell the user about an error that made us quit, and work out what to exit with.
+///
+/// Without `log_paths`, there are no logs to attach, so we don't ask for a bug report.
+fn handle_toplevel_error(err: anyhow::Error, log_paths: Option<&logging::LogPaths>) -> ExitCode {
+    let code = ExitCode::of_error(&err);
+    debug!(?err, ?code, "Exiting because of error");
 
-    if let Some(e) = err.downcast_ref::<InquireError>() {
-        match e {
-            InquireError::OperationCanceled
-            | InquireError::OperationInterrupted
-            | InquireError::NotTTY => eprintln!("{e}"),
-            _ => panic!("{err}"),
+    if !err.chain().any(|e| e.is::<AlreadyReported>()) {
+        eprintln!("Error: {err:#}");
+        if let Some(log_paths) = log_paths
+            && code.is_likely_bug()
+        {
+            eprintln!();
+            eprintln!("{}", log_paths.get_bug_report_msg());
         }
-    } else {
-        panic!("{err}");
     }
+    code
 }
 
 /// Parse [Args] from the provided args, but format the help in an easy way for generating
@@ -0,0 +1,233 @@
+//! Saving how far burns got, so that they can be resumed with `--resume` after being
+//! interrupted.
+
+use std::{
+    fs::{self, DirBuilder, File},
+    io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use futures::{StreamExt, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    archive::ArchiveMember,
+    compression::CompressionFormat,
+    herder_daemon::ipc::{WriteCheckpoint, WriteVerifyEvent},
+    util,
+};
+
+/// Everything that has to stay the same for a checkpoint to be resumed from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BurnIdentity {
+    pub image: PathBuf,
+    pub image_len: u64,
+    pub image_modified: SystemTime,
+    pub compression: CompressionFormat,
+    pub archive_member: Option<ArchiveMember>,
+    pub android_sparse: bool,
+    pub target: PathBuf,
+}
+
+impl BurnIdentity {
+    /// Identify a burn of the image file at `image` to `target`.
+    pub fn new(
+        image: &Path,
+        compression: CompressionFormat,
+        archive_member: Option<ArchiveMember>,
+        android_sparse: bool,
+        target: &Path,
+    ) -> io::Result<Self> {
+        let metadata = image.metadata()?;
+        Ok(Self {
+            image: image.canonicalize()?,
+            image_len: metadata.len(),
+            image_modified: metadata.modified()?,
+            compression,
+            archive_member,
+            android_sparse,
+            // Files we burn to may not exist yet
+            target: target
+                .canonicalize()
+                .or_else(|_| std::path::absolute(target))?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedCheckpoint {
+    identity: BurnIdentity,
+    checkpoint: WriteCheckpoint,
+}
+
+/// Where the checkpoints of a single burn are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointFile {
+    path: PathBuf,
+    identity: BurnIdentity,
+}
+
+impl CheckpointFile {
+    /// The checkpoint file for `identity` in the default directory, which is created if
+    /// it doesn't exist.
+    pub fn new(identity: BurnIdentity) -> io::Result<Self> {
+        let dir = util::persistent_state_dir()
+            .ok_or_else(|| io::Error::other("Could not find a directory to save checkpoints in"))?
+            .join("checkpoints");
+        DirBuilder::new().mode(0o700).recursive(true).create(&dir)?;
+        Ok(Self::in_dir(&dir, identity))
+    }
+
+    /// The checkpoint file for `identity` in `dir`. There is one per target, so a new
+    /// burn to a target replaces the checkpoints of the last one.
+    pub fn in_dir(dir: &Path, identity: BurnIdentity) -> Self {
+        let name = identity.target.to_string_lossy().replace('/', "_");
+        Self {
+            path: dir.join(format!("{name}.checkpoint")),
+            identity,
+        }
+    }
+
+    /// Load the last checkpoint, if there is one and it is of the same burn.
+    pub fn load(&self) -> Option<WriteCheckpoint> {
+        let saved: SavedCheckpoint = match File::open(&self.path) {
+            Ok(f) => match bincode::deserialize_from(f) {
+                Ok(s) => s,
+                Err(error) => {
+                    warn!(?error, path = ?self.path, "Failed to read checkpoint");
+                    return None;
+                }
+            },
+            Err(error) => {
+                debug!(?error, path = ?self.path, "No checkpoint to load");
+                return None;
+            }
+        };
+        if saved.identity != self.identity {
+            debug!(saved = ?saved.identity, current = ?self.identity, "Checkpoint is of another burn");
+            return None;
+        }
+        Some(saved.checkpoint)
+    }
+
+    pub fn save(&self, checkpoint: &WriteCheckpoint) -> io::Result<()> {
+        let saved = SavedCheckpoint {
+            identity: self.identity.clone(),
+            checkpoint: checkpoint.clone(),
+        };
+        // Replace the old checkpoint all at once, so an interruption here can't leave
+        // a half-written one behind.
+        let tmp = self.path.with_extension("checkpoint.tmp");
+        bincode::serialize_into(File::create(&tmp)?, &saved).map_err(io::Error::other)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Forget the checkpoint, because the burn finished.
+    pub fn remove(&self) {
+        if let Err(error) = fs::remove_file(&self.path)
+            && error.kind() != io::ErrorKind::NotFound
+        {
+            warn!(?error, path = ?self.path, "Failed to remove checkpoint");
+        }
+    }
+}
+
+/// Save the checkpoints that come through `events` to `file`. Once writing is finished,
+/// there is nothing left to resume, so the checkpoint is removed.
+pub fn save_checkpoints(
+    events: BoxStream<'static, WriteVerifyEvent>,
+    file: CheckpointFile,
+) -> BoxStream<'static, WriteVerifyEvent> {
+    events
+        .inspect(move |e| match e {
+            WriteVerifyEvent::Checkpoint(cp) => {
+                if let Err(error) = file.save(cp) {
+                    warn!(?error, "Failed to save checkpoint");
+                }
+            }
+            WriteVerifyEvent::FinishedWriting { .. } => file.remove(),
+            _ => {}
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::SystemTime};
+
+    use crate::{
+        compression::CompressionFormat,
+        hash::{ExpectedHash, HashAlg},
+        herder_daemon::ipc::WriteCheckpoint,
+    };
+
+    use super::{BurnIdentity, CheckpointFile};
+
+    fn identity() -> BurnIdentity {
+        BurnIdentity {
+            image: "/images/disk.img.xz".into(),
+            image_len: 1234,
+            image_modified: SystemTime::UNIX_EPOCH,
+            compression: CompressionFormat::Xz,
+            archive_member: None,
+            android_sparse: false,
+            target: "/dev/sdz".into(),
+        }
+    }
+
+    fn checkpoint() -> WriteCheckpoint {
+        WriteCheckpoint {
+            src_offset: 100,
+            decompressed_offset: 4096,
+            dest_offset: 4096,
+            written_hash: ExpectedHash {
+                alg: HashAlg::Sha256,
+                hash: vec![1; 32],
+            },
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "caligula-checkpoint-test-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn saved_checkpoint_is_loaded() {
+        let dir = test_dir("roundtrip");
+        let file = CheckpointFile::in_dir(&dir, identity());
+        assert_eq!(file.load(), None);
+
+        file.save(&checkpoint()).unwrap();
+        assert_eq!(file.load(), Some(checkpoint()));
+
+        file.remove();
+        assert_eq!(file.load(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_of_other_burn_is_not_loaded() {
+        let dir = test_dir("other");
+        CheckpointFile::in_dir(&dir, identity())
+            .save(&checkpoint())
+            .unwrap();
+
+        let changed_image = CheckpointFile::in_dir(
+            &dir,
+            BurnIdentity {
+                image_len: 4321,
+                ..identity()
+            },
+        );
+        assert_eq!(changed_image.load(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,30 +1,58 @@
 use is_terminal::IsTerminal;
 use itertools::Itertools;
-use std::{fmt::Display, path::PathBuf};
+use std::{
+    fmt::Display,
+    os::{fd::RawFd, unix::fs::FileTypeExt},
+    path::{Path, PathBuf},
+};
 
-use clap::{Parser, ValueEnum};
+use bytesize::ByteSize;
+use clap::{Args, Parser, ValueEnum};
+use regex_lite::Regex;
 
 use crate::{
-    compression::CompressionArg,
-    hash::{HashAlg, parse_hash_input},
+    compression::{CompressionArg, CompressionLevel},
+    device::{selector::TargetSelector, watch::TargetFilter},
+    download,
+    hash::{ExpectedHash, HashAlg, parse_hash_input},
+    herder_daemon::ipc::{SparseMode, WipeMode},
 };
 
 /// Burn an image to a disk.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct BurnArgs {
-    /// Input image to burn.
+    /// Input image to burn. This may be `-` to read it from stdin, a pipe, or an HTTP(S)
+    /// URL to download it from.
     #[arg(value_parser = parse_image_path, display_order = 0)]
     pub image: PathBuf,
 
     /// Where to write the output. If not supplied, we will search for possible
     /// disks and ask you for where you want to burn.
+    ///
+    /// Besides a path, this may be one of these, which don't change between boots:
+    ///
+    ///  - `serial:<SERIAL>` for the disk with that serial number
+    ///
+    ///  - `model:<MODEL>` for the disk with exactly that model, or `model:~<REGEX>` for
+    ///    the disk whose vendor and model match a regular expression
+    ///
+    ///  - `by-id:<NAME>` or `by-path:<NAME>` for the disk linked to from
+    ///    `/dev/disk/by-id` or `/dev/disk/by-path`. If there is no link with exactly
+    ///    that name, it may be the start of one.
+    ///
+    /// It is an error if no disk or more than one disk matches. To burn to a file
+    /// named like one of these, prefix it with `./`.
+    ///
+    /// This may be provided multiple times to burn the same image to several
+    /// disks at once.
     #[arg(short, display_order = 1)] // needs display_order = 1 or else it will go above image
-    pub out: Option<PathBuf>,
+    pub out: Vec<TargetSelector>,
 
     /// What compression format the input file is in.
     ///
-    ///  - `auto` will guess based on the file extension.
+    ///  - `auto` will guess based on the start of the file's contents, or the file
+    ///    extension if the file can't be read. We'll warn you if they disagree.
     ///
     ///  - `ask` has the same behavior as `auto`, but with a confirmation.
     ///
@@ -34,6 +62,12 @@ pub struct BurnArgs {
     #[arg(short = 'z', long, default_value = "ask")]
     pub compression: CompressionArg,
 
+    /// If the input file is a zip or tar archive, the name of the file inside of it to
+    /// burn. If not supplied, we will look for disk images in the archive, and ask you
+    /// which one to burn if there is more than one.
+    #[arg(long)]
+    pub member: Option<String>,
+
     /// The hash of the input file. This can be provided in one of several formats:
     ///
     ///  - `ask` to ask the user for a hash
@@ -63,12 +97,84 @@ pub struct BurnArgs {
     #[arg(long)]
     pub hash_of: Option<HashOf>,
 
+    /// If provided, we will check the input file's hash while writing it, rather than
+    /// reading it in a separate pass beforehand.
+    ///
+    /// This avoids reading the input file twice, which helps a lot with big images on
+    /// slow storage. However, a corrupted image is only detected after it has been
+    /// written to the disk.
+    #[arg(long)]
+    pub hash_inline: bool,
+
+    /// How to verify the disk after writing it.
+    ///
+    ///  - `source` reads the input file again, and compares it against the disk.
+    ///
+    ///  - `hash` hashes what was written to the disk, and compares it against the
+    ///    input file's hash. The input file is not needed after writing, but this
+    ///    requires a hash of the raw (uncompressed) image.
+    #[arg(long, default_value = "source")]
+    pub verify_with: VerifyWith,
+
+    /// If provided, verifying against the input file keeps going after the first
+    /// mismatch, and reports every region of the disk that differs. This is slower on a
+    /// bad disk, but helps with telling a flaky disk from a bad burn.
+    #[arg(long)]
+    pub verify_all: bool,
+
+    /// If provided, regions of the disk that don't match the input file after burning
+    /// are rewritten and verified again, up to this many times (3 if no value is
+    /// given). This helps with cheap SD cards that sometimes drop writes.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "3")]
+    pub repair: Option<u32>,
+
+    /// If provided, we will not write blocks that are entirely zeroes, which can make
+    /// burning mostly-empty images a lot faster. Files are made sparse instead.
+    ///
+    ///  - `zero-out` (the default if no value is given) asks the disk to zero those
+    ///    blocks out.
+    ///
+    ///  - `discard` tells the disk those blocks are unused. Their contents are
+    ///    unspecified afterwards, so they will not be verified.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "zero-out")]
+    pub sparse: Option<SparseMode>,
+
+    /// If provided, we will save checkpoints while burning, so that if the burn is
+    /// interrupted, it can be continued with --resume. This makes burning a little
+    /// slower.
+    #[arg(long)]
+    pub resumable: bool,
+
+    /// If provided, and a previous burn of the same image to the same disk was
+    /// interrupted, we will check what it already wrote and continue where it left off.
+    /// Only burns made with --resumable can be resumed. Implies --resumable.
+    #[arg(long)]
+    pub resume: bool,
+
     /// If provided, we will show all disks, removable or not.
     ///
     /// If you use this option, please proceed with caution!
     #[arg(long)]
     pub show_all_disks: bool,
 
+    /// If provided, we will wait for a removable disk to be plugged in, and burn to
+    /// it. Disks that were already plugged in are never picked.
+    #[arg(long, conflicts_with = "out")]
+    pub wait_for_device: bool,
+
+    /// With --wait-for-device, only pick disks at least this big (i.e. `8GB`).
+    #[arg(long, value_name = "SIZE", requires = "wait_for_device")]
+    pub device_min_size: Option<ByteSize>,
+
+    /// With --wait-for-device, only pick disks at most this big (i.e. `64GB`).
+    #[arg(long, value_name = "SIZE", requires = "wait_for_device")]
+    pub device_max_size: Option<ByteSize>,
+
+    /// With --wait-for-device, only pick disks whose vendor and model (i.e. `SanDisk
+    /// Ultra Fit`) match this regular expression.
+    #[arg(long, value_name = "REGEX", requires = "wait_for_device")]
+    pub device_model: Option<Regex>,
+
     /// If we should run in interactive mode or not.
     ///
     /// Note that interactive mode will fail if all required arguments are not
@@ -76,13 +182,258 @@ pub struct BurnArgs {
     #[arg(long, default_value = "auto")]
     pub interactive: Interactive,
 
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+
     /// If supplied, we will not ask for confirmation before destroying your disk.
     #[arg(short, long)]
     pub force: bool,
 
+    /// If the output disk or any of its partitions are mounted, should we unmount them
+    /// before burning? Burning to a mounted disk would corrupt whatever is using it, so
+    /// we refuse to if they aren't unmounted.
+    ///
+    /// `ask` refuses without asking if `--force` is given.
+    #[arg(long, default_value = "ask")]
+    pub unmount: Unmount,
+
     /// If we don't have permissions on the output file, should we try to become root?
     #[arg(long, default_value = "ask")]
     pub root: UseSudo,
+
+    /// If supplied, we will let you burn to disks that the running system depends on,
+    /// such as the one with the root filesystem, /boot, or swap on it. These are never
+    /// shown when picking a disk.
+    ///
+    /// THIS WILL BREAK YOUR RUNNING SYSTEM!
+    #[arg(long)]
+    pub dangerously_allow_system_disks: bool,
+}
+
+/// Capture a disk into an image file.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct CaptureArgs {
+    /// Image file to write the disk's contents to.
+    #[arg(display_order = 0)]
+    pub image: PathBuf,
+
+    /// The disk to read from. If not supplied, we will search for possible
+    /// disks and ask you which one you want to capture.
+    ///
+    /// This may also be a selector like `serial:<SERIAL>`, as described for `burn -o`.
+    #[arg(short, display_order = 1)] // needs display_order = 1 or else it will go above image
+    pub input: Option<TargetSelector>,
+
+    /// What compression format to write the image file in.
+    ///
+    ///  - `auto` will guess based on the image file's extension.
+    ///
+    ///  - `ask` has the same behavior as `auto`, but with a confirmation.
+    ///
+    ///  - `none` means no compression.
+    ///
+    /// All other options are compression formats supported by this build of caligula.
+    #[arg(short = 'z', long, default_value = "ask")]
+    pub compression: CompressionArg,
+
+    /// How hard to try to compress the image file. Ignored if it is not compressed.
+    #[arg(short = 'l', long, default_value = "default")]
+    pub compression_level: CompressionLevel,
+
+    /// If provided, we will not read the image back to verify it against the disk.
+    #[arg(long)]
+    pub skip_verify: bool,
+
+    /// If provided, we will show all disks, removable or not.
+    #[arg(long)]
+    pub show_all_disks: bool,
+
+    /// If we should run in interactive mode or not.
+    ///
+    /// Note that interactive mode will fail if all required arguments are not
+    /// fully specified.
+    #[arg(long, default_value = "auto")]
+    pub interactive: Interactive,
+
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+
+    /// If supplied, we will not ask for confirmation before overwriting the image file.
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// If we don't have permissions on the disk, should we try to become root?
+    #[arg(long, default_value = "ask")]
+    pub root: UseSudo,
+}
+
+/// Wipe a disk, so that what was on it can't be read back.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct WipeArgs {
+    /// The disk to wipe. If not supplied, we will search for possible disks and ask you
+    /// which one you want to wipe.
+    ///
+    /// This may also be a selector like `serial:<SERIAL>`, as described for `burn -o`.
+    /// It may be provided multiple times to wipe several disks at once.
+    #[arg(short, display_order = 0)]
+    pub out: Vec<TargetSelector>,
+
+    /// How to wipe the disk. The discard modes are only supported on Linux, and only by
+    /// some disks.
+    #[arg(short, long, default_value = "zero")]
+    pub mode: WipeMode,
+
+    /// If provided, we will read the disk back after wiping it, to make sure it was.
+    /// This can't be used with the discard modes, which leave the disk's contents
+    /// unspecified.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// If provided, we will show all disks, removable or not.
+    ///
+    /// If you use this option, please proceed with caution!
+    #[arg(long)]
+    pub show_all_disks: bool,
+
+    /// If we should run in interactive mode or not.
+    ///
+    /// Note that interactive mode will fail if all required arguments are not
+    /// fully specified.
+    #[arg(long, default_value = "auto")]
+    pub interactive: Interactive,
+
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+
+    /// If supplied, we will not ask for confirmation before destroying your disk.
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// If the disk or any of its partitions are mounted, should we unmount them before
+    /// wiping? We refuse to wipe a mounted disk if they aren't unmounted.
+    ///
+    /// `ask` refuses without asking if `--force` is given.
+    #[arg(long, default_value = "ask")]
+    pub unmount: Unmount,
+
+    /// If we don't have permissions on the disk, should we try to become root?
+    #[arg(long, default_value = "ask")]
+    pub root: UseSudo,
+
+    /// If supplied, we will let you wipe disks that the running system depends on,
+    /// such as the one with the root filesystem, /boot, or swap on it. These are never
+    /// shown when picking a disk.
+    ///
+    /// THIS WILL BREAK YOUR RUNNING SYSTEM!
+    #[arg(long)]
+    pub dangerously_allow_system_disks: bool,
+}
+
+/// Check that a disk still matches an image or a hash, without writing to it.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct VerifyArgs {
+    /// Image to compare the disk against. This is read the same way as when burning it.
+    #[arg(
+        value_parser = parse_file_path,
+        required_unless_present = "hash",
+        display_order = 0
+    )]
+    pub image: Option<PathBuf>,
+
+    /// The disk to check. If not supplied, we will search for possible disks and ask
+    /// you which one you want to check.
+    ///
+    /// This may also be a selector like `serial:<SERIAL>`, as described for `burn -o`.
+    /// It may be provided multiple times to check several disks at once.
+    #[arg(short, display_order = 1)] // needs display_order = 1 or else it will go above image
+    pub out: Vec<TargetSelector>,
+
+    /// What compression format the image is in.
+    ///
+    ///  - `auto` will guess based on the start of the file's contents, or the file
+    ///    extension if the file can't be read.
+    ///
+    ///  - `ask` has the same behavior as `auto`, but with a confirmation.
+    ///
+    ///  - `none` means no compression.
+    ///
+    /// All other options are compression formats supported by this build of caligula.
+    #[arg(short = 'z', long, default_value = "auto")]
+    pub compression: CompressionArg,
+
+    /// If the image is a zip or tar archive, the name of the file inside of it that was
+    /// burned. If not supplied, we will look for disk images in the archive, and ask you
+    /// which one it was if there is more than one.
+    #[arg(long)]
+    pub member: Option<String>,
+
+    /// Rather than comparing the disk against an image, hash it and compare it against
+    /// this. This can be an SRI-like string in the format of `<alg>-<hash>` (i.e.
+    /// `sha256-EVSTQN3/azprGF...`), or just a hash value, and we will guess the
+    /// algorithm.
+    #[arg(long, value_parser = parse_expected_hash, conflicts_with_all = ["image", "member"])]
+    pub hash: Option<ExpectedHash>,
+
+    /// With --hash, how much of the start of the disk the hash is of (i.e. `4294967296`
+    /// or `4GiB`). If not supplied, the hash is of the whole disk.
+    #[arg(long, value_name = "SIZE", requires = "hash")]
+    pub size: Option<ByteSize>,
+
+    /// If provided, we will show all disks, removable or not.
+    #[arg(long)]
+    pub show_all_disks: bool,
+
+    /// If we should run in interactive mode or not.
+    ///
+    /// Note that interactive mode will fail if all required arguments are not
+    /// fully specified.
+    #[arg(long, default_value = "auto")]
+    pub interactive: Interactive,
+
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+
+    /// If we don't have permissions on the disk, should we try to become root?
+    #[arg(long, default_value = "ask")]
+    pub root: UseSudo,
+}
+
+/// List the disks that can be burned to.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct ListArgs {
+    /// If provided, we will show all disks, removable or not.
+    #[arg(long)]
+    pub show_all_disks: bool,
+
+    /// How to print the disks.
+    ///
+    ///  - `table` is for reading.
+    ///
+    ///  - `json` prints an array with an object for each disk. This is meant for
+    ///    scripts.
+    #[arg(long, default_value = "table")]
+    pub format: ListFormat,
+}
+
+/// How to report progress, shared by all the commands that run herds.
+#[derive(Args, Debug)]
+pub struct ProgressArgs {
+    /// How to report progress.
+    ///
+    ///  - `human` shows progress bars, or a full-screen display in interactive mode.
+    ///
+    ///  - `json` prints a line of JSON for every progress update, followed by a summary
+    ///    of how it went. This is meant for scripts.
+    #[arg(long = "progress", value_name = "PROGRESS", default_value = "human")]
+    pub format: ProgressFormat,
+
+    /// Write `--progress=json` output to this file descriptor, rather than stdout.
+    #[arg(long = "progress-fd", value_name = "FD")]
+    pub fd: Option<RawFd>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -101,6 +452,12 @@ pub enum HashOf {
     Compressed,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VerifyWith {
+    Source,
+    Hash,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Interactive {
     Auto,
@@ -108,6 +465,25 @@ pub enum Interactive {
     Never,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Unmount {
+    Ask,
+    Always,
+    Never,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum UseSudo {
     Ask,
@@ -131,29 +507,65 @@ fn parse_path_is_file(path: PathBuf) -> Result<PathBuf, String> {
 }
 
 fn parse_image_path(p: &str) -> Result<PathBuf, String> {
-    parse_path_exists(p).and_then(parse_path_is_file)
+    if p == "-" || download::is_url(p) {
+        return Ok(PathBuf::from(p));
+    }
+    let path = parse_path_exists(p)?;
+    if path.metadata().is_ok_and(|m| m.file_type().is_fifo()) {
+        return Ok(path);
+    }
+    parse_path_is_file(path)
+}
+
+fn parse_file_path(p: &str) -> Result<PathBuf, String> {
+    parse_path_is_file(parse_path_exists(p)?)
 }
 
 fn parse_hash_arg(h: &str) -> Result<HashArg, String> {
     match h.to_lowercase().as_ref() {
         "ask" => Ok(HashArg::Ask),
         "skip" | "none" => Ok(HashArg::Skip),
-        _ => match parse_hash_input(h) {
-            Ok((alg, expected_hash)) => {
-                if alg.len() > 1 {
-                    Err(format!(
-                        "Ambiguous hash algorithm! Could be one of: {}. Please specify by prepending [alg]- to your hash.",
-                        alg.iter().format(", ")
-                    ))
-                } else {
-                    Ok(HashArg::Hash {
-                        alg: alg[0],
-                        expected_hash,
-                    })
-                }
+        _ => parse_expected_hash(h).map(|h| HashArg::Hash {
+            alg: h.alg,
+            expected_hash: h.hash,
+        }),
+    }
+}
+
+fn parse_expected_hash(h: &str) -> Result<ExpectedHash, String> {
+    match parse_hash_input(h) {
+        Ok((alg, hash)) => {
+            if alg.len() > 1 {
+                Err(format!(
+                    "Ambiguous hash algorithm! Could be one of: {}. Please specify by prepending [alg]- to your hash.",
+                    alg.iter().format(", ")
+                ))
+            } else {
+                Ok(ExpectedHash { alg: alg[0], hash })
             }
-            Err(e) => Err(format!("{e}")),
-        },
+        }
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+impl BurnArgs {
+    /// If the image can only be read once, as it is being piped in or downloaded.
+    pub fn image_is_piped(&self) -> bool {
+        self.image == Path::new("-") || !self.image.is_file()
+    }
+
+    /// The URL to download the image from, if it is to be downloaded.
+    pub fn image_url(&self) -> Option<&str> {
+        self.image.to_str().filter(|s| download::is_url(s))
+    }
+
+    /// What a disk has to be like to be picked by --wait-for-device.
+    pub fn device_filter(&self) -> TargetFilter {
+        TargetFilter {
+            min_size: self.device_min_size,
+            max_size: self.device_max_size,
+            model: self.device_model.clone(),
+        }
     }
 }
 
@@ -14,7 +14,10 @@ use crate::{
     herder_daemon::ipc::WriteVerifyEvent,
     herder_facade::HerdHandle,
     logging::LogPaths,
-    ui::{start::BeginParams, writer_tracking::WriterState},
+    ui::{
+        start::DisplayParams,
+        writer_tracking::{WriterState, merge_herd_events},
+    },
 };
 
 use super::{
@@ -28,7 +31,7 @@ where
 {
     terminal: &'a mut Terminal<B>,
     events: EventStream,
-    handle: Option<HerdHandle<WriteVerifyEvent>>,
+    child_events: Option<BoxStream<'static, (usize, Option<WriteVerifyEvent>)>>,
     state: State,
     log_paths: Arc<LogPaths>,
 }
@@ -39,52 +42,56 @@ where
 {
     #[tracing::instrument(skip_all)]
     pub fn new(
-        params: &BeginParams,
-        handle: HerdHandle<WriteVerifyEvent>,
+        params: &DisplayParams,
+        handles: Vec<HerdHandle<WriteVerifyEvent>>,
         terminal: &'a mut Terminal<B>,
         log_paths: Arc<LogPaths>,
     ) -> Self {
-        let input_file_bytes = handle.initial_info.input_file_bytes;
+        let input_file_bytes: Vec<Option<u64>> = handles
+            .iter()
+            .map(|h| h.initial_info.input_file_bytes)
+            .collect();
         Self {
             terminal,
-            handle: Some(handle),
+            child_events: Some(merge_herd_events(handles.into_iter().map(|h| h.events))),
             events: EventStream::new(),
-            state: State::initial(Instant::now(), params, input_file_bytes),
+            state: State::initial(Instant::now(), params, &input_file_bytes),
             log_paths,
         }
     }
 
+    /// Show the UI until the user quits, and return how each writer ended up.
     #[tracing::instrument(skip_all, level = "debug")]
-    pub async fn show(mut self) -> anyhow::Result<()> {
+    pub async fn show(mut self) -> anyhow::Result<Vec<WriterState>> {
         loop {
             match self.get_and_handle_events().await {
-                Ok(s) => self = s,
+                Ok(()) => {}
                 Err(e) => match e.downcast::<Quit>()? {
                     Quit => break,
                 },
             }
         }
-        Ok(())
+        Ok((self.state.targets.into_iter()).map(|t| t.child).collect())
     }
 
     #[tracing::instrument(skip_all, level = "trace")]
-    async fn get_and_handle_events(mut self) -> anyhow::Result<FancyUI<'a, B>> {
+    async fn get_and_handle_events(&mut self) -> anyhow::Result<()> {
         let msg = {
-            if let Some(handle) = &mut self.handle {
-                get_event_child_active(&mut self.events, &mut handle.events).await
+            if let Some(child_events) = &mut self.child_events {
+                get_event_child_active(&mut self.events, child_events).await
             } else {
                 get_event_child_dead(&mut self.events).await
             }?
         };
-        self.state = self.state.on_event(msg)?;
+        self.state.on_event(msg)?;
 
-        // Drop handle/process if process died
-        if self.state.child.is_finished() {
-            self.handle = None;
+        // Drop handles/processes if all processes died
+        if self.state.is_finished() {
+            self.child_events = None;
         }
 
         draw(&mut self.state, self.terminal, &self.log_paths)?;
-        Ok(self)
+        Ok(())
     }
 }
 
@@ -95,7 +102,7 @@ async fn get_event_child_dead(ui_events: &mut EventStream) -> anyhow::Result<UIE
 #[tracing::instrument(skip_all, level = "trace")]
 async fn get_event_child_active(
     ui_events: &mut EventStream,
-    child_events: &mut BoxStream<'static, WriteVerifyEvent>,
+    child_events: &mut BoxStream<'static, (usize, Option<WriteVerifyEvent>)>,
 ) -> anyhow::Result<UIEvent> {
     let sleep = tokio::time::sleep(time::Duration::from_millis(250));
     select! {
@@ -103,7 +110,12 @@ async fn get_event_child_active(
             return Ok(UIEvent::SleepTimeout);
         }
         msg = child_events.next() => {
-            return Ok(UIEvent::RecvChildStatus(Instant::now(), msg));
+            // The merged stream only ends after every herd's stream has ended, at
+            // which point every writer has already been told about its termination.
+            let Some((i, msg)) = msg else {
+                return Ok(UIEvent::SleepTimeout);
+            };
+            return Ok(UIEvent::RecvChildStatus(Instant::now(), i, msg));
         }
         event = ui_events.next() => {
             return Ok(UIEvent::RecvTermEvent(event.unwrap()?));
@@ -118,12 +130,12 @@ struct ComputedLayout {
     quit_modal: Rect,
 }
 
-impl From<Rect> for ComputedLayout {
-    fn from(area: Rect) -> Self {
+impl ComputedLayout {
+    fn new(area: Rect, n_progress_bars: u16) -> Self {
         let root = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
-                Constraint::Length(1),
+                Constraint::Length(n_progress_bars),
                 Constraint::Min(10),
                 Constraint::Length(10),
             ])
@@ -168,33 +180,48 @@ pub fn draw(
     terminal: &mut Terminal<impl ratatui::backend::Backend>,
     log_paths: &LogPaths,
 ) -> anyhow::Result<()> {
-    let progress_bar = WriterProgressBar::from_writer(&state.child);
+    let show_names = state.targets.len() > 1;
+    let progress_bars: Vec<_> = (state.targets.iter().enumerate())
+        .map(|(i, t)| {
+            let bar = WriterProgressBar::from_writer(&t.child, state.verb);
+            match (show_names, i == state.selected) {
+                (false, _) => bar,
+                (true, true) => bar.with_prefix(format!("> {}", t.target_name)),
+                (true, false) => bar.with_prefix(format!("  {}", t.target_name)),
+            }
+        })
+        .collect();
+
+    let selected = &state.targets[state.selected];
 
-    let final_time = match state.child {
+    let final_time = match selected.child {
         WriterState::Finished { finish_time, .. } => finish_time,
         _ => Instant::now(),
     };
 
-    let error = match &state.child {
-        WriterState::Finished { error, .. } => error.as_ref(),
-        _ => None,
-    };
+    let error = selected.child.error();
 
     let info_table = WritingInfoTable {
         input_filename: &state.input_filename,
-        target_filename: &state.target_filename,
-        state: &state.child,
+        target_filename: &selected.target_filename,
+        state: &selected.child,
     };
 
     let speed_chart = SpeedChart {
-        state: &state.child,
+        state: &selected.child,
         final_time,
     };
 
     terminal.draw(|f| {
-        let layout = ComputedLayout::from(f.size());
+        let layout = ComputedLayout::new(f.size(), progress_bars.len() as u16);
 
-        f.render_widget(progress_bar.render(), layout.progress);
+        let progress_rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(progress_bars.iter().map(|_| Constraint::Length(1)))
+            .split(layout.progress);
+        for (bar, area) in progress_bars.iter().zip(progress_rows.iter()) {
+            f.render_widget(bar.render(), *area);
+        }
         f.render_stateful_widget(speed_chart, layout.graph, &mut state.graph_state);
 
         if let Some(error) = error {
@@ -5,7 +5,7 @@ use tracing::info;
 
 use crate::{
     herder_daemon::ipc::WriteVerifyEvent,
-    ui::{start::BeginParams, writer_tracking::WriterState},
+    ui::{start::DisplayParams, writer_tracking::WriterState},
 };
 
 use super::widgets::{QuitModal, QuitModalResult, SpeedChartState};
@@ -13,44 +13,67 @@ use super::widgets::{QuitModal, QuitModalResult, SpeedChartState};
 #[derive(Debug, PartialEq, Clone)]
 pub enum UIEvent {
     SleepTimeout,
-    RecvChildStatus(Instant, Option<WriteVerifyEvent>),
+    /// A status message from the herd writing to the target with the given index.
+    RecvChildStatus(Instant, usize, Option<WriteVerifyEvent>),
     RecvTermEvent(Event),
 }
 
 #[derive(Debug, Clone)]
 pub struct State {
+    pub verb: &'static str,
     pub input_filename: String,
-    pub target_filename: String,
-    pub child: WriterState,
+    pub targets: Vec<TargetState>,
+    /// Index of the target whose details are currently being displayed.
+    pub selected: usize,
     pub graph_state: SpeedChartState,
     pub quit_modal: Option<QuitModal>,
 }
 
+/// The state of a single target being written to.
+#[derive(Debug, Clone)]
+pub struct TargetState {
+    pub target_name: String,
+    pub target_filename: String,
+    pub child: WriterState,
+}
+
 impl State {
-    pub fn initial(now: Instant, params: &BeginParams, input_file_bytes: u64) -> Self {
+    /// `input_file_bytes` holds the input file size reported by each target's herd, in
+    /// the same order as [DisplayParams::targets].
+    pub fn initial(now: Instant, params: &DisplayParams, input_file_bytes: &[Option<u64>]) -> Self {
         State {
-            input_filename: params.input_file.to_string_lossy().to_string(),
-            target_filename: params.target.devnode.to_string_lossy().to_string(),
-            child: WriterState::initial(now, !params.compression.is_identity(), input_file_bytes),
+            verb: params.verb,
+            input_filename: params.input_filename.clone(),
+            targets: (params.targets.iter().zip(input_file_bytes))
+                .map(|(target, &input_file_bytes)| TargetState {
+                    target_name: target.name.clone(),
+                    target_filename: target.filename.clone(),
+                    child: WriterState::initial(now, params.is_input_compressed, input_file_bytes),
+                })
+                .collect(),
+            selected: 0,
             graph_state: SpeedChartState::default(),
             quit_modal: None,
         }
     }
 
+    /// Whether or not all of the targets have finished.
+    pub fn is_finished(&self) -> bool {
+        self.targets.iter().all(|t| t.child.is_finished())
+    }
+
     #[tracing::instrument(skip_all, level = "debug", fields(ev))]
-    pub fn on_event(self, ev: UIEvent) -> anyhow::Result<Self> {
-        Ok(match ev {
-            UIEvent::SleepTimeout => self,
-            UIEvent::RecvChildStatus(t, m) => Self {
-                child: self.child.on_status(t, m),
-                ..self
-            },
+    pub fn on_event(&mut self, ev: UIEvent) -> anyhow::Result<()> {
+        match ev {
+            UIEvent::SleepTimeout => {}
+            UIEvent::RecvChildStatus(t, i, m) => self.targets[i].child.update(t, m),
             UIEvent::RecvTermEvent(e) => self.on_term_event(e)?,
-        })
+        }
+        Ok(())
     }
 
     #[tracing::instrument(skip_all, level = "debug", fields(ev))]
-    fn on_term_event(self, ev: Event) -> anyhow::Result<Self> {
+    fn on_term_event(&mut self, ev: Event) -> anyhow::Result<()> {
         match ev {
             Event::Key(KeyEvent {
                 kind: KeyEventKind::Press,
@@ -58,37 +81,40 @@ impl State {
                 modifiers,
                 ..
             }) => self.handle_key_down((code, modifiers)),
-            _ => Ok(self),
+            _ => Ok(()),
         }
     }
 
-    fn handle_key_down(mut self, (kc, km): (KeyCode, KeyModifiers)) -> anyhow::Result<Self> {
+    fn handle_key_down(&mut self, (kc, km): (KeyCode, KeyModifiers)) -> anyhow::Result<()> {
         if let Some(qm) = &self.quit_modal {
-            return match qm.handle_key_down(kc) {
-                Some(QuitModalResult::Quit) => Err(Quit.into()),
-                Some(QuitModalResult::Stay) => Ok(Self {
-                    quit_modal: None,
-                    ..self
-                }),
-                None => Ok(self),
-            };
+            match qm.handle_key_down(kc) {
+                Some(QuitModalResult::Quit) => return Err(Quit.into()),
+                Some(QuitModalResult::Stay) => self.quit_modal = None,
+                None => {}
+            }
+            return Ok(());
         }
 
         match (kc, km) {
             (KeyCode::Char('c'), KeyModifiers::CONTROL)
             | (KeyCode::Esc, _)
             | (KeyCode::Char('q'), _) => {
-                if self.child.is_finished() {
+                if self.is_finished() {
                     info!("Writing and verification finished; quitting immediately");
-                    Err(Quit.into())
-                } else {
-                    info!("Got request to quit, spawning prompt");
-                    self.quit_modal = Some(QuitModal::new());
-                    Ok(self)
+                    return Err(Quit.into());
                 }
+                info!("Got request to quit, spawning prompt");
+                self.quit_modal = Some(QuitModal::new());
+            }
+            (KeyCode::Tab, _) | (KeyCode::Right, _) | (KeyCode::Down, _) => {
+                self.selected = (self.selected + 1) % self.targets.len();
+            }
+            (KeyCode::BackTab, _) | (KeyCode::Left, _) | (KeyCode::Up, _) => {
+                self.selected = (self.selected + self.targets.len() - 1) % self.targets.len();
             }
-            _ => Ok(self),
+            _ => {}
         }
+        Ok(())
     }
 }
 
@@ -125,27 +125,31 @@ fn calculate_ticks(
 }
 
 pub struct WriterProgressBar {
+    prefix: Option<String>,
     bytes_written: u64,
     display_total_bytes: Option<u64>,
     ratio: f64,
-    label_state: &'static str,
+    label_state: String,
     style: Style,
 }
 
 impl WriterProgressBar {
-    pub fn from_writer(state: &WriterState) -> WriterProgressBar {
+    /// `verb` is what to call the writing phase, i.e. "Burning".
+    pub fn from_writer(state: &WriterState, verb: &str) -> WriterProgressBar {
         match state {
             WriterState::Writing(st) => WriterProgressBar {
+                prefix: None,
                 bytes_written: st.write_hist.bytes_encountered(),
-                label_state: "Burning...",
+                label_state: format!("{verb}..."),
                 style: Style::default().fg(Color::Yellow),
-                ratio: st.approximate_ratio(),
+                ratio: st.approximate_ratio().unwrap_or(0.0),
                 display_total_bytes: st.total_raw_bytes,
             },
 
             WriterState::Verifying {
                 verify_hist,
                 total_write_bytes,
+                repair: None,
                 ..
             } => WriterProgressBar::from_simple(
                 verify_hist.bytes_encountered(),
@@ -154,13 +158,34 @@ impl WriterProgressBar {
                 Style::default().fg(Color::Blue).bg(Color::Yellow),
             ),
 
+            WriterState::Verifying {
+                verify_hist,
+                total_write_bytes,
+                repair: Some(r),
+                ..
+            } => WriterProgressBar::from_simple(
+                verify_hist.bytes_encountered(),
+                *total_write_bytes,
+                format!(
+                    "Repairing {} (attempt {})...",
+                    ByteSize::b(r.bad_bytes),
+                    r.attempt
+                ),
+                Style::default().fg(Color::Magenta).bg(Color::Yellow),
+            ),
+
             WriterState::Finished {
                 write_hist,
+                verify_hist,
                 error,
                 total_write_bytes,
                 ..
             } => WriterProgressBar::from_simple(
-                write_hist.bytes_encountered(),
+                // Jobs that only verify have nothing written to show
+                match verify_hist {
+                    Some(v) if write_hist.bytes_encountered() == 0 => v.bytes_encountered(),
+                    _ => write_hist.bytes_encountered(),
+                },
                 *total_write_bytes,
                 if error.is_some() {
                     "Error!"
@@ -176,16 +201,30 @@ impl WriterProgressBar {
         }
     }
 
-    fn from_simple(bytes_written: u64, max: u64, label_state: &'static str, style: Style) -> Self {
+    fn from_simple(
+        bytes_written: u64,
+        max: u64,
+        label_state: impl Into<String>,
+        style: Style,
+    ) -> Self {
         Self {
+            prefix: None,
             bytes_written,
             display_total_bytes: Some(max),
-            ratio: bytes_written as f64 / max as f64,
-            label_state,
+            ratio: bytes_written as f64 / max.max(1) as f64,
+            label_state: label_state.into(),
             style,
         }
     }
 
+    /// Prepend some text, like the name of the target, to this bar's label.
+    pub fn with_prefix(self, prefix: String) -> Self {
+        Self {
+            prefix: Some(prefix),
+            ..self
+        }
+    }
+
     /// This function clamps the ratio to [0, 1].
     ///
     /// Unfortunately, it is sometimes outside of [0, 1]. The most common example is when
@@ -196,11 +235,16 @@ impl WriterProgressBar {
     }
 
     pub fn render(&self) -> Gauge<'_> {
+        let label_state = match &self.prefix {
+            Some(prefix) => format!("{prefix}: {}", self.label_state),
+            None => self.label_state.clone(),
+        };
+
         if let Some(max) = self.display_total_bytes {
             Gauge::default()
                 .label(format!(
                     "{} {} / {} ({:.1} %)",
-                    self.label_state,
+                    label_state,
                     ByteSize::b(self.bytes_written),
                     ByteSize::b(max),
                     self.ratio() * 100.0
@@ -211,7 +255,7 @@ impl WriterProgressBar {
             Gauge::default()
                 .label(format!(
                     "{} {} / ???",
-                    self.label_state,
+                    label_state,
                     ByteSize::b(self.bytes_written),
                 ))
                 .ratio(self.ratio())
@@ -249,6 +293,7 @@ impl WritingInfoTable<'_> {
             WriterState::Verifying {
                 verify_hist: vdata,
                 total_write_bytes,
+                repair,
                 ..
             } => {
                 rows.push(Row::new([
@@ -259,9 +304,22 @@ impl WritingInfoTable<'_> {
                     Cell::from("ETA verify"),
                     Cell::from(format!("{}", vdata.estimated_time_left(*total_write_bytes))),
                 ]));
+                if let Some(r) = repair {
+                    rows.push(Row::new([
+                        Cell::from("Repairing"),
+                        Cell::from(format!(
+                            "{} (attempt {})",
+                            ByteSize::b(r.bad_bytes),
+                            r.attempt
+                        )),
+                    ]));
+                }
             }
             WriterState::Finished {
-                verify_hist: vdata, ..
+                verify_hist: vdata,
+                error,
+                repair,
+                ..
             } => {
                 if let Some(vdata) = vdata {
                     rows.push(Row::new([
@@ -269,6 +327,16 @@ impl WritingInfoTable<'_> {
                         Cell::from(format!("{}", vdata.total_avg_speed())),
                     ]));
                 }
+                if let (None, Some(r)) = (error, repair) {
+                    rows.push(Row::new([
+                        Cell::from("Repaired"),
+                        Cell::from(format!(
+                            "{} in {} attempt(s)",
+                            ByteSize::b(r.first_bad_bytes),
+                            r.attempt
+                        )),
+                    ]));
+                }
             }
         }
 
@@ -0,0 +1,522 @@
+//! Machine-readable progress output, for scripts that burn disks.
+//!
+//! Every event received from a herd is written out as a single line of JSON,
+//! followed by a summary of how each target went once they have all finished.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::fd::{FromRawFd, RawFd},
+    time::Instant,
+};
+
+use anyhow::{Context, bail};
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::{
+    exit_code::UsageError,
+    herder_daemon::ipc::{Mismatches, WriteVerifyError, WriteVerifyEvent},
+    herder_facade::HerdHandle,
+    ui::{
+        cli::{ProgressArgs, ProgressFormat},
+        start::DisplayParams,
+        writer_tracking::{WriterState, merge_herd_events},
+    },
+};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonMessage<'a> {
+    Progress {
+        /// Index of the target in the order they were given
+        index: usize,
+        target: &'a str,
+        event: &'static str,
+        phase: Phase,
+        /// Bytes of the input read so far in this phase
+        src_bytes: u64,
+        /// Bytes of the disk written or verified so far in this phase
+        dest_bytes: u64,
+        /// Average speed of this phase so far, in bytes per second
+        speed: Option<f64>,
+        eta_secs: Option<f64>,
+        /// Which repair attempt is going on, if any
+        attempt: Option<u32>,
+        /// How many bytes that repair attempt is rewriting
+        bad_bytes: Option<u64>,
+    },
+    Summary {
+        result: Outcome,
+        targets: Vec<TargetSummary<'a>>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct TargetSummary<'a> {
+    target: &'a str,
+    path: &'a str,
+    result: Outcome,
+    error_kind: Option<&'static str>,
+    error: Option<String>,
+    /// Where the disk didn't match, if that's why it failed.
+    mismatches: Option<&'a Mismatches>,
+    bytes_written: u64,
+    /// How many bytes had to be rewritten to make it succeed, if any.
+    repaired_bytes: Option<u64>,
+    repair_attempts: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    Writing,
+    Verifying,
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Success,
+    Failure,
+}
+
+/// Where JSON progress should be written, or None if progress is for a human.
+pub fn json_progress_output(args: &ProgressArgs) -> anyhow::Result<Option<Box<dyn Write + Send>>> {
+    match (args.format, args.fd) {
+        (ProgressFormat::Human, None) => Ok(None),
+        (ProgressFormat::Human, Some(_)) => {
+            bail!(UsageError(
+                "--progress-fd can only be used with --progress=json".into()
+            ))
+        }
+        (ProgressFormat::Json, None) => Ok(Some(Box::new(io::stdout()))),
+        (ProgressFormat::Json, Some(fd)) => {
+            Ok(Some(Box::new(open_fd(fd).with_context(|| {
+                format!("Failed to open --progress-fd {fd}")
+            })?)))
+        }
+    }
+}
+
+fn open_fd(fd: RawFd) -> io::Result<File> {
+    // Duplicate it rather than taking it over, so that we fail cleanly if it isn't
+    // open, instead of closing some unrelated file later.
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(dup) })
+}
+
+pub async fn run_json_progress(
+    params: &DisplayParams,
+    handles: Vec<HerdHandle<WriteVerifyEvent>>,
+    mut out: impl Write,
+) -> anyhow::Result<Vec<WriterState>> {
+    let now = Instant::now();
+    let mut states: Vec<WriterState> = handles
+        .iter()
+        .map(|h| {
+            WriterState::initial(
+                now,
+                params.is_input_compressed,
+                h.initial_info.input_file_bytes,
+            )
+        })
+        .collect();
+    // The latest byte counts reported for the current phase of each target
+    let mut totals = vec![(0u64, 0u64); states.len()];
+
+    let mut events = merge_herd_events(handles.into_iter().map(|h| h.events));
+
+    while !states.iter().all(WriterState::is_finished) {
+        let Some((i, event)) = events.next().await else {
+            break;
+        };
+        if states[i].is_finished() {
+            // Its stream ending after it reported how it went is not news
+            continue;
+        }
+        let name = event_name(event.as_ref());
+        match &event {
+            Some(WriteVerifyEvent::TotalBytes { src, dest }) => totals[i] = (*src, *dest),
+            Some(WriteVerifyEvent::FinishedWriting { verifying: true }) => totals[i] = (0, 0),
+            _ => {}
+        }
+
+        states[i].update(Instant::now(), event);
+        let (phase, speed, eta) = match &states[i] {
+            WriterState::Writing(w) => (
+                Phase::Writing,
+                Some(w.write_hist.total_avg_speed().0),
+                w.eta_write().secs_left(),
+            ),
+            WriterState::Verifying {
+                verify_hist,
+                total_write_bytes,
+                ..
+            } => (
+                Phase::Verifying,
+                Some(verify_hist.total_avg_speed().0),
+                verify_hist
+                    .estimated_time_left(*total_write_bytes)
+                    .secs_left(),
+            ),
+            WriterState::Finished { .. } => (Phase::Finished, None, None),
+        };
+
+        let (src_bytes, dest_bytes) = totals[i];
+        let repair = match &states[i] {
+            WriterState::Verifying { repair, .. } => *repair,
+            _ => None,
+        };
+        write_message(
+            &mut out,
+            &JsonMessage::Progress {
+                index: i,
+                target: &params.targets[i].name,
+                event: name,
+                phase,
+                src_bytes,
+                dest_bytes,
+                speed,
+                eta_secs: eta,
+                attempt: repair.map(|r| r.attempt),
+                bad_bytes: repair.map(|r| r.bad_bytes),
+            },
+        )?;
+    }
+
+    let targets: Vec<TargetSummary> = (params.targets.iter().zip(&states))
+        .map(|(target, state)| {
+            let error = state.error();
+            let repaired = state.repair().filter(|_| error.is_none());
+            TargetSummary {
+                target: &target.name,
+                path: &target.filename,
+                result: match error {
+                    None => Outcome::Success,
+                    Some(_) => Outcome::Failure,
+                },
+                error_kind: error.map(|e| e.kind()),
+                error: error.map(|e| e.to_string()),
+                mismatches: match error {
+                    Some(WriteVerifyError::VerificationFailed(m)) => Some(m),
+                    Some(WriteVerifyError::RepairFailed { unfixed, .. }) => Some(unfixed),
+                    _ => None,
+                },
+                bytes_written: state.write_hist().bytes_encountered(),
+                repaired_bytes: repaired.map(|r| r.first_bad_bytes),
+                repair_attempts: repaired.map(|r| r.attempt),
+            }
+        })
+        .collect();
+    let result = match targets.iter().all(|t| t.result == Outcome::Success) {
+        true => Outcome::Success,
+        false => Outcome::Failure,
+    };
+    write_message(&mut out, &JsonMessage::Summary { result, targets })?;
+
+    Ok(states)
+}
+
+fn write_message(out: &mut impl Write, message: &JsonMessage) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, message)?;
+    writeln!(out)?;
+    // Whoever is reading this wants to know about progress as it happens
+    out.flush()
+}
+
+fn event_name(event: Option<&WriteVerifyEvent>) -> &'static str {
+    match event {
+        Some(WriteVerifyEvent::InitSuccess(_)) => "init_success",
+        Some(WriteVerifyEvent::TotalBytes { .. }) => "total_bytes",
+        Some(WriteVerifyEvent::InputHashed { .. }) => "input_hashed",
+        Some(WriteVerifyEvent::Checkpoint(_)) => "checkpoint",
+        Some(WriteVerifyEvent::FinishedWriting { .. }) => "finished_writing",
+        Some(WriteVerifyEvent::Repairing { .. }) => "repairing",
+        Some(WriteVerifyEvent::BlockSizeChanged(_)) => "block_size_changed",
+        Some(WriteVerifyEvent::BlockSizeSpeedInfo { .. }) => "block_size_speed_info",
+        Some(WriteVerifyEvent::Success) => "success",
+        Some(WriteVerifyEvent::Error(_)) => "error",
+        None => "terminated",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{StreamExt, stream};
+    use serde_json::{Value, json};
+
+    use crate::{
+        herder_daemon::ipc::{HerdEvent, Mismatches, WriteVerifyError, WriteVerifyEvent},
+        herder_facade::HerdHandle,
+        ui::start::{DisplayParams, DisplayTarget},
+    };
+
+    use super::run_json_progress;
+
+    type WriteVerifyStart = <WriteVerifyEvent as HerdEvent>::StartInfo;
+
+    fn handle(events: Vec<WriteVerifyEvent>) -> HerdHandle<WriteVerifyEvent> {
+        HerdHandle {
+            initial_info: WriteVerifyStart {
+                input_file_bytes: Some(100),
+            },
+            events: stream::iter(events).boxed(),
+        }
+    }
+
+    fn target(name: &str) -> DisplayTarget {
+        DisplayTarget {
+            name: name.to_owned(),
+            filename: format!("/dev/{name}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn emits_one_line_per_event_then_summary() {
+        let params = DisplayParams {
+            verb: "Burning",
+            input_filename: "image.iso".to_owned(),
+            is_input_compressed: false,
+            targets: vec![target("sda"), target("sdb")],
+        };
+        let handles = vec![
+            handle(vec![
+                WriteVerifyEvent::TotalBytes { src: 50, dest: 50 },
+                WriteVerifyEvent::TotalBytes {
+                    src: 100,
+                    dest: 100,
+                },
+                WriteVerifyEvent::FinishedWriting { verifying: true },
+                WriteVerifyEvent::TotalBytes {
+                    src: 100,
+                    dest: 100,
+                },
+                WriteVerifyEvent::Success,
+            ]),
+            handle(vec![
+                WriteVerifyEvent::TotalBytes { src: 10, dest: 10 },
+                WriteVerifyEvent::Error(WriteVerifyError::EndOfOutput),
+            ]),
+        ];
+
+        let mut out = vec![];
+        run_json_progress(&params, handles, &mut out).await.unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let of = |i: u64| -> Vec<&Value> {
+            lines
+                .iter()
+                .filter(|l| l["type"] == "progress" && l["index"] == i)
+                .collect()
+        };
+
+        let sda = of(0);
+        let events: Vec<_> = sda.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(
+            events,
+            [
+                "total_bytes",
+                "total_bytes",
+                "finished_writing",
+                "total_bytes",
+                "success"
+            ]
+        );
+        let phases: Vec<_> = sda.iter().map(|l| l["phase"].as_str().unwrap()).collect();
+        assert_eq!(
+            phases,
+            ["writing", "writing", "verifying", "verifying", "finished"]
+        );
+        assert_eq!(sda[1]["target"], "sda");
+        assert_eq!(sda[1]["src_bytes"], 100);
+        assert_eq!(sda[1]["dest_bytes"], 100);
+        assert_eq!(sda[2]["dest_bytes"], 0);
+
+        let sdb = of(1);
+        assert_eq!(sdb.last().unwrap()["event"], "error");
+        assert_eq!(sdb.last().unwrap()["phase"], "finished");
+
+        assert_eq!(
+            lines.last().unwrap(),
+            &json!({
+                "type": "summary",
+                "result": "failure",
+                "targets": [
+                    {
+                        "target": "sda",
+                        "path": "/dev/sda",
+                        "result": "success",
+                        "error_kind": null,
+                        "error": null,
+                        "mismatches": null,
+                        "bytes_written": 100,
+                        "repaired_bytes": null,
+                        "repair_attempts": null,
+                    },
+                    {
+                        "target": "sdb",
+                        "path": "/dev/sdb",
+                        "result": "failure",
+                        "error_kind": "end_of_output",
+                        "error": WriteVerifyError::EndOfOutput.to_string(),
+                        "mismatches": null,
+                        "bytes_written": 10,
+                        "repaired_bytes": null,
+                        "repair_attempts": null,
+                    },
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_jobs_are_reported_as_verifying() {
+        let params = DisplayParams {
+            verb: "Verifying",
+            input_filename: "image.iso".to_owned(),
+            is_input_compressed: false,
+            targets: vec![target("sda")],
+        };
+        // What a herd that only verifies sends
+        let handles = vec![handle(vec![
+            WriteVerifyEvent::FinishedWriting { verifying: true },
+            WriteVerifyEvent::TotalBytes { src: 50, dest: 50 },
+            WriteVerifyEvent::TotalBytes {
+                src: 100,
+                dest: 100,
+            },
+            WriteVerifyEvent::Success,
+        ])];
+
+        let mut out = vec![];
+        run_json_progress(&params, handles, &mut out).await.unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let phases: Vec<_> = (lines.iter())
+            .filter(|l| l["type"] == "progress")
+            .map(|l| l["phase"].as_str().unwrap())
+            .collect();
+        assert_eq!(phases, ["verifying", "verifying", "verifying", "finished"]);
+        assert_eq!(lines[2]["dest_bytes"], 100);
+
+        let sda = &lines.last().unwrap()["targets"][0];
+        assert_eq!(sda["result"], "success");
+        assert_eq!(sda["bytes_written"], 0);
+    }
+
+    #[tokio::test]
+    async fn summary_includes_mismatches() {
+        let params = DisplayParams {
+            verb: "Burning",
+            input_filename: "image.iso".to_owned(),
+            is_input_compressed: false,
+            targets: vec![target("sda")],
+        };
+        let mismatches = Mismatches {
+            first_offset: 1000,
+            first_block_differing_bytes: 3,
+            block_size: 512,
+            bad_ranges: vec![],
+            bad_range_count: 0,
+            bad_blocks: 0,
+            bad_bytes: 0,
+            scanned_all: true,
+        };
+        let handles = vec![handle(vec![WriteVerifyEvent::Error(
+            WriteVerifyError::VerificationFailed(mismatches),
+        )])];
+
+        let mut out = vec![];
+        run_json_progress(&params, handles, &mut out).await.unwrap();
+
+        let summary: Value =
+            serde_json::from_str(String::from_utf8(out).unwrap().lines().last().unwrap()).unwrap();
+        let sda = &summary["targets"][0];
+        assert_eq!(sda["error_kind"], "verification_failed");
+        assert_eq!(
+            sda["mismatches"],
+            json!({
+                "first_offset": 1000,
+                "first_block_differing_bytes": 3,
+                "block_size": 512,
+                "bad_ranges": [],
+                "bad_range_count": 0,
+                "bad_blocks": 0,
+                "bad_bytes": 0,
+                "scanned_all": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn repairs_are_reported() {
+        let params = DisplayParams {
+            verb: "Burning",
+            input_filename: "image.iso".to_owned(),
+            is_input_compressed: false,
+            targets: vec![target("sda")],
+        };
+        let handles = vec![handle(vec![
+            WriteVerifyEvent::TotalBytes {
+                src: 100,
+                dest: 100,
+            },
+            WriteVerifyEvent::FinishedWriting { verifying: true },
+            WriteVerifyEvent::Repairing {
+                attempt: 1,
+                bad_bytes: 1024,
+            },
+            WriteVerifyEvent::Repairing {
+                attempt: 2,
+                bad_bytes: 512,
+            },
+            WriteVerifyEvent::Success,
+        ])];
+
+        let mut out = vec![];
+        run_json_progress(&params, handles, &mut out).await.unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let repairing: Vec<_> = lines
+            .iter()
+            .filter(|l| l["event"] == "repairing")
+            .map(|l| {
+                (
+                    l["phase"].clone(),
+                    l["attempt"].clone(),
+                    l["bad_bytes"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            repairing,
+            [
+                (json!("verifying"), json!(1), json!(1024)),
+                (json!("verifying"), json!(2), json!(512)),
+            ]
+        );
+        assert_eq!(lines[0]["attempt"], Value::Null);
+
+        let sda = &lines.last().unwrap()["targets"][0];
+        assert_eq!(sda["result"], "success");
+        assert_eq!(sda["repaired_bytes"], 1024);
+        assert_eq!(sda["repair_attempts"], 2);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::{
+    device::{Removable, WriteTarget, enumerate_targets},
+    ui::cli::{ListArgs, ListFormat},
+};
+
+/// A disk, as it is described in `--format json` output.
+#[derive(Debug, Serialize)]
+struct ListedTarget<'a> {
+    name: &'a str,
+    devnode: String,
+    size_bytes: Option<u64>,
+    model: Option<&'a str>,
+    vendor: Option<&'a str>,
+    serial: Option<&'a str>,
+    transport: Option<String>,
+    removable: Option<bool>,
+    rotational: Option<bool>,
+    read_only: Option<bool>,
+    #[serde(rename = "type")]
+    target_type: String,
+    block_size_bytes: Option<u64>,
+    logical_block_size_bytes: Option<u64>,
+    partitions: Vec<ListedPartition<'a>>,
+    mountpoints: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListedPartition<'a> {
+    name: &'a str,
+    devnode: String,
+    size_bytes: Option<u64>,
+    label: Option<&'a str>,
+    mountpoints: Vec<String>,
+}
+
+fn path_strings(paths: &[PathBuf]) -> Vec<String> {
+    (paths.iter())
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect()
+}
+
+impl<'a> From<&'a WriteTarget> for ListedTarget<'a> {
+    fn from(t: &'a WriteTarget) -> Self {
+        Self {
+            name: &t.name,
+            devnode: t.devnode.to_string_lossy().into_owned(),
+            size_bytes: t.size.0.map(|s| s.as_u64()),
+            model: t.model.0.as_deref(),
+            vendor: t.vendor.as_deref(),
+            serial: t.serial.as_deref(),
+            transport: t.transport.map(|t| t.to_string()),
+            removable: match t.removable {
+                Removable::Yes => Some(true),
+                Removable::No => Some(false),
+                Removable::Unknown => None,
+            },
+            rotational: t.rotational,
+            read_only: t.read_only,
+            target_type: t.target_type.to_string(),
+            block_size_bytes: t.block_size.0.map(|s| s.as_u64()),
+            logical_block_size_bytes: t.logical_block_size.0.map(|s| s.as_u64()),
+            partitions: (t.partitions.iter())
+                .map(|p| ListedPartition {
+                    name: &p.name,
+                    devnode: p.devnode.to_string_lossy().into_owned(),
+                    size_bytes: p.size.0.map(|s| s.as_u64()),
+                    label: p.label.as_deref(),
+                    mountpoints: path_strings(&p.mountpoints),
+                })
+                .collect(),
+            mountpoints: path_strings(&t.mountpoints),
+        }
+    }
+}
+
+pub fn list_main(args: &ListArgs) -> anyhow::Result<()> {
+    let targets = enumerate_targets(args.show_all_disks, false);
+    if targets.is_empty() && !args.show_all_disks {
+        eprintln!("No removable disks were found. To show all disks, use --show-all-disks");
+    }
+
+    let mut stdout = io::stdout().lock();
+    match args.format {
+        ListFormat::Table => write_table(&mut stdout, &targets)?,
+        ListFormat::Json => write_json(&mut stdout, &targets)?,
+    }
+    Ok(())
+}
+
+fn write_table(out: &mut impl Write, targets: &[WriteTarget]) -> io::Result<()> {
+    fn or_unknown(x: Option<impl ToString>) -> String {
+        x.map_or_else(|| "unknown".to_owned(), |x| x.to_string())
+    }
+
+    let header = [
+        "NAME",
+        "DEVICE",
+        "SIZE",
+        "VENDOR",
+        "MODEL",
+        "TRANSPORT",
+        "REMOVABLE",
+        "TYPE",
+        "BLOCK SIZE",
+    ]
+    .map(str::to_owned);
+    let rows: Vec<[String; 9]> = std::iter::once(header)
+        .chain(targets.iter().map(|t| {
+            [
+                t.name.clone(),
+                t.devnode.to_string_lossy().into_owned(),
+                or_unknown(t.size.0),
+                or_unknown(t.vendor.as_ref()),
+                or_unknown(t.model.0.as_ref()),
+                or_unknown(t.transport),
+                t.removable.to_string(),
+                t.target_type.to_string(),
+                or_unknown(t.block_size.0),
+            ]
+        }))
+        .collect();
+
+    let mut widths = [0; 9];
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let line = (row.iter().zip(widths))
+            .map(|(cell, w)| format!("{cell:w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+fn write_json(out: &mut impl Write, targets: &[WriteTarget]) -> io::Result<()> {
+    let listed: Vec<ListedTarget> = targets.iter().map(ListedTarget::from).collect();
+    serde_json::to_writer_pretty(&mut *out, &listed)?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytesize::ByteSize;
+    use serde_json::json;
+
+    use crate::device::{
+        BlockSize, Model, Partition, Removable, TargetSize, Transport, Type, WriteTarget,
+    };
+
+    use super::{write_json, write_table};
+
+    fn targets() -> Vec<WriteTarget> {
+        vec![
+            WriteTarget {
+                name: "sda".into(),
+                devnode: "/dev/sda".into(),
+                size: TargetSize(Some(ByteSize::b(16_000_000_000))),
+                model: Model(Some("Ultra Fit".into())),
+                vendor: Some("SanDisk".into()),
+                serial: Some("4C530001".into()),
+                transport: Some(Transport::Usb),
+                removable: Removable::Yes,
+                rotational: Some(false),
+                read_only: Some(false),
+                target_type: Type::Disk,
+                block_size: BlockSize(Some(ByteSize::b(512))),
+                logical_block_size: BlockSize(Some(ByteSize::b(512))),
+                partitions: vec![Partition {
+                    name: "sda1".into(),
+                    devnode: "/dev/sda1".into(),
+                    size: TargetSize(Some(ByteSize::b(15_000_000_000))),
+                    label: Some("Ubuntu 24.04".into()),
+                    mountpoints: vec!["/media/ubuntu".into()],
+                }],
+                mountpoints: vec![],
+                system_uses: vec![],
+            },
+            WriteTarget {
+                name: "mmcblk0p1".into(),
+                devnode: "/dev/mmcblk0p1".into(),
+                size: TargetSize(None),
+                model: Model(None),
+                vendor: None,
+                serial: None,
+                transport: None,
+                removable: Removable::Unknown,
+                rotational: None,
+                read_only: None,
+                target_type: Type::Partition,
+                block_size: BlockSize(None),
+                logical_block_size: BlockSize(None),
+                partitions: vec![],
+                mountpoints: vec!["/boot".into()],
+                system_uses: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn table_lines_up_columns() {
+        let mut out = vec![];
+        write_table(&mut out, &targets()).unwrap();
+
+        let expected = [
+            "NAME       DEVICE          SIZE     VENDOR   MODEL      TRANSPORT  REMOVABLE  TYPE       BLOCK SIZE",
+            "sda        /dev/sda        16.0 GB  SanDisk  Ultra Fit  usb        yes        disk       512 B",
+            "mmcblk0p1  /dev/mmcblk0p1  unknown  unknown  unknown    unknown    unknown    partition  unknown",
+        ];
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn json_has_raw_values() {
+        let mut out = vec![];
+        write_json(&mut out, &targets()).unwrap();
+
+        let actual: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            actual,
+            json!([
+                {
+                    "name": "sda",
+                    "devnode": "/dev/sda",
+                    "size_bytes": 16_000_000_000u64,
+                    "model": "Ultra Fit",
+                    "vendor": "SanDisk",
+                    "serial": "4C530001",
+                    "transport": "usb",
+                    "removable": true,
+                    "rotational": false,
+                    "read_only": false,
+                    "type": "disk",
+                    "block_size_bytes": 512,
+                    "logical_block_size_bytes": 512,
+                    "partitions": [
+                        {
+                            "name": "sda1",
+                            "devnode": "/dev/sda1",
+                            "size_bytes": 15_000_000_000u64,
+                            "label": "Ubuntu 24.04",
+                            "mountpoints": ["/media/ubuntu"],
+                        },
+                    ],
+                    "mountpoints": [],
+                },
+                {
+                    "name": "mmcblk0p1",
+                    "devnode": "/dev/mmcblk0p1",
+                    "size_bytes": null,
+                    "model": null,
+                    "vendor": null,
+                    "serial": null,
+                    "transport": null,
+                    "removable": null,
+                    "rotational": null,
+                    "read_only": null,
+                    "type": "partition",
+                    "block_size_bytes": null,
+                    "logical_block_size_bytes": null,
+                    "partitions": [],
+                    "mountpoints": ["/boot"],
+                },
+            ])
+        );
+    }
+}
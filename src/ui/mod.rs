@@ -1,5 +1,9 @@
+mod checkpoints;
 mod cli;
 mod fancy_ui;
+mod json_ui;
+mod list;
+mod piped_image;
 mod simple_ui;
 mod start;
 mod utils;
@@ -7,49 +11,153 @@ mod writer_tracking;
 
 use std::{fs::File, path::Path, sync::Arc};
 
-pub use self::cli::BurnArgs;
+pub use self::cli::{BurnArgs, CaptureArgs, ListArgs, VerifyArgs, WipeArgs};
+pub use self::list::list_main;
 pub use self::utils::ByteSpeed;
 use crate::{
+    exit_code::{AlreadyReported, ExitCode},
     herder_facade::make_herder_facade_impl,
     logging::LogPaths,
     tty::TermiosRestore,
     ui::{
-        simple_ui::do_setup_wizard,
-        start::{begin_writing, try_start_burn},
+        json_ui::json_progress_output,
+        simple_ui::{do_capture_wizard, do_setup_wizard, do_verify_wizard, do_wipe_wizard},
+        start::{begin_writing, start_burns, start_herds, try_start_herd},
     },
 };
 use tracing::{debug, info};
 
 pub async fn main(
-    _state_dir: &Path,
+    state_dir: &Path,
     log_paths: Arc<LogPaths>,
     args: &BurnArgs,
 ) -> anyhow::Result<()> {
-    let _termios_restore = match File::open("/dev/tty") {
-        Ok(tty) => TermiosRestore::new(tty).ok(),
-        Err(error) => {
-            info!(
-                ?error,
-                "failed to open /dev/tty, will not attempt to restore after program"
-            );
-            None
-        }
+    let _termios_restore = store_termios();
+    let json_progress = json_progress_output(&args.progress)?;
+
+    let Some(begin_params) = do_setup_wizard(args)? else {
+        return Err(AlreadyReported(ExitCode::Aborted).into());
     };
 
-    let Some(begin_params) = do_setup_wizard(&args)? else {
-        return Ok(());
+    let mut herder = make_herder_facade_impl(log_paths.main());
+    let handles = start_burns(
+        &mut herder,
+        &begin_params,
+        state_dir,
+        args.root,
+        args.interactive.is_interactive(),
+    )
+    .await?;
+    begin_writing(
+        args.interactive,
+        json_progress,
+        (&begin_params).into(),
+        handles,
+        log_paths,
+    )
+    .await?;
+
+    debug!("Done!");
+    Ok(())
+}
+
+pub async fn capture_main(log_paths: Arc<LogPaths>, args: &CaptureArgs) -> anyhow::Result<()> {
+    let _termios_restore = store_termios();
+    let json_progress = json_progress_output(&args.progress)?;
+
+    let Some(capture_params) = do_capture_wizard(args)? else {
+        return Err(AlreadyReported(ExitCode::Aborted).into());
+    };
+
+    let mut herder = make_herder_facade_impl(log_paths.main());
+    let (handle, _) = try_start_herd(
+        &mut herder,
+        &capture_params.make_child_config(),
+        &capture_params.source.devnode,
+        args.root,
+        args.interactive.is_interactive(),
+    )
+    .await?;
+    begin_writing(
+        args.interactive,
+        json_progress,
+        (&capture_params).into(),
+        vec![handle],
+        log_paths,
+    )
+    .await?;
+
+    debug!("Done!");
+    Ok(())
+}
+
+pub async fn wipe_main(log_paths: Arc<LogPaths>, args: &WipeArgs) -> anyhow::Result<()> {
+    let _termios_restore = store_termios();
+    let json_progress = json_progress_output(&args.progress)?;
+
+    let Some(wipe_params) = do_wipe_wizard(args)? else {
+        return Err(AlreadyReported(ExitCode::Aborted).into());
     };
 
     let mut herder = make_herder_facade_impl(log_paths.main());
-    let handle = try_start_burn(
+    let handles = start_herds(
         &mut herder,
-        &begin_params.make_child_config(),
+        &wipe_params.targets,
+        |t| wipe_params.make_child_config(t),
         args.root,
         args.interactive.is_interactive(),
     )
     .await?;
-    begin_writing(args.interactive, begin_params, handle, log_paths).await?;
+    begin_writing(
+        args.interactive,
+        json_progress,
+        (&wipe_params).into(),
+        handles,
+        log_paths,
+    )
+    .await?;
 
     debug!("Done!");
     Ok(())
 }
+
+pub async fn verify_main(log_paths: Arc<LogPaths>, args: &VerifyArgs) -> anyhow::Result<()> {
+    let _termios_restore = store_termios();
+    let json_progress = json_progress_output(&args.progress)?;
+
+    let verify_params = do_verify_wizard(args)?;
+
+    let mut herder = make_herder_facade_impl(log_paths.main());
+    let handles = start_herds(
+        &mut herder,
+        &verify_params.targets,
+        |t| verify_params.make_child_config(t),
+        args.root,
+        args.interactive.is_interactive(),
+    )
+    .await?;
+    begin_writing(
+        args.interactive,
+        json_progress,
+        (&verify_params).into(),
+        handles,
+        log_paths,
+    )
+    .await?;
+
+    debug!("Done!");
+    Ok(())
+}
+
+fn store_termios() -> Option<TermiosRestore<File>> {
+    match File::open("/dev/tty") {
+        Ok(tty) => TermiosRestore::new(tty).ok(),
+        Err(error) => {
+            info!(
+                ?error,
+                "failed to open /dev/tty, will not attempt to restore after program"
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Support for burning images that are piped in or downloaded, rather than stored in
+//! files.
+//!
+//! The herder daemon can't read our stdin, because its own stdin is used for IPC, and
+//! it may be running as another user who can't open our pipes. Instead, each herd
+//! gets a FIFO in the state directory to read from, and we copy the image into all
+//! of them.
+
+use std::{
+    ffi::CString,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::{StreamExt, stream::BoxStream};
+use tracing::{debug, info, warn};
+
+use crate::{
+    download,
+    herder_daemon::ipc::{WriteVerifyError, WriteVerifyEvent},
+};
+
+/// How much of the image to read up front, to detect what format it's in.
+const HEAD_LEN: u64 = 64 * 1024;
+
+/// How much to copy into the FIFOs at a time.
+const FEED_BUF_LEN: usize = 1 << 20;
+
+/// How long to wait between attempts at opening FIFOs that nobody is reading yet.
+const OPEN_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+type Source = Box<dyn Read + Send>;
+
+/// An image that can only be read once, from start to end.
+#[derive(Clone)]
+pub struct PipedImage {
+    name: String,
+    size: Option<u64>,
+    source: Arc<Mutex<Source>>,
+    /// The start of the image, which has already been read out of `source`.
+    head: Vec<u8>,
+    /// Why reading the rest of `source` failed, if it did.
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl PartialEq for PipedImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.size == other.size && self.head == other.head
+    }
+}
+
+impl Eq for PipedImage {}
+
+impl fmt::Debug for PipedImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipedImage")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("head_len", &self.head.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PipedImage {
+    /// Open the pipe at `path`, or stdin if `path` is `-`, and read the start of it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (name, source) = if path == Path::new("-") {
+            let stdin = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+            ("stdin".to_owned(), stdin)
+        } else {
+            (path.to_string_lossy().into_owned(), File::open(path)?)
+        };
+        Self::from_source(name, None, Box::new(source))
+    }
+
+    /// Start downloading the image at `url`, and read the start of it.
+    pub fn download(url: &str) -> anyhow::Result<Self> {
+        let download = download::get(url)?;
+        Ok(Self::from_source(
+            url.to_owned(),
+            download.size,
+            download.body,
+        )?)
+    }
+
+    fn from_source(name: String, size: Option<u64>, mut source: Source) -> io::Result<Self> {
+        let mut head = Vec::with_capacity(HEAD_LEN as usize);
+        (&mut source).take(HEAD_LEN).read_to_end(&mut head)?;
+        debug!(len = head.len(), "Read head of piped image");
+
+        Ok(Self {
+            name,
+            size,
+            source: Arc::new(Mutex::new(source)),
+            head,
+            failure: Arc::default(),
+        })
+    }
+
+    /// A user-friendly name for where the image is coming from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How big the image is, if we were told ahead of time.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The start of the image, for detecting its format.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Make a FIFO in `dir` for each of `n` herds, and start copying the image into
+    /// them in the background.
+    ///
+    /// Copying starts once every FIFO has been opened by its herd, or let go of. Keep
+    /// each [Fifo] around until its herd has either started or failed to, so that a
+    /// herd that never opens its FIFO doesn't hold up the rest.
+    pub fn feed(&self, dir: &Path, n: usize) -> io::Result<Vec<Fifo>> {
+        let fifos: Vec<Fifo> = (0..n)
+            .map(|i| Fifo {
+                path: dir.join(format!("image-{i}.fifo")),
+                released: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        for fifo in &fifos {
+            mkfifo(&fifo.path)?;
+        }
+
+        let image = self.clone();
+        let pending = (fifos.iter())
+            .map(|f| (f.path.clone(), f.released.clone()))
+            .collect();
+        std::thread::Builder::new()
+            .name("image-feeder".to_owned())
+            .spawn(move || match image.feed_blocking(pending) {
+                Ok(()) => info!("Finished feeding piped image"),
+                Err(error) => warn!(?error, "Failed to feed piped image"),
+            })?;
+
+        Ok(fifos)
+    }
+
+    /// Make a herd's `events` end in an error if the image stopped coming in partway
+    /// through, since all the herd sees is the image ending early.
+    pub fn check_feed(
+        &self,
+        events: BoxStream<'static, WriteVerifyEvent>,
+    ) -> BoxStream<'static, WriteVerifyEvent> {
+        let failure = self.failure.clone();
+        events
+            .map(move |e| match e {
+                WriteVerifyEvent::FinishedWriting { .. } | WriteVerifyEvent::Success => {
+                    match failure.lock().unwrap().clone() {
+                        Some(error) => {
+                            WriteVerifyEvent::Error(WriteVerifyError::SourceFailed(error))
+                        }
+                        None => e,
+                    }
+                }
+                e => e,
+            })
+            .scan(false, |failed, e| {
+                let done = *failed;
+                *failed = matches!(e, WriteVerifyEvent::Error(_));
+                std::future::ready((!done).then_some(e))
+            })
+            .boxed()
+    }
+
+    fn feed_blocking(&self, mut pending: Vec<(PathBuf, Arc<AtomicBool>)>) -> io::Result<()> {
+        let mut sinks = Vec::with_capacity(pending.len());
+        loop {
+            let mut i = 0;
+            while i < pending.len() {
+                let (path, released) = &pending[i];
+                // Checked before trying to open it, so that a herd which opened it
+                // and then let go is never mistaken for one that gave up.
+                let released = released.load(Ordering::Acquire);
+                match open_for_writing(path)? {
+                    Some(sink) => {
+                        debug!(?path, "Herd opened FIFO");
+                        sinks.push(sink);
+                    }
+                    None if released => warn!(?path, "Herd never opened FIFO, skipping it"),
+                    None => {
+                        i += 1;
+                        continue;
+                    }
+                }
+                std::fs::remove_file(path)?;
+                pending.swap_remove(i);
+            }
+            if pending.is_empty() {
+                break;
+            }
+            std::thread::sleep(OPEN_RETRY_INTERVAL);
+        }
+        if sinks.is_empty() {
+            return Ok(());
+        }
+
+        let mut source = self.source.lock().unwrap();
+        let mut buf = vec![0u8; FEED_BUF_LEN];
+        let mut chunk = &self.head[..];
+        loop {
+            if chunk.is_empty() {
+                let read = source.read(&mut buf).inspect_err(|error| {
+                    // Recorded before the FIFOs are closed, so it's there by the time
+                    // the herds see the image end
+                    *self.failure.lock().unwrap() = Some(format!("{}: {error}", self.name));
+                })?;
+                if read == 0 {
+                    return Ok(());
+                }
+                chunk = &buf[..read];
+            }
+
+            // If a herd stops reading, it has failed, and it reports that itself.
+            // The rest of them should carry on.
+            sinks.retain_mut(|s| match s.write_all(chunk) {
+                Ok(()) => true,
+                Err(error) => {
+                    warn!(?error, "Herd stopped reading piped image");
+                    false
+                }
+            });
+            if sinks.is_empty() {
+                return Ok(());
+            }
+            chunk = &[];
+        }
+    }
+}
+
+/// A FIFO that a herd reads the piped image from. Dropping it tells the feeder that
+/// nobody will open it if they haven't already.
+#[derive(Debug)]
+pub struct Fifo {
+    path: PathBuf,
+    released: Arc<AtomicBool>,
+}
+
+impl Fifo {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        self.released.store(true, Ordering::Release);
+    }
+}
+
+/// Open the FIFO at `path` for writing, or return `None` if nobody has it open for
+/// reading yet. Unlike a plain open, this never blocks.
+fn open_for_writing(path: &Path) -> io::Result<Option<File>> {
+    let file = match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // Writes should block until the herd catches up, though
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(file))
+}
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::mpsc};
+
+    use super::*;
+
+    #[test]
+    fn herd_that_never_opens_its_fifo_does_not_hold_up_the_others() {
+        let dir =
+            std::env::temp_dir().join(format!("caligula-piped-image-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..3 * FEED_BUF_LEN).map(|i| (i % 251) as u8).collect();
+        let image =
+            PipedImage::from_source("test".to_owned(), None, Box::new(Cursor::new(data.clone())))
+                .unwrap();
+
+        let mut fifos = image.feed(&dir, 2).unwrap();
+        let never_opened = fifos.pop().unwrap();
+        let opened = fifos.pop().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let path = opened.path().to_owned();
+        std::thread::spawn(move || {
+            let mut read = vec![];
+            File::open(path).unwrap().read_to_end(&mut read).unwrap();
+            tx.send(read).unwrap();
+        });
+        drop(never_opened);
+
+        let read = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        drop(opened);
+        assert!(read == data, "herd read the wrong image");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Gives `len` bytes, then fails like a stalled download.
+    struct Stalls(usize);
+
+    impl Read for Stalls {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+            }
+            let n = buf.len().min(self.0);
+            buf[..n].fill(1);
+            self.0 -= n;
+            Ok(n)
+        }
+    }
+
+    #[tokio::test]
+    async fn herd_fails_if_the_image_stops_coming_in() {
+        let dir = std::env::temp_dir().join(format!(
+            "caligula-piped-image-stall-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = PipedImage::from_source(
+            "https://example.com/disk.img".to_owned(),
+            None,
+            Box::new(Stalls(2 * FEED_BUF_LEN)),
+        )
+        .unwrap();
+
+        let fifos = image.feed(&dir, 1).unwrap();
+        let mut read = vec![];
+        File::open(fifos[0].path())
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read.len(), 2 * FEED_BUF_LEN);
+
+        let herd_events = futures::stream::iter([
+            WriteVerifyEvent::FinishedWriting { verifying: true },
+            WriteVerifyEvent::Success,
+        ])
+        .boxed();
+        let events: Vec<_> = image.check_feed(herd_events).collect().await;
+        assert_eq!(
+            events,
+            [WriteVerifyEvent::Error(WriteVerifyError::SourceFailed(
+                "https://example.com/disk.img: timed out".to_owned()
+            ))]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,7 +2,6 @@ use std::{
     fs::File,
     io::{BufReader, Seek},
     path::Path,
-    process::exit,
 };
 
 use anyhow::Context;
@@ -11,14 +10,28 @@ use indicatif::{ProgressBar, ProgressStyle};
 use inquire::{Confirm, Select, Text};
 
 use crate::{
+    archive::{ArchiveMember, ArchiveMemberRead},
     compression::{CompressionFormat, decompress},
-    hash::{FileHashInfo, HashAlg, Hashing, parse_hash_input},
-    hashfile::{find_hash_in_standard_files, find_hash_in_user_file},
+    exit_code::{AlreadyReported, ExitCode},
+    hash::{ExpectedHash, FileHashInfo, HashAlg, Hashing, parse_hash_input},
+    hashfile::{find_hash_at_url, find_hash_in_standard_files, find_hash_in_user_file},
+    herder_daemon::ipc::ImageHash,
     ui::cli::{BurnArgs, HashArg, HashOf},
 };
 
+/// Asks for the image's hash, and unless the hash is to be checked while writing,
+/// checks the image against it.
+///
+/// Returns the image's hash, if one was provided.
 #[tracing::instrument(skip_all, fields(cf))]
-pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option<FileHashInfo>> {
+pub fn ask_hash(
+    args: &BurnArgs,
+    cf: CompressionFormat,
+    archive_member: Option<&ArchiveMember>,
+) -> anyhow::Result<Option<ImageHash>> {
+    // If the image is compressed or archived, the hash could be of either the image or
+    // of the file containing it.
+    let is_packed = !cf.is_identity() || archive_member.is_some();
     let hash_params = match (&args.hash, &args.hash_file) {
         (_, Some(hash_file)) => {
             let Some((algs, _, expected_hash)) = find_hash_in_user_file(&args.image, hash_file)
@@ -27,7 +40,7 @@ pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option
                     "Could not parse {} as a valid hash file!",
                     hash_file.to_string_lossy()
                 );
-                exit(-1);
+                return Err(AlreadyReported(ExitCode::Usage).into());
             };
 
             eprintln!(
@@ -37,12 +50,16 @@ pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option
             Some(BeginHashParams {
                 expected_hash,
                 alg: ask_alg(&algs)?,
-                hasher_compression: ask_hasher_compression(cf, args.hash_of)?,
+                of_compressed: ask_hash_of(is_packed, args.hash_of)?,
             })
         }
         (HashArg::Skip, _) => None,
         (HashArg::Ask, _) => {
-            match find_hash_in_standard_files(&args.image) {
+            let found = match args.image_url() {
+                Some(url) => find_hash_at_url(url),
+                None => find_hash_in_standard_files(&args.image),
+            };
+            match found {
                 Some((algs, expected_hashfile, expected_hash))
                     if Confirm::new(&format!(
                         "Detected hash file {expected_hashfile} in the directory. Do you want to use it?"
@@ -53,16 +70,16 @@ pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option
                     Some(BeginHashParams {
                         expected_hash,
                         alg: ask_alg(&algs)?,
-                        hasher_compression: ask_hasher_compression(cf, args.hash_of)?,
+                        of_compressed: ask_hash_of(is_packed, args.hash_of)?,
                     })
                 }
-                _ => ask_hash_loop(cf)?,
+                _ => ask_hash_loop(is_packed)?,
             }
         }
         (HashArg::Hash { alg, expected_hash }, _) => Some(BeginHashParams {
             expected_hash: expected_hash.clone(),
             alg: *alg,
-            hasher_compression: ask_hasher_compression(cf, args.hash_of)?,
+            of_compressed: ask_hash_of(is_packed, args.hash_of)?,
         }),
     };
 
@@ -72,7 +89,26 @@ pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option
         return Ok(None);
     };
 
-    let hash_result = do_hashing(&args.image, &params)?;
+    let image_hash = ImageHash {
+        expected: ExpectedHash {
+            alg: params.alg,
+            hash: params.expected_hash.clone(),
+        },
+        of_compressed: params.of_compressed,
+    };
+
+    if args.hash_inline {
+        eprintln!("The disk image will be checked against the hash while writing it.");
+        return Ok(Some(image_hash));
+    }
+    if args.image_is_piped() {
+        eprintln!(
+            "The disk image can only be read once, so it will be checked against the hash while writing it."
+        );
+        return Ok(Some(image_hash));
+    }
+
+    let hash_result = do_hashing(&args.image, &params, cf, archive_member)?;
 
     if hash_result.file_hash == params.expected_hash {
         eprintln!("Disk image verified successfully!");
@@ -87,16 +123,16 @@ pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option
             base16::encode_lower(&hash_result.file_hash)
         );
         eprintln!("Your disk image may be corrupted!");
-        exit(-1);
+        return Err(AlreadyReported(ExitCode::InputHashMismatch).into());
     }
 
-    Ok(Some(hash_result))
+    Ok(Some(image_hash))
 }
 
 #[tracing::instrument]
-fn ask_hash_loop(cf: CompressionFormat) -> anyhow::Result<Option<BeginHashParams>> {
+fn ask_hash_loop(is_packed: bool) -> anyhow::Result<Option<BeginHashParams>> {
     loop {
-        match ask_hash_once(cf) {
+        match ask_hash_once(is_packed) {
             Ok(bhp) => {
                 return Ok(Some(bhp));
             }
@@ -113,7 +149,7 @@ fn ask_hash_loop(cf: CompressionFormat) -> anyhow::Result<Option<BeginHashParams
 }
 
 #[tracing::instrument]
-fn ask_hash_once(cf: CompressionFormat) -> anyhow::Result<BeginHashParams> {
+fn ask_hash_once(is_packed: bool) -> anyhow::Result<BeginHashParams> {
     let input_hash = Text::new("What is the file's hash?")
         .with_help_message(
             "We will guess the hash algorithm from your input. Press ESC or type \"skip\" to skip.",
@@ -133,12 +169,12 @@ fn ask_hash_once(cf: CompressionFormat) -> anyhow::Result<BeginHashParams> {
 
     let alg = ask_alg(&algs)?;
 
-    let hasher_compression = ask_hasher_compression(cf, None)?;
+    let of_compressed = ask_hash_of(is_packed, None)?;
 
     Ok(BeginHashParams {
         expected_hash: hash,
         alg,
-        hasher_compression,
+        of_compressed,
     })
 }
 
@@ -164,13 +200,11 @@ fn ask_alg(algs: &[HashAlg]) -> anyhow::Result<HashAlg> {
     }
 }
 
+/// Returns true if the hash is of the compressed file rather than the raw image.
 #[tracing::instrument]
-fn ask_hasher_compression(
-    cf: CompressionFormat,
-    hash_of: Option<HashOf>,
-) -> anyhow::Result<CompressionFormat> {
-    if cf.is_identity() {
-        return Ok(cf);
+fn ask_hash_of(is_packed: bool, hash_of: Option<HashOf>) -> anyhow::Result<bool> {
+    if !is_packed {
+        return Ok(false);
     }
 
     let ans = hash_of.map(Ok).unwrap_or_else(|| {
@@ -181,14 +215,16 @@ fn ask_hasher_compression(
         .prompt()
     })?;
 
-    Ok(match ans {
-        HashOf::Raw => cf,
-        HashOf::Compressed => CompressionFormat::Identity,
-    })
+    Ok(ans == HashOf::Compressed)
 }
 
 #[tracing::instrument(skip_all, fields(path))]
-fn do_hashing(path: &Path, params: &BeginHashParams) -> anyhow::Result<FileHashInfo> {
+fn do_hashing(
+    path: &Path,
+    params: &BeginHashParams,
+    cf: CompressionFormat,
+    archive_member: Option<&ArchiveMember>,
+) -> anyhow::Result<FileHashInfo> {
     let mut file = File::open(path)?;
 
     // Calculate total file size
@@ -201,8 +237,16 @@ fn do_hashing(path: &Path, params: &BeginHashParams) -> anyhow::Result<FileHashI
             .unwrap(),
     );
 
-    let decompress = decompress(params.hasher_compression, BufReader::new(file))
+    let (cf, archive_member) = match params.of_compressed {
+        true => (CompressionFormat::Identity, None),
+        false => (cf, archive_member),
+    };
+    let decompress = decompress(cf, BufReader::new(file))
         .context("Failed to open input file with decompressor")?;
+    let decompress = match archive_member {
+        Some(m) => ArchiveMemberRead::new(decompress, m),
+        None => ArchiveMemberRead::passthrough(decompress),
+    };
 
     let mut hashing = Hashing::new(
         params.alg,
@@ -216,7 +260,13 @@ fn do_hashing(path: &Path, params: &BeginHashParams) -> anyhow::Result<FileHashI
                 None => return Ok(hashing.finalize()?),
             }
         }
-        progress_bar.set_position(hashing.get_reader_mut().get_mut().stream_position()?);
+        progress_bar.set_position(
+            hashing
+                .get_reader_mut()
+                .get_mut()
+                .get_mut()
+                .stream_position()?,
+        );
     }
 }
 
@@ -224,7 +274,8 @@ fn do_hashing(path: &Path, params: &BeginHashParams) -> anyhow::Result<FileHashI
 struct BeginHashParams {
     expected_hash: Vec<u8>,
     alg: HashAlg,
-    hasher_compression: CompressionFormat,
+    /// If true, the hash is of the file as-is, rather than of the image inside of it
+    of_compressed: bool,
 }
 
 /// A signaling error for the outer loop.
@@ -1,28 +1,197 @@
-use std::fmt;
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
 
 use inquire::{Confirm, InquireError, Select};
+use itertools::Itertools;
 use tracing::debug;
 
 use crate::{
-    compression::{AVAILABLE_FORMATS, CompressionArg, CompressionFormat},
-    device::{self, Removable, WriteTarget, enumerate_devices},
-    ui::{cli::BurnArgs, start::BeginParams},
+    archive::{self, ArchiveFormat, ArchiveMember},
+    compression::{AVAILABLE_FORMATS, CompressionArg, CompressionFormat, decompress},
+    device::{
+        self, WriteTarget, enumerate_targets,
+        watch::{NewTargets, POLL_INTERVAL},
+    },
+    exit_code::{AlreadyReported, ExitCode, UsageError},
+    ui::{
+        cli::{BurnArgs, CaptureArgs, Unmount, VerifyArgs, WipeArgs},
+        piped_image::PipedImage,
+        simple_ui::live_select::live_select,
+        start::{BeginParams, CaptureParams, WipeParams},
+    },
 };
 
+/// Figure out the input file's compression format, and if it is an archive, which
+/// member of it to burn.
+#[tracing::instrument(skip_all)]
+pub fn ask_compression(
+    args: &BurnArgs,
+    piped: Option<&PipedImage>,
+) -> anyhow::Result<(CompressionFormat, Option<ArchiveMember>)> {
+    let from_contents = match piped {
+        Some(p) => CompressionFormat::detect_from_contents(p.head()).ok(),
+        None => detect_from_contents(&args.image),
+    };
+    let cf = ask_compression_of(
+        "Input file",
+        &args.image,
+        args.compression,
+        args.force,
+        from_contents,
+    )?;
+    let member = match piped {
+        Some(p) => {
+            // Listing an archive would use up the pipe before we got to burn anything
+            if ArchiveFormat::detect(decompress(cf, p.head())?)?.is_some() {
+                bail!("Archives can't be burned from a pipe. Please save it to a file first");
+            }
+            if args.member.is_some() {
+                bail!(UsageError(
+                    "--member was provided, but the input file is not an archive".into()
+                ));
+            }
+            None
+        }
+        None => ask_archive_member(&args.image, args.member.as_deref(), args.force, cf)?,
+    };
+    Ok((cf, member))
+}
+
+/// Figure out the compression format of the image to verify against, and if it is an
+/// archive, which member of it was burned.
+#[tracing::instrument(skip_all)]
+pub fn ask_verify_compression(
+    args: &VerifyArgs,
+    image: &Path,
+) -> anyhow::Result<(CompressionFormat, Option<ArchiveMember>)> {
+    let cf = ask_compression_of(
+        "Image",
+        image,
+        args.compression,
+        false,
+        detect_from_contents(image),
+    )?;
+    let member = ask_archive_member(image, args.member.as_deref(), false, cf)?;
+    Ok((cf, member))
+}
+
+/// If `image` is an archive once decompressed, ask which member of it to use, unless
+/// `member` names it already. With `force`, we never ask.
+fn ask_archive_member(
+    image: &Path,
+    member: Option<&str>,
+    force: bool,
+    cf: CompressionFormat,
+) -> anyhow::Result<Option<ArchiveMember>> {
+    let open = || -> anyhow::Result<_> { decompress(cf, BufReader::new(File::open(image)?)) };
+    let Some(format) = ArchiveFormat::detect(open()?)? else {
+        if member.is_some() {
+            bail!(UsageError(
+                "--member was provided, but the input file is not an archive".into()
+            ));
+        }
+        return Ok(None);
+    };
+
+    eprintln!("Detected archive format: {format}");
+    let members = match format {
+        ArchiveFormat::Zip if cf.is_identity() => archive::list_zip(File::open(image)?)?,
+        ArchiveFormat::Zip => bail!("Zip archives inside of {cf} files are not supported"),
+        ArchiveFormat::Tar => archive::list_tar(open()?)?,
+    };
+
+    if let Some(name) = member {
+        let member = (members.into_iter())
+            .find(|m| m.name == name)
+            .with_context(|| format!("Could not find {name} in the archive"))?;
+        return Ok(Some(member));
+    }
+
+    let images = members
+        .iter()
+        .filter(|m| m.is_likely_image())
+        .cloned()
+        .collect::<Vec<_>>();
+    // If nothing is named like an image, the user probably knows something we don't
+    let candidates = if images.is_empty() { members } else { images };
+
+    match &candidates[..] {
+        [] => bail!("The archive does not contain any files"),
+        [only] => {
+            eprintln!("Using file from archive: {only}");
+            Ok(Some(only.clone()))
+        }
+        _ if force => {
+            bail!(UsageError(
+                "The archive contains more than one disk image. Please select one with --member"
+                    .into()
+            ))
+        }
+        _ => Ok(Some(
+            Select::new("Which file in the archive do you want to use?", candidates).prompt()?,
+        )),
+    }
+}
+
 #[tracing::instrument(skip_all)]
-pub fn ask_compression(args: &BurnArgs) -> anyhow::Result<CompressionFormat> {
-    let cf = match args.compression {
+pub fn ask_capture_compression(args: &CaptureArgs) -> anyhow::Result<CompressionFormat> {
+    // The output file doesn't exist yet, so there's nothing to look inside of.
+    ask_compression_of(
+        "Output file",
+        &args.image,
+        args.compression,
+        args.force,
+        None,
+    )
+}
+
+/// Sniff the compression format from the file's contents, if we can read it.
+fn detect_from_contents(image: &Path) -> Option<CompressionFormat> {
+    match File::open(image).and_then(CompressionFormat::detect_from_contents) {
+        Ok(cf) => Some(cf),
+        Err(error) => {
+            debug!(?error, "Could not read file to detect compression");
+            None
+        }
+    }
+}
+
+/// Figure out the compression format of `image`. `label` describes what `image` is to
+/// the user. `from_contents` is what the file's contents look like, if known, and takes
+/// precedence over the extension when detecting.
+fn ask_compression_of(
+    label: &str,
+    image: &Path,
+    compression: CompressionArg,
+    force: bool,
+    from_contents: Option<CompressionFormat>,
+) -> anyhow::Result<CompressionFormat> {
+    let cf = match compression {
         CompressionArg::Auto | CompressionArg::Ask => {
-            CompressionFormat::detect_from_path(&args.image)
+            let from_path = CompressionFormat::detect_from_path(image);
+            if let (Some(p), Some(c)) = (from_path, from_contents)
+                && p != c
+            {
+                eprintln!(
+                    "Warning: the file extension suggests {p}, but the contents look like {c}!"
+                );
+            }
+            from_contents.or(from_path)
         }
         other => other.associated_format(),
     };
 
     if let Some(cf) = cf {
-        eprintln!("Input file: {}", args.image.to_string_lossy());
+        eprintln!("{label}: {}", image.to_string_lossy());
         eprintln!("Detected compression format: {}", cf);
 
-        if args.force || args.compression != CompressionArg::Ask {
+        if force || compression != CompressionArg::Ask {
             return Ok(cf);
         }
 
@@ -34,110 +203,280 @@ pub fn ask_compression(args: &BurnArgs) -> anyhow::Result<CompressionFormat> {
 
     eprintln!(
         "Couldn't detect compression format for {}",
-        args.image.to_string_lossy()
+        image.to_string_lossy()
     );
-    if args.force {
+    if force {
         eprintln!("Since --force was provided, assuming it's uncompressed!");
         return Ok(CompressionFormat::Identity);
     }
     let format = Select::new("What format to use?", AVAILABLE_FORMATS.to_vec()).prompt()?;
 
-    return Ok(format);
+    Ok(format)
 }
 
 #[tracing::instrument(skip_all)]
 pub fn ask_outfile(args: &BurnArgs) -> anyhow::Result<WriteTarget> {
-    let mut show_all_disks = args.show_all_disks;
+    ask_disk("Select target disk", args.show_all_disks, false)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ask_infile(args: &CaptureArgs) -> anyhow::Result<WriteTarget> {
+    // Reading the system disk is harmless, and is how you back it up
+    ask_disk("Select disk to capture", args.show_all_disks, true)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ask_wipe_target(args: &WipeArgs) -> anyhow::Result<WriteTarget> {
+    ask_disk("Select disk to wipe", args.show_all_disks, false)
+}
 
+#[tracing::instrument(skip_all)]
+pub fn ask_verify_target(args: &VerifyArgs) -> anyhow::Result<WriteTarget> {
+    // Reading the system disk is harmless
+    ask_disk("Select disk to verify", args.show_all_disks, true)
+}
+
+fn ask_disk(
+    message: &str,
+    mut show_all_disks: bool,
+    include_system: bool,
+) -> anyhow::Result<WriteTarget> {
     loop {
         debug!(show_all_disks, "Beginning loop");
 
-        let targets = enumerate_options(show_all_disks)?;
-
-        let ans = Select::new("Select target disk", targets)
-            .with_help_message(if show_all_disks {
+        let ans = live_select(
+            message,
+            if show_all_disks {
                 "Showing all disks. Proceed with caution!"
             } else {
                 "Only displaying removable disks."
-            })
-            .prompt()?;
+            },
+            || enumerate_options(show_all_disks, include_system),
+            ListOption::devnode,
+        )?;
 
         let dev = match ans {
-            ListOption::Device(dev) => dev,
+            ListOption::Device(dev) => *dev,
             ListOption::RetryWithShowAll(sa) => {
                 show_all_disks = sa;
                 continue;
             }
-            ListOption::Refresh => {
-                continue;
-            }
         };
         return Ok(dev);
     }
 }
 
+/// Wait for a removable disk that matches the filters in `args` to be plugged in.
 #[tracing::instrument(skip_all)]
-pub fn confirm_write(args: &BurnArgs, begin_params: &BeginParams) -> Result<bool, InquireError> {
+pub fn wait_for_outfile(args: &BurnArgs) -> WriteTarget {
+    let filter = args.device_filter();
+    let mut new_targets = NewTargets::new(&enumerate_targets(false, false));
+    eprintln!("Waiting for a removable disk to be plugged in...");
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        for target in new_targets.update(enumerate_targets(false, false)) {
+            if filter.matches(&target) {
+                eprintln!("Found {}", DiskDescription(&target));
+                return target;
+            }
+            eprintln!(
+                "Ignoring {}, since it doesn't match the --device-* filters",
+                DiskDescription(&target)
+            );
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn confirm_write(args: &BurnArgs, begin_params: &BeginParams) -> anyhow::Result<bool> {
+    // Burning would only fail partway through, so don't let it start
+    if let Some(size) = begin_params.output_size() {
+        for target in &begin_params.targets {
+            let Some(target_size) = target.size.0 else {
+                continue;
+            };
+            if size.exceeds(target_size.as_u64()) {
+                eprintln!(
+                    "The image is {size} once decompressed, but {} is only {target_size}, so it won't fit!",
+                    target.devnode.to_string_lossy()
+                );
+                return Err(AlreadyReported(ExitCode::TargetTooSmall).into());
+            }
+            // The size is only a hint, so it's up to the user
+            if size.min_bytes() > target_size.as_u64() {
+                eprintln!(
+                    "Warning: the image looks like it is {size} once decompressed, but {} is only {target_size}, so it might not fit.",
+                    target.devnode.to_string_lossy()
+                );
+            }
+        }
+    }
+
     if args.force {
         debug!("Skipping confirm because of --force");
         Ok(true)
     } else {
-        println!("{}", begin_params);
+        eprintln!("{}", begin_params);
 
-        Confirm::new("Is this okay?")
+        Ok(Confirm::new("Is this okay?")
             .with_help_message("THIS ACTION WILL DESTROY ALL DATA ON THIS DEVICE!!!")
             .with_default(false)
+            .prompt()?)
+    }
+}
+
+/// If any of the targets are mounted, make sure we're allowed to unmount them before
+/// `verb`ing them, i.e. "burning". Returns whether they need to be unmounted.
+#[tracing::instrument(skip_all)]
+pub fn ask_unmount(
+    unmount: Unmount,
+    force: bool,
+    verb: &str,
+    targets: &[WriteTarget],
+) -> anyhow::Result<bool> {
+    let mounted = (targets.iter())
+        .filter(|t| t.all_mountpoints().next().is_some())
+        .collect::<Vec<_>>();
+    if mounted.is_empty() {
+        return Ok(false);
+    }
+    let description = (mounted.iter())
+        .map(|t| {
+            format!(
+                "{} is mounted at {}",
+                t.devnode.to_string_lossy(),
+                t.all_mountpoints().map(|p| p.to_string_lossy()).join(", ")
+            )
+        })
+        .join("\n");
+
+    match unmount {
+        Unmount::Always => Ok(true),
+        Unmount::Ask if !force => {
+            eprintln!("{description}");
+            if !Confirm::new(&format!("Unmount before {verb}?"))
+                .with_help_message("Writing to a mounted disk would corrupt it")
+                .with_default(true)
+                .prompt()?
+            {
+                Err(InquireError::OperationCanceled)?;
+            }
+            Ok(true)
+        }
+        Unmount::Ask | Unmount::Never => bail!(UsageError(format!(
+            "{description}\nPlease unmount it first, or use --unmount=always"
+        ))),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn confirm_capture(
+    args: &CaptureArgs,
+    capture_params: &CaptureParams,
+) -> Result<bool, InquireError> {
+    if args.force {
+        debug!("Skipping confirm because of --force");
+        return Ok(true);
+    }
+
+    eprintln!("{}", capture_params);
+
+    let overwriting = capture_params.image_file.exists();
+    let confirm = Confirm::new("Is this okay?").with_default(!overwriting);
+    if overwriting {
+        confirm
+            .with_help_message("THE EXISTING IMAGE FILE WILL BE OVERWRITTEN!!!")
             .prompt()
+    } else {
+        confirm.prompt()
     }
 }
 
+#[tracing::instrument(skip_all)]
+pub fn confirm_wipe(args: &WipeArgs, wipe_params: &WipeParams) -> Result<bool, InquireError> {
+    if args.force {
+        debug!("Skipping confirm because of --force");
+        return Ok(true);
+    }
+
+    eprintln!("{}", wipe_params);
+
+    Confirm::new("Is this okay?")
+        .with_help_message("THIS ACTION WILL DESTROY ALL DATA ON THIS DEVICE!!!")
+        .with_default(false)
+        .prompt()
+}
+
 enum ListOption {
-    Device(WriteTarget),
-    Refresh,
+    Device(Box<WriteTarget>),
     RetryWithShowAll(bool),
 }
 
+impl ListOption {
+    /// What identifies this option as the list of disks changes.
+    fn devnode(&self) -> Option<PathBuf> {
+        match self {
+            ListOption::Device(dev) => Some(dev.devnode.clone()),
+            ListOption::RetryWithShowAll(_) => None,
+        }
+    }
+}
+
 impl fmt::Display for ListOption {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ListOption::Device(dev) => match dev.target_type {
-                device::Type::Disk => write!(
-                    f,
-                    "{} | {} - {} ({}, removable: {})",
-                    dev.name, dev.model, dev.size, dev.target_type, dev.removable
-                )?,
-                _ => write!(
-                    f,
-                    "{} | {} - {} ({})",
-                    dev.name, dev.model, dev.size, dev.target_type
-                )?,
-            },
+            ListOption::Device(dev) => {
+                write!(f, "{}", DiskDescription(dev))?;
+            }
             ListOption::RetryWithShowAll(true) => {
                 write!(f, "<Show all disks, removable or not>")?;
             }
             ListOption::RetryWithShowAll(false) => {
                 write!(f, "<Only show removable disks>")?;
             }
-            ListOption::Refresh => {
-                write!(f, "<Refresh devices>")?;
-            }
         }
         Ok(())
     }
 }
 
-#[tracing::instrument]
-fn enumerate_options(show_all_disks: bool) -> anyhow::Result<Vec<ListOption>> {
-    let mut burn_targets: Vec<WriteTarget> = enumerate_devices()
-        .filter(|d| show_all_disks || d.removable == Removable::Yes)
-        .collect();
+/// How a disk is described to the user when picking it.
+struct DiskDescription<'a>(&'a WriteTarget);
 
-    burn_targets.sort();
+impl fmt::Display for DiskDescription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dev = self.0;
+        write!(f, "{} | {} - {} (", dev.name, dev.description(), dev.size)?;
+        if let Some(t) = dev.transport {
+            write!(f, "{t} ")?;
+        }
+        write!(f, "{}", dev.target_type)?;
+        if dev.target_type == device::Type::Disk {
+            write!(f, ", removable: {}", dev.removable)?;
+        }
+        write!(f, ")")?;
 
-    let options = burn_targets.into_iter().map(ListOption::Device).chain([
-        ListOption::Refresh,
-        ListOption::RetryWithShowAll(!show_all_disks),
-    ]);
+        let labels = (dev.partitions.iter())
+            .filter_map(|p| p.label.as_deref())
+            .collect::<Vec<_>>();
+        if !labels.is_empty() {
+            write!(f, " [{}]", labels.join(", "))?;
+        }
+        let mountpoints = dev.all_mountpoints().collect::<Vec<_>>();
+        if !mountpoints.is_empty() {
+            write!(
+                f,
+                " mounted at {}",
+                mountpoints.iter().map(|p| p.display()).format(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
 
-    Ok(options.collect())
+fn enumerate_options(show_all_disks: bool, include_system: bool) -> Vec<ListOption> {
+    enumerate_targets(show_all_disks, include_system)
+        .into_iter()
+        .map(|t| ListOption::Device(Box::new(t)))
+        .chain([ListOption::RetryWithShowAll(!show_all_disks)])
+        .collect()
 }
@@ -0,0 +1,328 @@
+//! A list to pick from, like [inquire::Select], but whose options are looked up again
+//! every so often while it's shown. This is so that disks show up in the list as they
+//! are plugged in, and disappear when they are removed.
+
+use std::{
+    fmt::Display,
+    io::{self, Stderr, Write},
+    time::Instant,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveToColumn, MoveUp, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{PrintStyledContent, Stylize},
+    terminal::{self, Clear, ClearType, disable_raw_mode, enable_raw_mode},
+};
+use inquire::InquireError;
+use is_terminal::IsTerminal;
+use tracing_unwrap::ResultExt;
+
+use crate::device::watch::POLL_INTERVAL;
+
+/// How many options are shown at once. This is the same as inquire's default.
+const PAGE_SIZE: usize = 7;
+
+/// Ask the user to pick one of `options()`, which is called again every
+/// [POLL_INTERVAL]. `key` identifies an option across calls, so that the same one stays
+/// selected when the list changes.
+pub fn live_select<T: Display, K: PartialEq>(
+    message: &str,
+    help_message: &str,
+    mut options: impl FnMut() -> Vec<T>,
+    key: impl Fn(&T) -> K,
+) -> Result<T, InquireError> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(InquireError::NotTTY);
+    }
+
+    let mut state = SelectState::new(options());
+    let mut screen = Screen::new()?;
+    let mut last_refresh = Instant::now();
+    loop {
+        screen.draw(&state.lines(message, help_message))?;
+
+        if event::poll(POLL_INTERVAL.saturating_sub(last_refresh.elapsed()))?
+            && let Event::Key(k) = event::read()?
+            && k.kind != KeyEventKind::Release
+        {
+            match state.on_key(k) {
+                Some(Outcome::Submit) => break,
+                Some(Outcome::Cancel) => return Err(InquireError::OperationCanceled),
+                Some(Outcome::Interrupt) => return Err(InquireError::OperationInterrupted),
+                None => {}
+            }
+        }
+
+        if last_refresh.elapsed() >= POLL_INTERVAL {
+            state.set_options(options(), &key);
+            last_refresh = Instant::now();
+        }
+    }
+    drop(screen);
+
+    let Some(choice) = state.take_selected() else {
+        // Only possible if nothing was selected
+        return Err(InquireError::OperationCanceled);
+    };
+    eprintln!("{} {message} {}", ">".green(), choice.to_string().cyan());
+    Ok(choice)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Submit,
+    Cancel,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Prompt(String),
+    Option { text: String, selected: bool },
+    Help(String),
+}
+
+#[derive(Debug)]
+struct SelectState<T> {
+    options: Vec<T>,
+    /// The index of the selected option, or None if nothing is selected because the
+    /// selected option went away.
+    cursor: Option<usize>,
+}
+
+impl<T: Display> SelectState<T> {
+    fn new(options: Vec<T>) -> Self {
+        let cursor = (!options.is_empty()).then_some(0);
+        Self { options, cursor }
+    }
+
+    /// Replace the options, keeping the one with the same key selected if it's still
+    /// there. If it isn't, nothing is selected, so that pressing enter can't pick
+    /// whatever took its place.
+    fn set_options<K: PartialEq>(&mut self, options: Vec<T>, key: impl Fn(&T) -> K) {
+        let selected = self.cursor.and_then(|c| self.options.get(c)).map(&key);
+        self.cursor = selected.and_then(|k| options.iter().position(|o| key(o) == k));
+        self.options = options;
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Option<Outcome> {
+        let len = self.options.len();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Outcome::Interrupt);
+            }
+            KeyCode::Esc => return Some(Outcome::Cancel),
+            KeyCode::Enter if self.cursor.is_some() => return Some(Outcome::Submit),
+            KeyCode::Up if len > 0 => {
+                self.cursor = Some(self.cursor.map_or(len - 1, |c| (c + len - 1) % len));
+            }
+            KeyCode::Down if len > 0 => {
+                self.cursor = Some(self.cursor.map_or(0, |c| (c + 1) % len));
+            }
+            KeyCode::Home if len > 0 => self.cursor = Some(0),
+            KeyCode::End if len > 0 => self.cursor = Some(len - 1),
+            _ => {}
+        }
+        None
+    }
+
+    fn take_selected(mut self) -> Option<T> {
+        let cursor = self.cursor.filter(|&c| c < self.options.len())?;
+        Some(self.options.swap_remove(cursor))
+    }
+
+    /// What to show, without any styling.
+    fn lines(&self, message: &str, help_message: &str) -> Vec<Line> {
+        // Scroll just enough to keep the cursor in view
+        let start = (self.cursor.unwrap_or(0) + 1)
+            .saturating_sub(PAGE_SIZE)
+            .min(self.options.len().saturating_sub(PAGE_SIZE));
+
+        let options = (self.options.iter().enumerate())
+            .skip(start)
+            .take(PAGE_SIZE)
+            .map(|(i, o)| {
+                let selected = self.cursor == Some(i);
+                Line::Option {
+                    text: format!("{} {o}", if selected { ">" } else { " " }),
+                    selected,
+                }
+            });
+        std::iter::once(Line::Prompt(message.to_owned()))
+            .chain(options)
+            .chain([Line::Help(format!(
+                "[↑↓ to move, enter to select, esc to cancel. {help_message}]"
+            ))])
+            .collect()
+    }
+}
+
+/// The part of the terminal that the list is drawn on. It is cleared when dropped.
+struct Screen {
+    stderr: Stderr,
+    drawn: Vec<Line>,
+}
+
+impl Screen {
+    fn new() -> io::Result<Self> {
+        enable_raw_mode()?;
+        let mut stderr = io::stderr();
+        queue!(stderr, Hide)?;
+        Ok(Self {
+            stderr,
+            drawn: vec![],
+        })
+    }
+
+    fn draw(&mut self, lines: &[Line]) -> io::Result<()> {
+        if self.drawn == lines {
+            return Ok(());
+        }
+        self.clear()?;
+
+        // Lines that wrap would throw off how far up to go to redraw them
+        let width = match terminal::size()?.0 {
+            0 => usize::MAX, // Unknown
+            w => w as usize - 1,
+        };
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                queue!(self.stderr, crossterm::style::Print("\r\n"))?;
+            }
+            let styled = match line {
+                Line::Prompt(m) => format!("? {m}")
+                    .chars()
+                    .take(width)
+                    .collect::<String>()
+                    .bold(),
+                Line::Option { text, selected } => {
+                    let text = text.chars().take(width).collect::<String>();
+                    if *selected {
+                        text.cyan()
+                    } else {
+                        text.stylize()
+                    }
+                }
+                Line::Help(h) => h.chars().take(width).collect::<String>().dark_cyan(),
+            };
+            queue!(self.stderr, PrintStyledContent(styled))?;
+        }
+        self.stderr.flush()?;
+        self.drawn = lines.to_vec();
+        Ok(())
+    }
+
+    /// Move back to the start of what was drawn, and erase it.
+    fn clear(&mut self) -> io::Result<()> {
+        queue!(self.stderr, MoveToColumn(0))?;
+        if self.drawn.len() > 1 {
+            queue!(self.stderr, MoveUp(self.drawn.len() as u16 - 1))?;
+        }
+        queue!(self.stderr, Clear(ClearType::FromCursorDown))?;
+        self.drawn.clear();
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.clear().unwrap_or_log();
+        queue!(self.stderr, Show).unwrap_or_log();
+        self.stderr.flush().unwrap_or_log();
+        disable_raw_mode().unwrap_or_log();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Line, Outcome, PAGE_SIZE, SelectState};
+
+    fn press(state: &mut SelectState<&'static str>, code: KeyCode) -> Option<Outcome> {
+        state.on_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn selected(state: &SelectState<&'static str>) -> &'static str {
+        state.options[state.cursor.unwrap()]
+    }
+
+    #[test]
+    fn moving_wraps_around() {
+        let mut state = SelectState::new(vec!["sda", "sdb", "<Show all disks>"]);
+
+        press(&mut state, KeyCode::Up);
+        assert_eq!(selected(&state), "<Show all disks>");
+        press(&mut state, KeyCode::Down);
+        assert_eq!(selected(&state), "sda");
+    }
+
+    #[test]
+    fn selection_stays_on_the_same_option_when_others_appear() {
+        let mut state = SelectState::new(vec!["sdb", "<Show all disks>"]);
+        press(&mut state, KeyCode::Down);
+
+        state.set_options(vec!["sda", "sdb", "sdc", "<Show all disks>"], |o| *o);
+
+        assert_eq!(selected(&state), "<Show all disks>");
+    }
+
+    #[test]
+    fn selection_is_cleared_when_the_selected_option_disappears() {
+        let mut state = SelectState::new(vec!["sda", "sdb", "sdc", "<Show all disks>"]);
+        press(&mut state, KeyCode::Down);
+
+        state.set_options(vec!["sda", "sdc", "<Show all disks>"], |o| *o);
+
+        assert_eq!(state.cursor, None);
+        assert_eq!(press(&mut state, KeyCode::Enter), None);
+        press(&mut state, KeyCode::Down);
+        assert_eq!(selected(&state), "sda");
+        assert_eq!(press(&mut state, KeyCode::Enter), Some(Outcome::Submit));
+    }
+
+    #[test]
+    fn keys_finish_the_prompt() {
+        let mut state = SelectState::new(vec!["sda"]);
+
+        assert_eq!(press(&mut state, KeyCode::Enter), Some(Outcome::Submit));
+        assert_eq!(press(&mut state, KeyCode::Esc), Some(Outcome::Cancel));
+        assert_eq!(
+            state.on_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(Outcome::Interrupt)
+        );
+        assert_eq!(
+            SelectState::<&str>::new(vec![])
+                .on_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)),
+            None
+        );
+    }
+
+    #[test]
+    fn long_lists_scroll_to_the_cursor() {
+        let options = vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let mut state = SelectState::new(options);
+        press(&mut state, KeyCode::End);
+
+        let lines = state.lines("Select target disk", "help");
+
+        assert_eq!(lines.len(), PAGE_SIZE + 2);
+        assert_eq!(lines[0], Line::Prompt("Select target disk".into()));
+        assert_eq!(
+            lines[1],
+            Line::Option {
+                text: "  d".into(),
+                selected: false
+            }
+        );
+        assert_eq!(
+            lines[PAGE_SIZE],
+            Line::Option {
+                text: "> j".into(),
+                selected: true
+            }
+        );
+    }
+}
@@ -6,78 +6,394 @@
 
 use std::time::Instant;
 
+use bytesize::ByteSize;
+
+use anyhow::{Context, bail};
 use futures::StreamExt;
+use indicatif::MultiProgress;
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
+use itertools::Itertools;
 
-use crate::compression::CompressionFormat;
 use crate::device::WriteTarget;
-use crate::herder_daemon::ipc::WriteVerifyEvent;
-use crate::ui::writer_tracking::WriterState;
+use crate::exit_code::UsageError;
+use crate::herder_daemon::ipc::{SparseMode, VerifyAgainst, WriteVerifyEvent};
+use crate::ui::writer_tracking::{WriterState, merge_herd_events};
 
 use self::ask_hash::ask_hash;
+use self::ask_outfile::ask_capture_compression;
 use self::ask_outfile::ask_compression;
+use self::ask_outfile::ask_infile;
 use self::ask_outfile::ask_outfile;
+use self::ask_outfile::ask_unmount;
+use self::ask_outfile::ask_verify_compression;
+use self::ask_outfile::ask_verify_target;
+use self::ask_outfile::ask_wipe_target;
+use self::ask_outfile::confirm_capture;
+use self::ask_outfile::confirm_wipe;
 use self::ask_outfile::confirm_write;
+use self::ask_outfile::wait_for_outfile;
 
-use super::cli::BurnArgs;
-use super::start::BeginParams;
+use super::cli::{BurnArgs, CaptureArgs, VerifyArgs, VerifyWith, WipeArgs};
+use super::piped_image::PipedImage;
+use super::start::{
+    BeginParams, CaptureParams, DisplayParams, VerifyParams, WipeParams, read_android_sparse_header,
+};
 use crate::herder_facade::HerdHandle;
 
 mod ask_hash;
 mod ask_outfile;
+mod live_select;
 
 /// Returns the [BeginParams] if the user confirms, and None if the user doesn't.
 #[tracing::instrument(skip_all)]
 pub fn do_setup_wizard(args: &BurnArgs) -> Result<Option<BeginParams>, anyhow::Error> {
-    let compression = ask_compression(args)?;
-    let _hash_info = ask_hash(args, compression)?;
-    let target = match &args.out {
-        Some(f) => WriteTarget::try_from(f.as_ref())?,
-        None => ask_outfile(args)?,
+    let piped = match args.image_url() {
+        Some(url) => Some(PipedImage::download(url)?),
+        None => (args.image_is_piped())
+            .then(|| PipedImage::open(&args.image))
+            .transpose()
+            .context("Failed to read the start of the piped image")?,
+    };
+    let (compression, archive_member) = ask_compression(args, piped.as_ref())?;
+    let image_hash = ask_hash(args, compression, archive_member.as_ref())?;
+    let verify_hash = match args.verify_with {
+        VerifyWith::Source => None,
+        VerifyWith::Hash => Some(
+            (image_hash.as_ref())
+                .filter(|h| !h.of_compressed)
+                .map(|h| h.expected.clone())
+                .ok_or_else(|| {
+                    UsageError(
+                        "Verifying with a hash requires the hash of the raw (uncompressed) image"
+                            .into(),
+                    )
+                })?,
+        ),
+    };
+    let input_hash = image_hash.filter(|_| args.hash_inline || piped.is_some());
+    if args.sparse == Some(SparseMode::Discard) && (verify_hash.is_some() || piped.is_some()) {
+        bail!(UsageError(
+            "Disks can't be verified against a hash when blocks of zeroes are discarded".into()
+        ));
+    }
+    let targets = if args.wait_for_device {
+        vec![wait_for_outfile(args)]
+    } else if args.out.is_empty() {
+        vec![ask_outfile(args)?]
+    } else {
+        args.out
+            .iter()
+            .map(|s| s.resolve())
+            .collect::<Result<Vec<_>, _>>()?
     };
-    let begin_params = BeginParams::new(args.image.clone(), compression, target)?;
+    check_targets(&targets, args.dangerously_allow_system_disks, "burning to")?;
+    let mut begin_params = BeginParams::new(
+        args.image.clone(),
+        piped,
+        compression,
+        archive_member,
+        input_hash,
+        verify_hash,
+        args.sparse,
+        targets,
+    )?;
+    begin_params.verify_all = args.verify_all;
+    if let Some(attempts) = args.repair {
+        if begin_params.verify_hash.is_some() || begin_params.piped.is_some() {
+            bail!(UsageError(
+                "Repairing needs to read the input file again, so it only works with --verify-with source and images that aren't piped".into()
+            ));
+        }
+        begin_params.repair_attempts = attempts;
+    }
+    if begin_params.android_sparse.is_some() && begin_params.verify_hash.is_some() {
+        bail!(UsageError(
+            "Android sparse images can't be verified against a hash, because the hash is of the sparse image rather than what gets written".into()
+        ));
+    }
+    if args.resumable || args.resume {
+        if begin_params.piped.is_some() {
+            bail!(UsageError("Burns of piped images can't be resumed".into()));
+        }
+        // Discarded blocks can read back as anything, so what was written can't be
+        // checked against the checkpoint
+        if begin_params.sparse == Some(SparseMode::Discard) {
+            bail!(UsageError(
+                "Burns with --sparse=discard can't be resumed".into()
+            ));
+        }
+        begin_params.resumable = true;
+    }
+    if args.resume {
+        begin_params.resume_from = (begin_params.targets.iter())
+            .map(|t| {
+                let cp = begin_params.checkpoint_file(t).and_then(|f| f.load());
+                if cp.is_none() {
+                    eprintln!(
+                        "No interrupted burn of this image to {} was found, so it will be burned from the start",
+                        t.name
+                    );
+                }
+                cp
+            })
+            .collect();
+    }
     if !confirm_write(args, &begin_params)? {
         eprintln!("Aborting.");
         return Ok(None);
     }
+    begin_params.unmount = ask_unmount(args.unmount, args.force, "burning", &begin_params.targets)?;
     Ok(Some(begin_params))
 }
 
+/// Returns the [CaptureParams] if the user confirms, and None if the user doesn't.
 #[tracing::instrument(skip_all)]
-pub async fn run_simple_burning_ui(
-    mut handle: HerdHandle<WriteVerifyEvent>,
-    cf: CompressionFormat,
+pub fn do_capture_wizard(args: &CaptureArgs) -> Result<Option<CaptureParams>, anyhow::Error> {
+    let compression = ask_capture_compression(args)?;
+    let source = match &args.input {
+        Some(s) => s.resolve()?,
+        None => ask_infile(args)?,
+    };
+    let capture_params = CaptureParams {
+        source,
+        image_file: args.image.clone(),
+        compression,
+        compression_level: args.compression_level,
+        verify: !args.skip_verify,
+    };
+    if !confirm_capture(args, &capture_params)? {
+        eprintln!("Aborting.");
+        return Ok(None);
+    }
+    Ok(Some(capture_params))
+}
+
+/// Returns the [WipeParams] if the user confirms, and None if the user doesn't.
+#[tracing::instrument(skip_all)]
+pub fn do_wipe_wizard(args: &WipeArgs) -> Result<Option<WipeParams>, anyhow::Error> {
+    if args.verify && !args.mode.can_verify() {
+        bail!(UsageError(
+            "Discarded disks can't be verified, since their contents are unspecified afterwards"
+                .into()
+        ));
+    }
+    let targets = if args.out.is_empty() {
+        vec![ask_wipe_target(args)?]
+    } else {
+        args.out
+            .iter()
+            .map(|s| s.resolve())
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    check_targets(&targets, args.dangerously_allow_system_disks, "wiping")?;
+    let mut wipe_params = WipeParams {
+        targets,
+        mode: args.mode,
+        verify: args.verify,
+        unmount: false,
+    };
+    if !confirm_wipe(args, &wipe_params)? {
+        eprintln!("Aborting.");
+        return Ok(None);
+    }
+    wipe_params.unmount = ask_unmount(args.unmount, args.force, "wiping", &wipe_params.targets)?;
+    Ok(Some(wipe_params))
+}
+
+/// Work out what to compare the disks against. Nothing is written, so unlike the other
+/// wizards, there is nothing to confirm.
+#[tracing::instrument(skip_all)]
+pub fn do_verify_wizard(args: &VerifyArgs) -> anyhow::Result<VerifyParams> {
+    let against = match &args.hash {
+        Some(expected) => VerifyAgainst::Hash {
+            expected: expected.clone(),
+            len: args.size.map(|s| s.as_u64()),
+        },
+        None => {
+            let image = (args.image.as_ref()).expect("clap requires an image without --hash");
+            let (compression, archive_member) = ask_verify_compression(args, image)?;
+            let android_sparse =
+                read_android_sparse_header(image, compression, archive_member.as_ref())?.is_some();
+            VerifyAgainst::Image {
+                src: image.clone(),
+                compression,
+                archive_member,
+                android_sparse,
+            }
+        }
+    };
+    let targets = if args.out.is_empty() {
+        vec![ask_verify_target(args)?]
+    } else {
+        args.out
+            .iter()
+            .map(|s| s.resolve())
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    check_targets(&targets, true, "verifying")?;
+    let verify_params = VerifyParams { against, targets };
+    eprintln!("{verify_params}");
+    Ok(verify_params)
+}
+
+/// Refuse to write to disks the system depends on, or to the same disk twice. `doing`
+/// says what would break the system, i.e. "burning to".
+fn check_targets(
+    targets: &[WriteTarget],
+    allow_system_disks: bool,
+    doing: &str,
 ) -> anyhow::Result<()> {
-    let input_file_bytes = handle.initial_info.input_file_bytes;
-    let write_progress = ProgressBar::new(100).with_message("Burning").with_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] {msg:>10} {wide_bar:.green/black} {percent:>3}%",
-        )
-        .unwrap(),
-    );
-    let verify_progress = ProgressBar::new(100).with_message("Verifying").with_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] {msg:>10} {wide_bar:.blue/black} {percent:>3}%",
-        )
-        .unwrap(),
-    );
+    if !allow_system_disks && let Some(t) = targets.iter().find(|t| !t.system_uses.is_empty()) {
+        bail!(UsageError(format!(
+            "{} is in use by the system ({}), and {doing} it would break your computer. If you really mean to, use --dangerously-allow-system-disks",
+            t.devnode.to_string_lossy(),
+            t.system_uses.iter().format(", ")
+        )));
+    }
+    if let Some(dup) = (targets.iter())
+        .duplicates_by(|t| {
+            t.devnode
+                .canonicalize()
+                .unwrap_or_else(|_| t.devnode.clone())
+        })
+        .next()
+    {
+        bail!(UsageError(format!(
+            "{} was specified as an output more than once",
+            dup.devnode.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
 
-    let mut child_state = WriterState::initial(Instant::now(), !cf.is_identity(), input_file_bytes);
+#[tracing::instrument(skip_all)]
+pub async fn run_simple_burning_ui(
+    params: &DisplayParams,
+    handles: Vec<HerdHandle<WriteVerifyEvent>>,
+) -> anyhow::Result<Vec<WriterState>> {
+    let multi = MultiProgress::new();
+    let now = Instant::now();
+    let prefix_width = params
+        .targets
+        .iter()
+        .map(|t| t.name.len())
+        .max()
+        .unwrap_or(0);
 
-    loop {
-        let x = handle.events.next().await;
-        child_state = child_state.on_status(Instant::now(), x);
-        match &child_state {
-            WriterState::Writing(b) => {
-                write_progress.set_position((b.approximate_ratio() * 1000.0) as u64)
+    let mut states: Vec<WriterState> = handles
+        .iter()
+        .map(|h| {
+            WriterState::initial(
+                now,
+                params.is_input_compressed,
+                h.initial_info.input_file_bytes,
+            )
+        })
+        .collect();
+    let mut bars: Vec<SimpleProgressBars> = params
+        .targets
+        .iter()
+        .map(|t| {
+            let prefix = format!("{:>prefix_width$}", t.name);
+            SimpleProgressBars {
+                write: multi.add(make_progress_bar(&prefix, params.verb, "green")),
+                verify: None,
+                prefix,
             }
+        })
+        .collect();
+
+    let mut events = merge_herd_events(handles.into_iter().map(|h| h.events));
+
+    while !states.iter().all(WriterState::is_finished) {
+        let Some((i, x)) = events.next().await else {
+            break;
+        };
+        states[i].update(Instant::now(), x);
+
+        let bars = &mut bars[i];
+        match &states[i] {
+            WriterState::Writing(b) => match b.approximate_ratio() {
+                Some(ratio) => bars.write.set_position((ratio * 1000.0) as u64),
+                // Without a total, the best we can do is say how much we've written
+                None => bars.write.set_message(format!(
+                    "{} {}",
+                    params.verb,
+                    ByteSize::b(b.write_hist.bytes_encountered())
+                )),
+            },
             WriterState::Verifying {
-                total_write_bytes, ..
-            } => verify_progress.set_position(total_write_bytes * 1000 / input_file_bytes),
-            WriterState::Finished { .. } => break,
+                write_hist,
+                verify_hist,
+                total_write_bytes,
+                repair,
+            } => {
+                let verify = bars.verify.get_or_insert_with(|| {
+                    if write_hist.bytes_encountered() == 0 {
+                        // Nothing was written, so this job only verifies
+                        return bars.write.clone();
+                    }
+                    bars.write.finish();
+                    multi.insert_after(
+                        &bars.write,
+                        make_progress_bar(&bars.prefix, "Verifying", "blue"),
+                    )
+                });
+                verify.set_position(
+                    (verify_hist.bytes_encountered() * 1000 / (*total_write_bytes).max(1))
+                        .min(1000),
+                );
+                if let Some(r) = repair {
+                    verify.set_message(format!(
+                        "Repairing {} (attempt {})",
+                        ByteSize::b(r.bad_bytes),
+                        r.attempt
+                    ));
+                }
+            }
+            WriterState::Finished { .. } => {
+                bars.write.finish();
+                if let Some(verify) = &bars.verify {
+                    verify.finish();
+                }
+            }
         }
     }
-    println!("Done!");
-    Ok(())
+
+    for (target, state) in params.targets.iter().zip(&states) {
+        match (state.error(), state.repair()) {
+            (None, None) => eprintln!("{}: Done!", target.name),
+            (None, Some(r)) => eprintln!(
+                "{}: Done! {} didn't match after burning, and was fixed by rewriting it {}.",
+                target.name,
+                ByteSize::b(r.first_bad_bytes),
+                match r.attempt {
+                    1 => "once".to_owned(),
+                    n => format!("{n} times"),
+                }
+            ),
+            (Some(e), _) => eprintln!("{}: {e}", target.name),
+        }
+    }
+    Ok(states)
+}
+
+/// The progress bars associated with a single target.
+struct SimpleProgressBars {
+    prefix: String,
+    write: ProgressBar,
+    verify: Option<ProgressBar>,
+}
+
+fn make_progress_bar(prefix: &str, msg: &'static str, color: &str) -> ProgressBar {
+    ProgressBar::new(1000)
+        .with_prefix(prefix.to_owned())
+        .with_message(msg)
+        .with_style(
+            ProgressStyle::with_template(&format!(
+                "[{{elapsed_precise}}] {{prefix}} {{msg:>10}} {{wide_bar:.{color}/black}} {{percent:>3}}%"
+            ))
+            .unwrap(),
+        )
 }
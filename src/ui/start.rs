@@ -1,68 +1,463 @@
-use std::{fmt::Display, fs::File, path::PathBuf, sync::Arc};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use anyhow::Context;
 use bytesize::ByteSize;
 use inquire::Confirm;
-use tracing::debug;
+use itertools::Itertools;
+use tracing::{debug, warn};
 
 use crate::{
-    compression::CompressionFormat,
+    android_sparse::{self, SparseHeader},
+    archive::{ArchiveMember, ArchiveMemberRead},
+    compression::{CompressionFormat, CompressionLevel, DecompressedSize, decompress},
     device::{self, WriteTarget},
-    herder_daemon::ipc::{WriteVerifyAction, WriteVerifyError, WriteVerifyEvent},
+    exit_code::{AlreadyReported, ExitCode},
+    hash::ExpectedHash,
+    herder_daemon::ipc::{
+        HerdAction, ImageHash, ReadDiskAction, SparseMode, VerifyAction, VerifyAgainst, WipeAction,
+        WipeMode, WriteCheckpoint, WriteVerifyAction, WriteVerifyError, WriteVerifyEvent,
+    },
     herder_facade::{HerdHandle, HerderFacade, StartWriterError},
     logging::LogPaths,
     ui::{
+        checkpoints::{BurnIdentity, CheckpointFile, save_checkpoints},
         cli::{Interactive, UseSudo},
         fancy_ui::FancyUI,
+        json_ui::run_json_progress,
+        piped_image::PipedImage,
         simple_ui::run_simple_burning_ui,
         utils::TUICapture,
+        writer_tracking::WriterState,
     },
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BeginParams {
     pub input_file: PathBuf,
-    pub input_file_size: ByteSize,
+    /// If the input file is being piped in, the pipe to read it from.
+    pub piped: Option<PipedImage>,
+    /// The size of the input file, or None if it is piped in and we don't know.
+    pub input_file_size: Option<ByteSize>,
     pub compression: CompressionFormat,
-    pub target: WriteTarget,
+    /// The size of the input file once decompressed, if we can tell without
+    /// decompressing it.
+    pub decompressed_size: Option<DecompressedSize>,
+    /// If the input file is an archive, the member of it to burn.
+    pub archive_member: Option<ArchiveMember>,
+    /// If the image is an Android sparse image, its header.
+    pub android_sparse: Option<SparseHeader>,
+    /// If provided, the input file is checked against this hash while it is written.
+    pub input_hash: Option<ImageHash>,
+    /// If provided, the disks are verified against this hash rather than against the
+    /// input file.
+    pub verify_hash: Option<ExpectedHash>,
+    /// If provided, blocks of zeroes are not written.
+    pub sparse: Option<SparseMode>,
+    pub targets: Vec<WriteTarget>,
+    /// If true, checkpoints are saved while burning so that it can be resumed.
+    pub resumable: bool,
+    /// Where to resume burning each of the targets from, in the same order as
+    /// [Self::targets].
+    pub resume_from: Vec<Option<WriteCheckpoint>>,
+    /// If true, the targets are unmounted before burning to them.
+    pub unmount: bool,
+    /// If true, verification keeps going after the first mismatch.
+    pub verify_all: bool,
+    /// How many times to rewrite regions that don't match after burning, if any.
+    pub repair_attempts: u32,
 }
 
 impl BeginParams {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         input_file: PathBuf,
+        piped: Option<PipedImage>,
         compression: CompressionFormat,
-        target: WriteTarget,
-    ) -> std::io::Result<Self> {
-        let input_file_size = ByteSize::b(File::open(&input_file)?.metadata()?.len());
+        archive_member: Option<ArchiveMember>,
+        input_hash: Option<ImageHash>,
+        verify_hash: Option<ExpectedHash>,
+        sparse: Option<SparseMode>,
+        targets: Vec<WriteTarget>,
+    ) -> anyhow::Result<Self> {
+        let (input_file_size, android_sparse) = match &piped {
+            Some(p) => (
+                p.size().map(ByteSize::b),
+                android_sparse::read_header(decompress(compression, p.head())?)?,
+            ),
+            None => {
+                let input_file_size = ByteSize::b(input_file.metadata()?.len());
+                let android_sparse =
+                    read_android_sparse_header(&input_file, compression, archive_member.as_ref())?;
+                (Some(input_file_size), android_sparse)
+            }
+        };
+        let decompressed_size = match &piped {
+            Some(p) => (p.size())
+                .filter(|_| compression.is_identity())
+                .map(DecompressedSize::Exact),
+            None => File::open(&input_file)
+                .map(BufReader::new)
+                .and_then(|f| compression.decompressed_size(f))
+                .unwrap_or_else(|error| {
+                    warn!(
+                        ?error,
+                        "Failed to read the decompressed size of the input file"
+                    );
+                    None
+                }),
+        };
         Ok(Self {
             input_file,
+            piped,
             input_file_size,
             compression,
-            target,
+            decompressed_size,
+            archive_member,
+            android_sparse,
+            input_hash,
+            verify_hash,
+            sparse,
+            resumable: false,
+            resume_from: vec![None; targets.len()],
+            targets,
+            unmount: false,
+            verify_all: false,
+            repair_attempts: 0,
         })
     }
 
-    pub fn make_child_config(&self) -> WriteVerifyAction {
+    /// Where to save checkpoints of burning `target`, or None if this burn can't be
+    /// resumed.
+    pub fn checkpoint_file(&self, target: &WriteTarget) -> Option<CheckpointFile> {
+        if !self.resumable || self.piped.is_some() {
+            return None;
+        }
+        let file = BurnIdentity::new(
+            &self.input_file,
+            self.compression,
+            self.archive_member.clone(),
+            self.android_sparse.is_some(),
+            &target.devnode,
+        )
+        .and_then(CheckpointFile::new);
+        match file {
+            Ok(f) => Some(f),
+            Err(error) => {
+                warn!(?error, "Can't save checkpoints, burn will not be resumable");
+                None
+            }
+        }
+    }
+
+    /// How much will be written to each target, if we can tell ahead of time.
+    pub fn output_size(&self) -> Option<DecompressedSize> {
+        if let Some(h) = &self.android_sparse {
+            return Some(DecompressedSize::Exact(h.expanded_size()));
+        }
+        if let Some(m) = &self.archive_member {
+            return Some(DecompressedSize::Exact(m.size));
+        }
+        self.decompressed_size
+    }
+
+    /// A user-friendly name for the input file.
+    pub fn input_name(&self) -> String {
+        match &self.piped {
+            Some(p) => p.name().to_owned(),
+            None => self.input_file.to_string_lossy().to_string(),
+        }
+    }
+
+    /// `src` is where the herd should read the input file from.
+    pub fn make_child_config(&self, target: &WriteTarget, src: PathBuf) -> WriteVerifyAction {
         WriteVerifyAction {
-            dest: self.target.devnode.clone(),
-            src: self.input_file.clone(),
-            verify: true,
+            dest: target.devnode.clone(),
+            src,
+            src_size: self.piped.as_ref().and_then(|p| p.size()),
+            checkpoints: false,
+            resume_from: None,
+            verify: self.verify_hash.is_none(),
+            verify_all: self.verify_all,
+            repair_attempts: self.repair_attempts,
+            verify_hash: self.verify_hash.clone(),
+            input_hash: self.input_hash.clone(),
+            sparse: self.sparse,
             compression: self.compression,
-            target_type: self.target.target_type,
-            block_size: self.target.block_size.0.map(|s| s.as_u64()),
+            archive_member: self.archive_member.clone(),
+            android_sparse: self.android_sparse.is_some(),
+            unmount: self.unmount,
+            target_type: target.target_type,
+            block_size: target.block_size.0.map(|s| s.as_u64()),
+        }
+    }
+}
+
+/// If the image in `input_file` is an Android sparse image, its header.
+pub fn read_android_sparse_header(
+    input_file: &Path,
+    compression: CompressionFormat,
+    archive_member: Option<&ArchiveMember>,
+) -> anyhow::Result<Option<SparseHeader>> {
+    let decompressed = decompress(compression, BufReader::new(File::open(input_file)?))?;
+    Ok(android_sparse::read_header(match archive_member {
+        Some(m) => ArchiveMemberRead::new(decompressed, m),
+        None => ArchiveMemberRead::passthrough(decompressed),
+    })?)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CaptureParams {
+    pub source: WriteTarget,
+    pub image_file: PathBuf,
+    pub compression: CompressionFormat,
+    pub compression_level: CompressionLevel,
+    pub verify: bool,
+}
+
+impl CaptureParams {
+    pub fn make_child_config(&self) -> ReadDiskAction {
+        ReadDiskAction {
+            src: self.source.devnode.clone(),
+            dest: self.image_file.clone(),
+            verify: self.verify,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            source_type: self.source.target_type,
+            block_size: self.source.block_size.0.map(|s| s.as_u64()),
+            size: self.source.size.0.map(|s| s.as_u64()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WipeParams {
+    pub targets: Vec<WriteTarget>,
+    pub mode: WipeMode,
+    pub verify: bool,
+    /// If true, the targets are unmounted before wiping them.
+    pub unmount: bool,
+}
+
+impl WipeParams {
+    pub fn make_child_config(&self, target: &WriteTarget) -> WipeAction {
+        WipeAction {
+            dest: target.devnode.clone(),
+            mode: self.mode,
+            verify: self.verify,
+            unmount: self.unmount,
+            target_type: target.target_type,
+            block_size: target.block_size.0.map(|s| s.as_u64()),
+            size: target.size.0.map(|s| s.as_u64()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VerifyParams {
+    pub against: VerifyAgainst,
+    pub targets: Vec<WriteTarget>,
+}
+
+impl VerifyParams {
+    pub fn make_child_config(&self, target: &WriteTarget) -> VerifyAction {
+        VerifyAction {
+            dest: target.devnode.clone(),
+            against: self.against.clone(),
+            target_type: target.target_type,
+            block_size: target.block_size.0.map(|s| s.as_u64()),
+        }
+    }
+}
+
+/// Describes a running job to the UIs, independently of what kind of herd is running it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DisplayParams {
+    /// What to call the main phase of the job, i.e. "Burning"
+    pub verb: &'static str,
+    pub input_filename: String,
+    /// If true, progress is measured by how much of the input has been read, rather than
+    /// by how much output has been written.
+    pub is_input_compressed: bool,
+    pub targets: Vec<DisplayTarget>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DisplayTarget {
+    /// A short, user-friendly name for the target
+    pub name: String,
+    pub filename: String,
+}
+
+impl From<&BeginParams> for DisplayParams {
+    fn from(params: &BeginParams) -> Self {
+        Self {
+            verb: "Burning",
+            input_filename: params.input_name(),
+            is_input_compressed: !params.compression.is_identity()
+                || params.archive_member.is_some()
+                || params.android_sparse.is_some(),
+            targets: (params.targets.iter())
+                .map(|t| DisplayTarget {
+                    name: t.name.clone(),
+                    filename: t.devnode.to_string_lossy().to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&CaptureParams> for DisplayParams {
+    fn from(params: &CaptureParams) -> Self {
+        Self {
+            verb: "Capturing",
+            input_filename: params.source.devnode.to_string_lossy().to_string(),
+            is_input_compressed: !params.compression.is_identity(),
+            targets: vec![DisplayTarget {
+                name: params.image_file.to_string_lossy().to_string(),
+                filename: params.image_file.to_string_lossy().to_string(),
+            }],
+        }
+    }
+}
+
+impl From<&WipeParams> for DisplayParams {
+    fn from(params: &WipeParams) -> Self {
+        Self {
+            verb: "Wiping",
+            input_filename: params.mode.to_string(),
+            is_input_compressed: false,
+            targets: (params.targets.iter())
+                .map(|t| DisplayTarget {
+                    name: t.name.clone(),
+                    filename: t.devnode.to_string_lossy().to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&VerifyParams> for DisplayParams {
+    fn from(params: &VerifyParams) -> Self {
+        let (input_filename, is_input_compressed) = match &params.against {
+            VerifyAgainst::Image {
+                src,
+                compression,
+                archive_member,
+                android_sparse,
+            } => (
+                src.to_string_lossy().to_string(),
+                !compression.is_identity() || archive_member.is_some() || *android_sparse,
+            ),
+            VerifyAgainst::Hash { expected, .. } => (format!("{} hash", expected.alg), false),
+        };
+        Self {
+            verb: "Verifying",
+            input_filename,
+            is_input_compressed,
+            targets: (params.targets.iter())
+                .map(|t| DisplayTarget {
+                    name: t.name.clone(),
+                    filename: t.devnode.to_string_lossy().to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Start one herd per target in `params`, in the same order as
+/// [BeginParams::targets].
+///
+/// If we have to escalate to start any one of them, all subsequent herds are started
+/// escalated as well, so that the user is asked at most once.
+///
+/// If the input file is piped in, the herds read it from FIFOs made in `state_dir`.
+/// A herd that fails to start doesn't hold up the rest.
+pub async fn start_burns(
+    herder: &mut impl HerderFacade,
+    params: &BeginParams,
+    state_dir: &Path,
+    mut root: UseSudo,
+    interactive: bool,
+) -> anyhow::Result<Vec<HerdHandle<WriteVerifyEvent>>> {
+    let mut fifos = match &params.piped {
+        Some(p) => p
+            .feed(state_dir, params.targets.len())
+            .context("Failed to set up pipes for the herds to read the image from")?,
+        None => vec![],
+    }
+    .into_iter();
+    let mut handles = Vec::with_capacity(params.targets.len());
+    for (target, resume_from) in params.targets.iter().zip(&params.resume_from) {
+        // Held until the herd has started or failed to, after which it has either
+        // opened the FIFO or never will
+        let fifo = fifos.next();
+        let src = fifo
+            .as_ref()
+            .map_or_else(|| params.input_file.clone(), |f| f.path().to_owned());
+        let checkpoint_file = params.checkpoint_file(target);
+        let config = WriteVerifyAction {
+            checkpoints: checkpoint_file.is_some(),
+            resume_from: resume_from.clone(),
+            ..params.make_child_config(target, src)
+        };
+        let started = try_start_herd(herder, &config, &target.devnode, root, interactive).await;
+        drop(fifo);
+        let (mut handle, escalated) = started?;
+        if escalated {
+            root = UseSudo::Always;
+        }
+        if let Some(file) = checkpoint_file {
+            handle.events = save_checkpoints(handle.events, file);
+        }
+        if let Some(piped) = &params.piped {
+            handle.events = piped.check_feed(handle.events);
+        }
+        handles.push(handle);
+    }
+    Ok(handles)
+}
+
+/// Start one herd per target, with the config `make_config` makes for it. Escalation
+/// carries over like in [start_burns].
+pub async fn start_herds<A: HerdAction<Event = WriteVerifyEvent>>(
+    herder: &mut impl HerderFacade,
+    targets: &[WriteTarget],
+    make_config: impl Fn(&WriteTarget) -> A,
+    mut root: UseSudo,
+    interactive: bool,
+) -> anyhow::Result<Vec<HerdHandle<WriteVerifyEvent>>> {
+    let mut handles = Vec::with_capacity(targets.len());
+    for target in targets {
+        let config = make_config(target);
+        let (handle, escalated) =
+            try_start_herd(herder, &config, &target.devnode, root, interactive).await?;
+        if escalated {
+            root = UseSudo::Always;
         }
+        handles.push(handle);
     }
+    Ok(handles)
 }
 
+/// Start a single herd operating on `device`, escalating if needed and allowed. Returns
+/// the handle along with whether or not it was escalated.
 #[tracing::instrument(skip_all, fields(root, interactive))]
-pub async fn try_start_burn(
+pub async fn try_start_herd<A: HerdAction<Event = WriteVerifyEvent>>(
     herder: &mut impl HerderFacade,
-    args: &WriteVerifyAction,
+    args: &A,
+    device: &Path,
     root: UseSudo,
     interactive: bool,
-) -> anyhow::Result<HerdHandle<WriteVerifyEvent>> {
+) -> anyhow::Result<(HerdHandle<WriteVerifyEvent>, bool)> {
     let err = match herder.start_herd(args.clone(), false).await {
         Ok(p) => {
-            return Ok(p);
+            return Ok((p, false));
         }
         Err(e) => e,
     };
@@ -74,7 +469,7 @@ pub async fn try_start_burn(
 
                 let response = Confirm::new(&format!(
                     "We don't have permissions on {}. Escalate using sudo?",
-                    args.dest.to_string_lossy()
+                    device.to_string_lossy()
                 ))
                 .with_default(true)
                 .with_help_message(
@@ -83,11 +478,11 @@ pub async fn try_start_burn(
                 .prompt()?;
 
                 if response {
-                    return Ok(herder.start_herd(args.clone(), true).await?);
+                    return Ok((herder.start_herd(args.clone(), true).await?, true));
                 }
             }
             (UseSudo::Always, _) => {
-                return Ok(herder.start_herd(args.clone(), true).await?);
+                return Ok((herder.start_herd(args.clone(), true).await?, true));
             }
             _ => {}
         }
@@ -96,53 +491,252 @@ pub async fn try_start_burn(
     Err(err.into())
 }
 
+/// Show the progress of the herds until they finish.
+///
+/// If any of them failed, or the user quit before they finished, the error returned
+/// says what to exit with. The user has already been told why.
 pub async fn begin_writing(
     interactive: Interactive,
-    params: BeginParams,
-    handle: HerdHandle<WriteVerifyEvent>,
+    json_progress: Option<Box<dyn Write + Send>>,
+    params: DisplayParams,
+    handles: Vec<HerdHandle<WriteVerifyEvent>>,
     log_paths: Arc<LogPaths>,
 ) -> anyhow::Result<()> {
-    debug!("Opening TUI");
-    if interactive.is_interactive() {
+    let states = if let Some(out) = json_progress {
+        debug!("Writing JSON progress");
+        run_json_progress(&params, handles, out).await?
+    } else if interactive.is_interactive() {
         debug!("Using fancy interactive TUI");
         let mut tui = TUICapture::new()?;
         let terminal = tui.terminal();
 
         // create app and run it
-        FancyUI::new(&params, handle, terminal, log_paths)
+        let states = FancyUI::new(&params, handles, terminal, log_paths)
             .show()
             .await?;
-        debug!("Closing TUI");
+        drop(tui);
+        debug!("Closed TUI");
+
+        // The TUI is gone now, so say what went wrong where it can still be seen
+        for (target, state) in params.targets.iter().zip(&states) {
+            match state.error() {
+                Some(e) => eprintln!("{}: {e}", target.name),
+                None if !state.is_finished() => {
+                    eprintln!("{}: Cancelled before finishing", target.name)
+                }
+                None => {}
+            }
+        }
+        states
     } else {
         debug!("Using simple TUI");
-        run_simple_burning_ui(handle, params.compression).await?;
-    }
+        run_simple_burning_ui(&params, handles).await?
+    };
 
+    if let Some(error) = states.iter().find_map(WriterState::error) {
+        return Err(AlreadyReported(ExitCode::of_write_error(error)).into());
+    }
+    if !states.iter().all(WriterState::is_finished) {
+        return Err(AlreadyReported(ExitCode::Aborted).into());
+    }
     Ok(())
 }
 
 impl Display for BeginParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Input: {}", self.input_file.to_string_lossy())?;
-        if self.compression.is_identity() {
-            writeln!(f, "  Size: {}", self.input_file_size)?;
-        } else {
-            writeln!(f, "  Size (compressed): {}", self.input_file_size)?;
+        writeln!(f, "Input: {}", self.input_name())?;
+        match self.input_file_size {
+            None => writeln!(f, "  Size: unknown")?,
+            Some(s) if self.compression.is_identity() && self.archive_member.is_none() => {
+                writeln!(f, "  Size: {s}")?
+            }
+            Some(s) => writeln!(f, "  Size (compressed): {s}")?,
         }
         writeln!(f, "  Compression: {}", self.compression)?;
+        if !self.compression.is_identity() {
+            match self.decompressed_size {
+                Some(s) => writeln!(f, "  Size (decompressed): {s}")?,
+                None => writeln!(f, "  Size (decompressed): unknown")?,
+            }
+        }
+        if let Some(m) = &self.archive_member {
+            writeln!(f, "  Archive member: {m}")?;
+        }
+        if let Some(h) = &self.android_sparse {
+            writeln!(
+                f,
+                "  Android sparse image, expands to {}",
+                ByteSize::b(h.expanded_size())
+            )?;
+        }
+        if let Some(h) = &self.input_hash {
+            writeln!(f, "  Hash: {} (checked while writing)", h.expected.alg)?;
+        }
+        match &self.verify_hash {
+            Some(h) => writeln!(f, "  Verify: against {} hash", h.alg)?,
+            None if self.piped.is_some() => {
+                writeln!(f, "  Verify: against a hash of what was written")?
+            }
+            None => writeln!(f, "  Verify: against input file")?,
+        }
+        if self.repair_attempts > 0 {
+            writeln!(
+                f,
+                "  Repair: rewrite regions that don't match, up to {} times",
+                self.repair_attempts
+            )?;
+        }
+        match self.sparse {
+            Some(SparseMode::ZeroOut) => writeln!(f, "  Blocks of zeroes: zeroed out")?,
+            Some(SparseMode::Discard) => writeln!(f, "  Blocks of zeroes: discarded")?,
+            None => {}
+        }
         writeln!(f)?;
 
-        writeln!(f, "Output: {}", self.target.name)?;
-        writeln!(f, "  Model: {}", self.target.model)?;
-        writeln!(f, "  Size: {}", self.target.size)?;
-        writeln!(f, "  Block size: {}", self.target.block_size)?;
-        writeln!(f, "  Type: {}", self.target.target_type)?;
-        writeln!(f, "  Path: {}", self.target.devnode.to_string_lossy())?;
+        for (i, target) in self.targets.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "Output: {}", target.name)?;
+            write_target_details(f, target)?;
+            if let Some(Some(cp)) = self.resume_from.get(i) {
+                writeln!(f, "  Resuming from: {}", ByteSize::b(cp.dest_offset))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for CaptureParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Input: {}", self.source.name)?;
+        write_target_details(f, &self.source)?;
+        writeln!(f)?;
 
-        if self.target.target_type == device::Type::Disk {
-            writeln!(f, "  Removable: {}", self.target.removable)?;
+        writeln!(f, "Output: {}", self.image_file.to_string_lossy())?;
+        writeln!(f, "  Compression: {}", self.compression)?;
+        if !self.compression.is_identity() {
+            writeln!(
+                f,
+                "  Compression level: {}",
+                self.compression.numeric_level(self.compression_level)
+            )?;
         }
+        writeln!(f, "  Verify: {}", if self.verify { "yes" } else { "no" })?;
 
         Ok(())
     }
 }
+
+impl Display for WipeParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Mode: {}", self.mode)?;
+        writeln!(f, "Verify: {}", if self.verify { "yes" } else { "no" })?;
+
+        for target in &self.targets {
+            writeln!(f)?;
+            writeln!(f, "Disk: {}", target.name)?;
+            write_target_details(f, target)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for VerifyParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.against {
+            VerifyAgainst::Image {
+                src,
+                compression,
+                archive_member,
+                android_sparse,
+            } => {
+                writeln!(f, "Image: {}", src.to_string_lossy())?;
+                writeln!(f, "  Compression: {compression}")?;
+                if let Some(m) = archive_member {
+                    writeln!(f, "  Archive member: {m}")?;
+                }
+                if *android_sparse {
+                    writeln!(f, "  Android sparse image")?;
+                }
+            }
+            VerifyAgainst::Hash { expected, len } => {
+                writeln!(
+                    f,
+                    "Hash: {} {}",
+                    expected.alg,
+                    base16::encode_lower(&expected.hash)
+                )?;
+                match len {
+                    Some(len) => writeln!(f, "  Of: the first {}", ByteSize::b(*len))?,
+                    None => writeln!(f, "  Of: the whole disk")?,
+                }
+            }
+        }
+
+        for target in &self.targets {
+            writeln!(f)?;
+            writeln!(f, "Disk: {}", target.name)?;
+            write_target_details(f, target)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Describe a disk in the confirmation screen, so the user can be sure it's the right one.
+fn write_target_details(f: &mut std::fmt::Formatter<'_>, target: &WriteTarget) -> std::fmt::Result {
+    if let Some(v) = &target.vendor {
+        writeln!(f, "  Vendor: {v}")?;
+    }
+    writeln!(f, "  Model: {}", target.model)?;
+    if let Some(s) = &target.serial {
+        writeln!(f, "  Serial: {s}")?;
+    }
+    writeln!(f, "  Size: {}", target.size)?;
+    match (target.block_size.0, target.logical_block_size.0) {
+        (Some(p), Some(l)) if p != l => writeln!(f, "  Block size: {p} (logical: {l})")?,
+        _ => writeln!(f, "  Block size: {}", target.block_size)?,
+    }
+    writeln!(f, "  Type: {}", target.target_type)?;
+    if let Some(t) = target.transport {
+        writeln!(f, "  Transport: {t}")?;
+    }
+    writeln!(f, "  Path: {}", target.devnode.to_string_lossy())?;
+
+    if target.target_type == device::Type::Disk {
+        writeln!(f, "  Removable: {}", target.removable)?;
+    }
+    if let Some(r) = target.rotational {
+        writeln!(f, "  Rotational: {}", if r { "yes" } else { "no" })?;
+    }
+    if target.read_only == Some(true) {
+        writeln!(f, "  Read-only: yes")?;
+    }
+    if !target.system_uses.is_empty() {
+        writeln!(
+            f,
+            "  In use by the system: {}",
+            target.system_uses.iter().format(", ")
+        )?;
+    }
+    for m in &target.mountpoints {
+        writeln!(f, "  Mounted at: {}", m.display())?;
+    }
+    if !target.partitions.is_empty() {
+        writeln!(f, "  Partitions:")?;
+    }
+    for p in &target.partitions {
+        write!(f, "    {} - {}", p.name, p.size)?;
+        if let Some(l) = &p.label {
+            write!(f, " \"{l}\"")?;
+        }
+        for m in &p.mountpoints {
+            write!(f, ", mounted at {}", m.display())?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
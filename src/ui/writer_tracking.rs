@@ -1,12 +1,30 @@
 use std::time::Instant;
 
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
 use tracing::{info, trace};
 
 use crate::{
     byteseries::{ByteSeries, EstimatedTime},
-    herder_daemon::ipc::{WriteVerifyError, WriteVerifyEvent},
+    herder_daemon::ipc::{HerdEvent, WriteVerifyError, WriteVerifyEvent},
 };
 
+/// Merge the event streams of several herds into a single stream. Each event is tagged
+/// with the index of the herd it came from, and when a herd's stream ends, a final
+/// `None` is emitted for that index.
+pub fn merge_herd_events<E: HerdEvent>(
+    streams: impl IntoIterator<Item = BoxStream<'static, E>>,
+) -> BoxStream<'static, (usize, Option<E>)> {
+    stream::select_all(streams.into_iter().enumerate().map(|(i, s)| {
+        s.map(move |e| (i, Some(e)))
+            .chain(stream::once(async move { (i, None) }))
+            .boxed()
+    }))
+    .boxed()
+}
+
 /// A state machine for tracking the state of the writer, based on received
 /// messages.
 #[derive(Debug, Clone, PartialEq)]
@@ -16,6 +34,8 @@ pub enum WriterState {
         write_hist: ByteSeries,
         verify_hist: ByteSeries,
         total_write_bytes: u64,
+        /// If regions that didn't match are being rewritten, how that's going
+        repair: Option<Repair>,
     },
     Finished {
         finish_time: Instant,
@@ -23,15 +43,35 @@ pub enum WriterState {
         write_hist: ByteSeries,
         verify_hist: Option<ByteSeries>,
         total_write_bytes: u64,
+        repair: Option<Repair>,
     },
 }
 
+/// How rewriting the regions of a disk that didn't match after burning is going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repair {
+    /// Which attempt this is, starting from 1
+    pub attempt: u32,
+    /// How many bytes are being rewritten in this attempt
+    pub bad_bytes: u64,
+    /// How many bytes were rewritten in the first attempt, which is how many didn't
+    /// match after burning
+    pub first_bad_bytes: u64,
+}
+
 impl WriterState {
     #[tracing::instrument]
-    pub fn initial(now: Instant, is_input_compressed: bool, input_file_bytes: u64) -> Self {
+    pub fn initial(now: Instant, is_input_compressed: bool, input_file_bytes: Option<u64>) -> Self {
         WriterState::Writing(Writing::new(now, is_input_compressed, input_file_bytes))
     }
 
+    /// Like [Self::on_status], but updates the state in place.
+    pub fn update(&mut self, now: Instant, msg: Option<WriteVerifyEvent>) {
+        // Anything will do to stand in while the old state is moved out
+        let old = std::mem::replace(self, WriterState::initial(now, false, None));
+        *self = old.on_status(now, msg);
+    }
+
     #[tracing::instrument(skip_all, fields(msg), level = "debug")]
     pub fn on_status(mut self, now: Instant, msg: Option<WriteVerifyEvent>) -> Self {
         match msg {
@@ -40,6 +80,16 @@ impl WriterState {
                 self.on_total_bytes(now, src, dest);
                 self
             }
+            Some(WriteVerifyEvent::InputHashed { .. }) => {
+                // If the hash did not match, we will shortly receive an error, so
+                // there is nothing to do here.
+                info!("Received input hash notification");
+                self
+            }
+            Some(WriteVerifyEvent::Checkpoint(_)) => {
+                // These are saved by whoever started the herd
+                self
+            }
             Some(WriteVerifyEvent::FinishedWriting { verifying }) => {
                 info!("Received finished writing notification");
                 match self {
@@ -47,6 +97,18 @@ impl WriterState {
                     c => c,
                 }
             }
+            Some(WriteVerifyEvent::Repairing { attempt, bad_bytes }) => {
+                // Repairing is part of verifying, so that's where we stay
+                info!(attempt, bad_bytes, "Received repairing notification");
+                if let WriterState::Verifying { repair, .. } = &mut self {
+                    *repair = Some(Repair {
+                        attempt,
+                        bad_bytes,
+                        first_bad_bytes: repair.map_or(bad_bytes, |r| r.first_bad_bytes),
+                    });
+                }
+                self
+            }
             Some(WriteVerifyEvent::Error(reason)) => {
                 info!("Received error notification");
                 self.into_finished(now, Some(reason))
@@ -82,6 +144,14 @@ impl WriterState {
         }
     }
 
+    /// How repairing went, if it was needed.
+    pub fn repair(&self) -> Option<Repair> {
+        match self {
+            Self::Writing(_) => None,
+            Self::Verifying { repair, .. } | Self::Finished { repair, .. } => *repair,
+        }
+    }
+
     fn on_total_bytes(&mut self, now: Instant, src: u64, dest: u64) {
         match self {
             WriterState::Writing(st) => {
@@ -103,22 +173,22 @@ impl WriterState {
                     write_hist: st.write_hist,
                     verify_hist: None,
                     total_write_bytes,
+                    repair: None,
                 }
             }
             WriterState::Verifying {
                 write_hist,
                 verify_hist,
-                ..
-            } => {
-                let total_write_bytes = write_hist.bytes_encountered();
-                WriterState::Finished {
-                    finish_time: now,
-                    error,
-                    write_hist,
-                    verify_hist: Some(verify_hist),
-                    total_write_bytes,
-                }
-            }
+                total_write_bytes,
+                repair,
+            } => WriterState::Finished {
+                finish_time: now,
+                error,
+                write_hist,
+                verify_hist: Some(verify_hist),
+                total_write_bytes,
+                repair,
+            },
             fin => fin,
         }
     }
@@ -126,6 +196,14 @@ impl WriterState {
     pub fn is_finished(&self) -> bool {
         matches!(self, WriterState::Finished { .. })
     }
+
+    /// The error this writer finished with, if any.
+    pub fn error(&self) -> Option<&WriteVerifyError> {
+        match self {
+            WriterState::Finished { error, .. } => error.as_ref(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -133,34 +211,42 @@ pub struct Writing {
     pub write_hist: ByteSeries,
     pub total_raw_bytes: Option<u64>,
     pub read_hist: ByteSeries,
-    pub input_file_bytes: u64,
+    /// None if the input is a pipe, so we don't know how big it is.
+    pub input_file_bytes: Option<u64>,
 }
 
 impl Writing {
-    pub fn new(start: Instant, is_input_compressed: bool, input_file_bytes: u64) -> Self {
+    pub fn new(start: Instant, is_input_compressed: bool, input_file_bytes: Option<u64>) -> Self {
         Self {
             write_hist: ByteSeries::new(start),
             total_raw_bytes: if is_input_compressed {
                 None
             } else {
-                Some(input_file_bytes)
+                input_file_bytes
             },
             read_hist: ByteSeries::new(start),
             input_file_bytes,
         }
     }
 
-    pub fn approximate_ratio(&self) -> f64 {
-        match self.total_raw_bytes {
-            Some(total_bytes) => self.write_hist.bytes_encountered() as f64 / total_bytes as f64,
-            None => self.read_hist.bytes_encountered() as f64 / self.input_file_bytes as f64,
+    /// How far along writing is, or None if we can't tell.
+    pub fn approximate_ratio(&self) -> Option<f64> {
+        match (self.total_raw_bytes, self.input_file_bytes) {
+            (Some(total_bytes), _) => {
+                Some(self.write_hist.bytes_encountered() as f64 / total_bytes as f64)
+            }
+            (None, Some(input_bytes)) => {
+                Some(self.read_hist.bytes_encountered() as f64 / input_bytes as f64)
+            }
+            (None, None) => None,
         }
     }
 
     pub fn eta_write(&self) -> EstimatedTime {
-        match self.total_raw_bytes {
-            Some(total_bytes) => self.write_hist.estimated_time_left(total_bytes),
-            None => self.read_hist.estimated_time_left(self.input_file_bytes),
+        match (self.total_raw_bytes, self.input_file_bytes) {
+            (Some(total_bytes), _) => self.write_hist.estimated_time_left(total_bytes),
+            (None, Some(input_bytes)) => self.read_hist.estimated_time_left(input_bytes),
+            (None, None) => EstimatedTime::Unknown,
         }
     }
 
@@ -170,10 +256,18 @@ impl Writing {
         if verifying {
             info!(verifying, "Transition to verifying");
 
+            // Jobs that only verify don't write anything, so they can only go off
+            // of how big the input is
+            let total_write_bytes = match total_write_bytes {
+                0 => self.total_raw_bytes.or(self.input_file_bytes).unwrap_or(0),
+                n => n,
+            };
+
             WriterState::Verifying {
                 write_hist: self.write_hist,
                 verify_hist: ByteSeries::new(time),
                 total_write_bytes,
+                repair: None,
             }
         } else {
             info!(verifying, "Transition to finished");
@@ -183,6 +277,7 @@ impl Writing {
                 write_hist: self.write_hist,
                 verify_hist: None,
                 total_write_bytes,
+                repair: None,
             }
         }
     }
@@ -192,17 +287,48 @@ impl Writing {
 mod tests {
     use std::time::{Duration, Instant};
 
+    use futures::{StreamExt, stream};
+
     use crate::{
         byteseries::ByteSeries,
         herder_daemon::ipc::{WriteVerifyError, WriteVerifyEvent},
     };
 
-    use super::WriterState;
+    use super::{Repair, WriterState, merge_herd_events};
+
+    #[tokio::test]
+    async fn merged_herd_events_are_tagged_and_terminated() {
+        let a = stream::iter([WriteVerifyEvent::Success]).boxed();
+        let b = stream::iter([
+            WriteVerifyEvent::TotalBytes { src: 1, dest: 2 },
+            WriteVerifyEvent::Error(WriteVerifyError::EndOfOutput),
+        ])
+        .boxed();
+
+        let events: Vec<_> = merge_herd_events([a, b]).collect().await;
+
+        let of = |i: usize| -> Vec<_> {
+            events
+                .iter()
+                .filter(|(j, _)| *j == i)
+                .map(|(_, e)| e.clone())
+                .collect()
+        };
+        assert_eq!(of(0), vec![Some(WriteVerifyEvent::Success), None]);
+        assert_eq!(
+            of(1),
+            vec![
+                Some(WriteVerifyEvent::TotalBytes { src: 1, dest: 2 }),
+                Some(WriteVerifyEvent::Error(WriteVerifyError::EndOfOutput)),
+                None
+            ]
+        );
+    }
 
     #[test]
     fn accept_total_bytes_messages() {
         let t0 = Instant::now();
-        let s = WriterState::initial(t0, false, 80)
+        let s = WriterState::initial(t0, false, Some(80))
             .on_status(
                 t0 + Duration::from_secs(1),
                 Some(WriteVerifyEvent::TotalBytes { src: 20, dest: 10 }),
@@ -227,7 +353,7 @@ mod tests {
     #[test]
     fn writing_value_for_uncompressed_ratio() {
         let t0 = Instant::now();
-        let s = WriterState::initial(t0, false, 400).on_status(
+        let s = WriterState::initial(t0, false, Some(400)).on_status(
             t0 + Duration::from_secs(1),
             Some(WriteVerifyEvent::TotalBytes { src: 15, dest: 40 }),
         );
@@ -236,13 +362,13 @@ mod tests {
             WriterState::Writing(s) => s,
             s => panic!("unexpected {:#?}", s),
         };
-        assert_eq!(s.approximate_ratio(), 0.1);
+        assert_eq!(s.approximate_ratio(), Some(0.1));
     }
 
     #[test]
     fn writing_value_for_compressed_ratio() {
         let t0 = Instant::now();
-        let s = WriterState::initial(t0, true, 80).on_status(
+        let s = WriterState::initial(t0, true, Some(80)).on_status(
             t0 + Duration::from_secs(1),
             Some(WriteVerifyEvent::TotalBytes {
                 src: 20,
@@ -254,13 +380,66 @@ mod tests {
             WriterState::Writing(s) => s,
             s => panic!("unexpected {s:#?}"),
         };
-        assert_eq!(s.approximate_ratio(), 0.25);
+        assert_eq!(s.approximate_ratio(), Some(0.25));
+    }
+
+    #[test]
+    fn writing_value_for_unknown_size_input() {
+        let t0 = Instant::now();
+        let s = WriterState::initial(t0, false, None).on_status(
+            t0 + Duration::from_secs(1),
+            Some(WriteVerifyEvent::TotalBytes { src: 20, dest: 20 }),
+        );
+
+        let s = match s {
+            WriterState::Writing(s) => s,
+            s => panic!("unexpected {s:#?}"),
+        };
+        assert_eq!(s.approximate_ratio(), None);
+        assert_eq!(s.total_raw_bytes, None);
+    }
+
+    #[test]
+    fn repairs_are_kept_track_of_until_finished() {
+        let t0 = Instant::now();
+        let s = WriterState::initial(t0, false, Some(80))
+            .on_status(
+                t0,
+                Some(WriteVerifyEvent::FinishedWriting { verifying: true }),
+            )
+            .on_status(
+                t0,
+                Some(WriteVerifyEvent::Repairing {
+                    attempt: 1,
+                    bad_bytes: 1024,
+                }),
+            )
+            .on_status(
+                t0,
+                Some(WriteVerifyEvent::Repairing {
+                    attempt: 2,
+                    bad_bytes: 512,
+                }),
+            );
+
+        let expected = Repair {
+            attempt: 2,
+            bad_bytes: 512,
+            first_bad_bytes: 1024,
+        };
+        assert!(matches!(s, WriterState::Verifying { .. }));
+        assert_eq!(s.repair(), Some(expected));
+
+        let s = s.on_status(t0, Some(WriteVerifyEvent::Success));
+        assert!(s.is_finished());
+        assert_eq!(s.error(), None);
+        assert_eq!(s.repair(), Some(expected));
     }
 
     #[test]
     fn sudden_terminate_in_writing_state_sets_error() {
         let t0 = Instant::now();
-        let s = WriterState::initial(t0, true, 80)
+        let s = WriterState::initial(t0, true, Some(80))
             .on_status(
                 t0 + Duration::from_secs(1),
                 Some(WriteVerifyEvent::TotalBytes { src: 20, dest: 20 }),
@@ -288,6 +467,7 @@ mod tests {
             write_hist: ByteSeries::new(t0),
             verify_hist: None,
             total_write_bytes: 12345678,
+            repair: None,
         };
         let s1 = s0
             .clone()
@@ -306,6 +486,7 @@ mod tests {
             write_hist: ByteSeries::new(t0),
             verify_hist: None,
             total_write_bytes: 12345678,
+            repair: None,
         };
         let s1 = s0.clone().on_status(
             finish_time + Duration::from_secs(2),
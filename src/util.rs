@@ -21,3 +21,13 @@ pub async fn ensure_state_dir() -> Result<PathBuf, futures_io::Error> {
 
     Ok(dir)
 }
+
+/// The directory to keep data in that should outlive a single invocation, like
+/// checkpoints of interrupted burns.
+pub fn persistent_state_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("caligula"))
+}